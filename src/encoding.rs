@@ -0,0 +1,70 @@
+use std::borrow::Cow;
+
+/// Character encoding of the text exchanged with the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1, common on older MUDs.
+    Latin1,
+    /// The original IBM PC code page, for MUDs drawing with box characters.
+    Cp437,
+}
+
+impl Encoding {
+    /// Encoding by one of its usual names, in any case (e.g. `utf-8`, `latin1`, `ISO-8859-1`, `cp437`).
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Some(Encoding::Latin1),
+            "cp437" | "ibm437" | "437" => Some(Encoding::Cp437),
+            _ => None,
+        }
+    }
+
+    /// Decode received text; invalid UTF-8 is replaced with `\u{FFFD}`.
+    pub fn decode(&self, data: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            Encoding::Latin1 => data.iter().map(|&byte| byte as char).collect(),
+            Encoding::Cp437 => data.iter().map(|&byte| cp437_char(byte)).collect(),
+        }
+    }
+
+    /// Encode text to be sent; characters the encoding lacks become `?`.
+    pub fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        match self {
+            Encoding::Utf8 => Cow::Borrowed(text.as_bytes()),
+            Encoding::Latin1 => Cow::Owned(text.chars()
+                .map(|ch| u8::try_from(ch).unwrap_or(b'?'))
+                .collect()),
+            Encoding::Cp437 => Cow::Owned(text.chars()
+                .map(|ch| match ch {
+                    '\0'..='\x7f' => ch as u8,
+                    _ => CP437_HIGH.chars()
+                        .position(|high| high == ch)
+                        .map(|index| 0x80 + index as u8)
+                        .unwrap_or(b'?'),
+                })
+                .collect()),
+        }
+    }
+}
+
+/// Characters of CP437 bytes 0x80-0xFF (the lower half is ASCII, with control characters kept as such).
+const CP437_HIGH: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅ",
+    "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧",
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩",
+    "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}",
+);
+
+fn cp437_char(byte: u8) -> char {
+    match byte {
+        0..=0x7f => byte as char,
+        _ => CP437_HIGH.chars().nth((byte - 0x80) as usize).unwrap_or(char::REPLACEMENT_CHARACTER),
+    }
+}
@@ -0,0 +1,38 @@
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+
+use anyhow::{Context, Result};
+
+use crate::pipeline::strip_ansi;
+
+/// Transcript of the session's server output, appended to a file as it is received.
+pub struct SessionLog {
+    path: String,
+    /// Flushes after every complete line, so that the transcript is up to date should Draugr crash.
+    file: LineWriter<File>,
+    keep_ansi: bool,
+}
+
+impl SessionLog {
+    /// Open `path` for appending (creating it if needed); unless `keep_ansi`, escape sequences are left out.
+    pub fn open(path: &str, keep_ansi: bool) -> Result<SessionLog> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("Open log file {path}"))?;
+
+        Ok(SessionLog { path: path.to_string(), file: LineWriter::new(file), keep_ansi })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn write(&mut self, data: &str) -> Result<()> {
+        let data = if self.keep_ansi { data.to_string() } else { strip_ansi(data) };
+
+        self.file.write_all(data.as_bytes())
+            .context(format!("Write to log file {}", self.path))
+    }
+}
@@ -1,17 +1,39 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use anyhow::{Result, Context};
-use clap::{Parser, arg};
-use tokio::sync::mpsc::Sender;
+use clap::Parser;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tokio::sync::oneshot;
 
+use crate::log::SessionLog;
+use crate::offline::*;
 use crate::script::*;
 use crate::telnet::*;
 use crate::tui::*;
 
+mod encoding;
+mod log;
+mod msdp;
+mod mxp;
+mod offline;
+mod pipeline;
 mod ring;
+mod rules;
 mod script;
+mod sound;
 mod telnet;
+mod tick;
+mod tls;
 mod tui;
 
+/// Version including build information, for bug reports.
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (", env!("DRAUGR_GIT_COMMIT"), ", built ", env!("DRAUGR_BUILD_DATE"), ")");
+
 #[derive(Parser, Debug)]
+#[command(version = VERSION)]
 struct Args {
     #[arg(short, long)]
     address: Option<String>,
@@ -19,36 +41,103 @@ struct Args {
     #[arg(short, long, default_value_t = 4000)]
     port: u16,
 
+    /// Connect over TLS (the server's certificate must be valid)
+    #[arg(long)]
+    tls: bool,
+
+    /// Script to run at startup; can be given more than once to run several, one after another
     #[arg(short, long)]
-    script: Option<String>,
+    script: Vec<String>,
+
+    /// Argument for scripts as key=value, available to them as `arg("key")`; can be given more than once
+    #[arg(long = "arg", value_parser = parse_script_arg)]
+    script_args: Vec<(String, String)>,
+
+    /// Config script to run before anything else [default: ~/.config/draugr/config.rhai]
+    #[arg(short, long)]
+    config: Option<String>,
+
+    /// Do not run any config script, nor load saved rules
+    #[arg(long)]
+    no_config: bool,
+
+    /// Do not connect to any server; sent data is discarded
+    #[arg(long)]
+    offline: bool,
+
+    /// Work offline, replaying lines from a file as server output once connected (to any address)
+    #[arg(long)]
+    replay: Option<String>,
+
+    /// Delay between replayed lines, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    replay_delay: u64,
+
+    /// Number of input history entries to keep
+    #[arg(long, default_value_t = 1000)]
+    history_size: usize,
+
+    /// What to do when input that is already in the history is submitted again
+    #[arg(long, value_enum, default_value_t = HistoryDedup::MoveToBack)]
+    history_dedup: HistoryDedup,
+
+    /// Colors the terminal can display [default: guessed from COLORTERM and TERM]
+    #[arg(long, value_enum)]
+    color_support: Option<ColorSupport>,
+
+    /// Whether the terminal reports mouse clicks (needed for click-send mode) [default: guessed from TERM]
+    #[arg(long)]
+    mouse: Option<bool>,
+
+    /// Log telnet protocol events (negotiations, subnegotiations and commands) to a file
+    #[arg(long)]
+    trace_telnet: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    let (tui_tx, mut tui_rx) = create_tui().await
-        .context("Create TUI")?;
-
-    let (telnet_tx, mut telnet_rx) = telnet_connection()
-        .context("Create connection")?;
+    let history_config = HistoryConfig { size: args.history_size, dedup: args.history_dedup };
 
-    if let Some(address) = args.address {
-        telnet_tx.send(TelnetRequest::Connect(address, args.port)).await
-            .context("Connect from command line")?;
+    let mut capabilities = Capabilities::detect();
+    if let Some(color) = args.color_support {
+        capabilities.color = color;
+    }
+    if let Some(mouse) = args.mouse {
+        capabilities.mouse = mouse;
     }
 
-    let (script_tx, mut script_rx) = create_script_engine()
+    let (tui_tx, mut tui_rx) = create_tui(history_config, capabilities).await
+        .context("Create TUI")?;
+
+    let (telnet_tx, mut telnet_rx) = if args.offline || args.replay.is_some() {
+        offline_connection(args.replay.clone(), Duration::from_millis(args.replay_delay))
+            .context("Create offline connection")?
+    } else {
+        telnet_connection(args.trace_telnet.clone())
+            .context("Create connection")?
+    };
+
+    let (script_tx, mut script_rx) = create_script_engine(args.script_args.clone())
         .context("Create script engine")?;
 
-    if let Some(script) = args.script {
-        script_tx.send(ScriptEngineRequest::ExecuteScriptFile(script)).await
-            .context("Execute startup script")?;
+    if let Some(rules) = rules_path(&args) {
+        script_tx.send(ScriptEngineRequest::LoadDefaultRules(rules))
+            .context("Load saved rules")?;
     }
 
-    let app = App { telnet_tx, tui_tx, script_tx };
+    let mut app = App {
+        telnet_tx: telnet_tx.clone(),
+        tui_tx,
+        script_tx: script_tx.clone(),
+        log: None,
+        connection: ConnectionState::Disconnected,
+        latency: None,
+    };
 
-    tokio::spawn(async move {
+    /* The loop runs while the config does, since it is what handles the events the config emits */
+    let mut app_loop = tokio::spawn(async move {
         loop {
             tokio::select! {
                 Some(event) = telnet_rx.recv() =>
@@ -67,25 +156,126 @@ async fn main() -> Result<()> {
         }
 
         anyhow::Ok(())
-    }).await??;
+    });
+
+    /* Precedence: config < command line < runtime script.
+     * The config is run to completion first, so that command line options can override what it sets.
+     */
+    let startup = async {
+        if let Some(config) = config_path(&args) {
+            let (done_tx, done_rx) = oneshot::channel();
+
+            script_tx.send(ScriptEngineRequest::ExecuteConfigFile(config, done_tx))
+                .context("Execute config script")?;
+
+            /* Errors are reported by the script engine; dropping the sender also ends the wait */
+            let _ = done_rx.await;
+        }
+
+        if let Some(address) = args.address {
+            let request = if args.tls {
+                TelnetRequest::ConnectTls(address, args.port)
+            } else {
+                TelnetRequest::Connect(address, args.port)
+            };
+
+            telnet_tx.send(request).await
+                .context("Connect from command line")?;
+        }
+
+        if !args.script.is_empty() {
+            script_tx.send(ScriptEngineRequest::ExecuteScriptFiles(args.script))
+                .context("Execute startup scripts")?;
+        }
+
+        anyhow::Ok(())
+    };
+
+    /* Quitting while the config is still running must not wait for it to finish */
+    tokio::select! {
+        result = startup => result?,
+        result = &mut app_loop => return result?,
+    }
+
+    app_loop.await??;
 
     Ok(())
 }
 
+fn parse_script_arg(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or(format!("Expected key=value, got \"{arg}\""))
+}
+
+/// Path of the config script to run at startup, if any.
+///
+/// An explicitly given `--config` is always returned (so that a missing file is reported),
+/// while the default location is only used if the file exists.
+fn config_path(args: &Args) -> Option<String> {
+    if args.no_config {
+        return None;
+    }
+
+    if let Some(config) = &args.config {
+        return Some(config.clone());
+    }
+
+    let config = config_dir()?.join("config.rhai");
+
+    if config.is_file() {
+        config.to_str().map(String::from)
+    } else {
+        None
+    }
+}
+
+/// Id of the session created at startup; until multiple sessions are supported, it is the only one.
+const DEFAULT_SESSION: usize = 1;
+
+/// Path of the file declarative rules are saved to and loaded from at startup.
+fn rules_path(args: &Args) -> Option<String> {
+    if args.no_config {
+        return None;
+    }
+
+    config_dir()?.join("rules.txt").to_str().map(String::from)
+}
+
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|config| config.join("draugr"))
+}
+
 struct App {
     telnet_tx: Sender<TelnetRequest>,
     tui_tx: Sender<TuiRequest>,
-    script_tx: Sender<ScriptEngineRequest>,
+    script_tx: UnboundedSender<ScriptEngineRequest>,
+
+    /// Transcript of server output, if one is being written.
+    log: Option<SessionLog>,
+
+    /// Shown in the status bar, as last reported by the connection.
+    connection: ConnectionState,
+    latency: Option<Duration>,
 }
 
 impl App {
-    async fn handle_telnet_event(&self, event: TelnetEvent) -> Result<()> {
+    async fn handle_telnet_event(&mut self, event: TelnetEvent) -> Result<()> {
         match event {
+            /* Output is displayed once it has gone through the script engine's output pipeline */
             TelnetEvent::Data(data) => {
-                self.tui_tx.send(TuiRequest::Print(data.clone(), 1)).await
-                    .context("Send output to TUI")?;
+                if let Some(Err(err)) = self.log.as_mut().map(|log| log.write(&data)) {
+                    /* Stop, rather than failing again on every bit of output */
+                    self.log = None;
 
-                self.script_tx.send(ScriptEngineRequest::Output(data)).await
+                    self.tui_tx.send(TuiRequest::PrintWarning(format!("{:?}", err.context("Logging stopped")), 1)).await
+                        .context("Warn about log failure")?;
+                }
+
+                self.script_tx.send(ScriptEngineRequest::Output(data))
                     .context("Send output to script engine")?;
             },
             TelnetEvent::Unhandled(event) => {
@@ -104,6 +294,49 @@ impl App {
                 self.tui_tx.send(TuiRequest::PrintError(format!("{:?}", err.context("Connection error")), 1)).await
                     .context("Send ERR to TUI")?;
             },
+            TelnetEvent::Gmcp(package, data) => {
+                self.script_tx.send(ScriptEngineRequest::Gmcp(package, data))
+                    .context("Send GMCP message to script engine")?;
+            },
+            TelnetEvent::Mssp(status) => {
+                self.script_tx.send(ScriptEngineRequest::Mssp(status))
+                    .context("Send MSSP status to script engine")?;
+            },
+            TelnetEvent::Msdp(variable, value) => {
+                self.script_tx.send(ScriptEngineRequest::Msdp(variable, value))
+                    .context("Send MSDP variable to script engine")?;
+            },
+            TelnetEvent::ConnectionState(state) => {
+                self.connection = state;
+                self.latency = None;
+
+                self.update_status().await
+                    .context("Show connection state")?;
+            },
+            TelnetEvent::Latency(latency) => {
+                self.latency = Some(latency);
+
+                self.update_status().await
+                    .context("Show latency")?;
+            },
+            TelnetEvent::EchoState(echo) => {
+                /* The server hides what is typed (e.g. a password), so mask it and do not echo it in the clear */
+                self.tui_tx.send(TuiRequest::SetServerEcho(echo)).await
+                    .context("Set secret input on server echo")?;
+            },
+            TelnetEvent::Prompt(prompt, complete) => {
+                self.tui_tx.send(TuiRequest::SetServerPrompt(prompt)).await
+                    .context("Show server prompt")?;
+
+                if complete {
+                    self.script_tx.send(ScriptEngineRequest::EndOfPrompt)
+                        .context("Send end of prompt to script engine")?;
+                }
+            },
+            TelnetEvent::QueueSize(queued) => {
+                self.tui_tx.send(TuiRequest::SetQueuedCommands(queued)).await
+                    .context("Show queued commands")?;
+            },
         }
 
         Ok(())
@@ -111,9 +344,21 @@ impl App {
 
     async fn handle_tui_event(&self, event: TuiEvent) -> Result<bool> {
         match event {
-            TuiEvent::Send(data) => {
-                self.telnet_tx.send(TelnetRequest::Send(data.clone())).await
-                    .context("Send data to Telnet")?;
+            TuiEvent::Send(data, target) => {
+                if let Some(command) = data.strip_prefix('/').filter(|command| !command.starts_with('/')) {
+                    self.handle_command(command).await
+                        .context("Handle client command")?;
+                } else if let Some(target) = target {
+                    /* Input from an input pane with a target is for the scripts handling it, e.g. to send it as chat */
+                    self.script_tx.send(ScriptEngineRequest::Input(target, data))
+                        .context("Send input to script engine")?;
+                } else {
+                    /* A doubled slash sends a line starting with a slash to the server */
+                    let data = data.strip_prefix('/').unwrap_or(&data).to_string();
+
+                    self.telnet_tx.send(TelnetRequest::Send(data)).await
+                        .context("Send data to Telnet")?;
+                }
             },
             TuiEvent::SendSecret(data) => {
                 self.telnet_tx.send(TelnetRequest::Send(data.clone())).await
@@ -122,11 +367,19 @@ impl App {
                 self.tui_tx.send(TuiRequest::PrintUserInput("*****".into(), 1)).await
                     .context("Echo user input (masked)")?;
             },
+            TuiEvent::WindowResize(columns, rows) => {
+                self.telnet_tx.send(TelnetRequest::WindowResize(columns, rows)).await
+                    .context("Send window size to Telnet")?;
+            },
+            TuiEvent::KeyPressed(key) => {
+                self.script_tx.send(ScriptEngineRequest::KeyPressed(key))
+                    .context("Send key press to script engine")?;
+            },
             TuiEvent::Quit => {
                 self.telnet_tx.send(TelnetRequest::Shutdown).await
                     .context("Send shutdown signal to Telnet")?;
 
-                self.script_tx.send(ScriptEngineRequest::Shutdown).await
+                self.script_tx.send(ScriptEngineRequest::Shutdown)
                     .context("Send shutdown signal to script engine")?;
 
                 return Ok(true);
@@ -136,12 +389,94 @@ impl App {
         Ok(false)
     }
 
-    async fn handle_script_event(&self, event: ScriptEngineEvent) -> Result<()> {
+    /// Show the connection state and latency in the status bar.
+    async fn update_status(&self) -> Result<()> {
+        let mut status = match &self.connection {
+            ConnectionState::Disconnected => "Not connected".to_string(),
+            ConnectionState::Connecting(server) => format!("Connecting to {server}..."),
+            ConnectionState::Connected(server) => format!("Connected to {server}"),
+        };
+
+        if let Some(latency) = self.latency {
+            status.push_str(&format!(" | latency {} ms", latency.as_millis()));
+        }
+
+        self.tui_tx.send(TuiRequest::SetStatus(status)).await
+            .context("Set status")
+    }
+
+    /// Handle a client command, i.e. user input starting with a slash.
+    async fn handle_command(&self, command: &str) -> Result<()> {
+        match command.trim() {
+            "version" => {
+                self.tui_tx.send(TuiRequest::PrintInfo(format!("Draugr {VERSION}"), 1)).await
+                    .context("Print version")?;
+            },
+            "dump_layout" => {
+                self.tui_tx.send(TuiRequest::DumpLayout).await
+                    .context("Dump layout")?;
+            },
+            "disconnect" => {
+                self.telnet_tx.send(TelnetRequest::Disconnect).await
+                    .context("Send disconnect request to Telnet")?;
+            },
+            "queue on" | "queue off" => {
+                self.telnet_tx.send(TelnetRequest::SetQueueWhileDisconnected(command.trim() == "queue on")).await
+                    .context("Set queue while disconnected")?;
+            },
+            "guards" => {
+                self.tui_tx.send(TuiRequest::ListGuards).await
+                    .context("List guards")?;
+            },
+            "urls" => {
+                self.tui_tx.send(TuiRequest::ListUrls).await
+                    .context("List URLs")?;
+            },
+            url if url.starts_with("url ") => {
+                match url["url ".len()..].trim().parse() {
+                    Ok(number) => {
+                        self.tui_tx.send(TuiRequest::OpenUrl(number)).await
+                            .context("Open URL")?;
+                    },
+                    Err(_) => {
+                        self.tui_tx.send(TuiRequest::PrintError("Usage: /url <number> (see /urls)".into(), 1)).await
+                            .context("Report bad URL number")?;
+                    },
+                }
+            },
+            "queue clear" => {
+                self.telnet_tx.send(TelnetRequest::ClearQueue).await
+                    .context("Clear queued commands")?;
+            },
+            _ => {
+                self.tui_tx.send(TuiRequest::PrintError(format!("Unknown command: /{command}"), 1)).await
+                    .context("Report unknown command")?;
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn handle_script_event(&mut self, event: ScriptEngineEvent) -> Result<()> {
         match event {
             ScriptEngineEvent::Connect(address, port) => {
                 self.telnet_tx.send(TelnetRequest::Connect(address, port)).await
                     .context("Send connect request to Telnet")?;
             },
+            ScriptEngineEvent::ConnectTls(address, port) => {
+                self.telnet_tx.send(TelnetRequest::ConnectTls(address, port)).await
+                    .context("Send TLS connect request to Telnet")?;
+            },
+            ScriptEngineEvent::Disconnect => {
+                self.telnet_tx.send(TelnetRequest::Disconnect).await
+                    .context("Send disconnect request to Telnet")?;
+            },
+            ScriptEngineEvent::Print(lines) => {
+                if !lines.is_empty() {
+                    self.tui_tx.send(TuiRequest::PrintOutput(lines)).await
+                        .context("Send output to TUI")?;
+                }
+            },
             ScriptEngineEvent::Send(data) => {
                 self.telnet_tx.send(TelnetRequest::Send(data.clone())).await
                     .context("Send data to Telnet")?;
@@ -149,6 +484,35 @@ impl App {
                 self.tui_tx.send(TuiRequest::PrintUserInput(data, 1)).await
                     .context("Echo user input")?;
             },
+            ScriptEngineEvent::SendSilent(data) => {
+                self.telnet_tx.send(TelnetRequest::Send(data)).await
+                    .context("Send data to Telnet")?;
+            },
+            ScriptEngineEvent::SendNoNewline(data, echo) => {
+                self.telnet_tx.send(TelnetRequest::SendNoNewline(data.clone())).await
+                    .context("Send data to Telnet")?;
+
+                if echo {
+                    self.tui_tx.send(TuiRequest::PrintUserInput(format!("{data} (no newline)"), 1)).await
+                        .context("Echo user input")?;
+                }
+            },
+            ScriptEngineEvent::SendRaw(data) => {
+                self.telnet_tx.send(TelnetRequest::SendRaw(data)).await
+                    .context("Send raw data to Telnet")?;
+            },
+            ScriptEngineEvent::SendTo(session, data) => {
+                if let Some(telnet_tx) = self.session(session) {
+                    telnet_tx.send(TelnetRequest::Send(data.clone())).await
+                        .context("Send data to Telnet")?;
+
+                    self.tui_tx.send(TuiRequest::PrintUserInput(format!("[{session}] {data}"), 1)).await
+                        .context("Echo user input")?;
+                } else {
+                    self.tui_tx.send(TuiRequest::PrintError(format!("Script error: no such session: {session}"), 1)).await
+                        .context("Display send to session error")?;
+                }
+            },
             ScriptEngineEvent::SendSecret(data) => {
                 self.telnet_tx.send(TelnetRequest::Send(data.clone())).await
                     .context("Send data to Telnet")?;
@@ -160,6 +524,164 @@ impl App {
                 self.tui_tx.send(TuiRequest::SetLayout(layout)).await
                     .context("Set layout")?;
             },
+            ScriptEngineEvent::RegisterLayout(name, layout) => {
+                self.tui_tx.send(TuiRequest::RegisterLayout(name, layout)).await
+                    .context("Register layout")?;
+            },
+            ScriptEngineEvent::UseLayout(name) => {
+                self.tui_tx.send(TuiRequest::UseLayout(name)).await
+                    .context("Use layout")?;
+            },
+            ScriptEngineEvent::SetTheme(theme) => {
+                self.tui_tx.send(TuiRequest::SetTheme(theme)).await
+                    .context("Set theme")?;
+            },
+            ScriptEngineEvent::SetCollapseDuplicates(pane_id, enabled) => {
+                self.tui_tx.send(TuiRequest::SetCollapseDuplicates(pane_id, enabled)).await
+                    .context("Set collapsing of duplicate lines")?;
+            },
+            ScriptEngineEvent::SetScrollLock(pane_id, locked) => {
+                self.tui_tx.send(TuiRequest::SetScrollLock(pane_id, locked)).await
+                    .context("Set scroll lock")?;
+            },
+            ScriptEngineEvent::SetMaxLineWidth(pane_id, max_width) => {
+                self.tui_tx.send(TuiRequest::SetMaxLineWidth(pane_id, max_width)).await
+                    .context("Set max line width")?;
+            },
+            ScriptEngineEvent::Echo(pane_id, text) => {
+                self.tui_tx.send(TuiRequest::Print(text, pane_id)).await
+                    .context("Echo to pane")?;
+            },
+            ScriptEngineEvent::SetPaneConstraint(pane_id, constraint) => {
+                self.tui_tx.send(TuiRequest::SetPaneConstraint(pane_id, constraint)).await
+                    .context("Set pane constraint")?;
+            },
+            ScriptEngineEvent::SetPane(pane_id, text) => {
+                self.tui_tx.send(TuiRequest::SetPane(pane_id, text)).await
+                    .context("Set pane content")?;
+            },
+            ScriptEngineEvent::SaveBuffer(pane_id, path) => {
+                self.tui_tx.send(TuiRequest::SaveBuffer(pane_id, path)).await
+                    .context("Save buffer")?;
+            },
+            ScriptEngineEvent::SetTimestamps(pane_id, enabled) => {
+                self.tui_tx.send(TuiRequest::SetTimestamps(pane_id, enabled)).await
+                    .context("Set timestamps")?;
+            },
+            ScriptEngineEvent::SetScrollback(pane_id, lines) => {
+                self.tui_tx.send(TuiRequest::SetScrollback(pane_id, lines)).await
+                    .context("Set scrollback")?;
+            },
+            ScriptEngineEvent::GetScrollInfo(pane_id, reply) => {
+                self.tui_tx.send(TuiRequest::GetScrollInfo(pane_id, reply)).await
+                    .context("Get scroll info")?;
+            },
+            ScriptEngineEvent::ScrollTo(pane_id, offset) => {
+                self.tui_tx.send(TuiRequest::ScrollTo(pane_id, offset)).await
+                    .context("Scroll pane")?;
+            },
+            ScriptEngineEvent::SetScrollFraction(fraction) => {
+                self.tui_tx.send(TuiRequest::SetScrollFraction(fraction)).await
+                    .context("Set scroll fraction")?;
+            },
+            ScriptEngineEvent::SetClickSend(enabled) => {
+                self.tui_tx.send(TuiRequest::SetClickSend(enabled)).await
+                    .context("Set click send")?;
+            },
+            ScriptEngineEvent::AddGuard(guard) => {
+                self.tui_tx.send(TuiRequest::AddGuard(guard)).await
+                    .context("Add guard")?;
+            },
+            ScriptEngineEvent::RemoveGuard(pattern) => {
+                self.tui_tx.send(TuiRequest::RemoveGuard(pattern)).await
+                    .context("Remove guard")?;
+            },
+            ScriptEngineEvent::ListGuards => {
+                self.tui_tx.send(TuiRequest::ListGuards).await
+                    .context("List guards")?;
+            },
+            ScriptEngineEvent::SetSpeedwalk(enabled) => {
+                self.tui_tx.send(TuiRequest::SetSpeedwalk(enabled)).await
+                    .context("Set speedwalk")?;
+            },
+            ScriptEngineEvent::SetSecretInput(secret) => {
+                self.tui_tx.send(TuiRequest::SetSecretInput(secret)).await
+                    .context("Set secret input")?;
+            },
+            ScriptEngineEvent::SetPromptLine(enabled) => {
+                self.tui_tx.send(TuiRequest::SetPromptLine(enabled)).await
+                    .context("Set prompt line")?;
+            },
+            ScriptEngineEvent::SetStatusBar(enabled) => {
+                self.tui_tx.send(TuiRequest::SetStatusBar(enabled)).await
+                    .context("Set status bar")?;
+            },
+            ScriptEngineEvent::SetNumpad(numpad) => {
+                self.tui_tx.send(TuiRequest::SetNumpad(numpad)).await
+                    .context("Set numpad directions")?;
+            },
+            ScriptEngineEvent::SetKeyBinding(key, description) => {
+                self.tui_tx.send(TuiRequest::SetKeyBinding(key, description)).await
+                    .context("Set key binding")?;
+            },
+            ScriptEngineEvent::SetEmphasis(emphasis) => {
+                self.tui_tx.send(TuiRequest::SetEmphasis(emphasis)).await
+                    .context("Set emphasis")?;
+            },
+            ScriptEngineEvent::DumpLayout => {
+                self.tui_tx.send(TuiRequest::DumpLayout).await
+                    .context("Dump layout")?;
+            },
+            ScriptEngineEvent::SetQueueWhileDisconnected(enabled) => {
+                self.telnet_tx.send(TelnetRequest::SetQueueWhileDisconnected(enabled)).await
+                    .context("Set queue while disconnected")?;
+            },
+            ScriptEngineEvent::SetAutoReconnect(enabled) => {
+                self.telnet_tx.send(TelnetRequest::SetAutoReconnect(enabled)).await
+                    .context("Set auto reconnect")?;
+            },
+            ScriptEngineEvent::SetKeepalive(seconds, keepalive) => {
+                self.telnet_tx.send(TelnetRequest::SetKeepalive(seconds, keepalive)).await
+                    .context("Set keepalive")?;
+            },
+            ScriptEngineEvent::StartLog(path, keep_ansi) => {
+                match SessionLog::open(&path, keep_ansi) {
+                    Ok(log) => {
+                        self.log = Some(log);
+
+                        self.tui_tx.send(TuiRequest::PrintInfo(format!("Logging to {path}"), 1)).await
+                            .context("Inform about logging")?;
+                    },
+                    Err(err) => {
+                        self.tui_tx.send(TuiRequest::PrintError(format!("{:?}", err.context("Start logging")), 1)).await
+                            .context("Report log failure")?;
+                    },
+                }
+            },
+            ScriptEngineEvent::StopLog => {
+                if let Some(log) = self.log.take() {
+                    self.tui_tx.send(TuiRequest::PrintInfo(format!("Stopped logging to {}", log.path()), 1)).await
+                        .context("Inform about logging stopped")?;
+                }
+            },
+            ScriptEngineEvent::MsdpReport(variable) => {
+                self.telnet_tx.send(TelnetRequest::MsdpReport(variable)).await
+                    .context("Send MSDP report request to Telnet")?;
+            },
+            ScriptEngineEvent::SetEncoding(encoding) => {
+                self.telnet_tx.send(TelnetRequest::SetEncoding(encoding)).await
+                    .context("Set encoding")?;
+            },
+            ScriptEngineEvent::PlaySound(path) => {
+                let tui_tx = self.tui_tx.clone();
+
+                /* A missing audio device or file is not worth more than a warning */
+                tokio::task::spawn_blocking(move || {
+                    if let Err(err) = sound::play(&path) {
+                        let _ = tui_tx.blocking_send(TuiRequest::PrintWarning(format!("{:?}", err.context("Play sound")), 1));
+                    }
+                });
+            },
             ScriptEngineEvent::Error(err) => {
                 self.tui_tx.send(TuiRequest::PrintError(format!("{:?}", err.context("Script error")), 1)).await
                     .context("Display script error")?;
@@ -168,4 +690,12 @@ impl App {
 
         Ok(())
     }
+
+    fn session(&self, session: usize) -> Option<&Sender<TelnetRequest>> {
+        if session == DEFAULT_SESSION {
+            Some(&self.telnet_tx)
+        } else {
+            None
+        }
+    }
 }
\ No newline at end of file
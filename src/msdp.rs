@@ -0,0 +1,121 @@
+//! Mud Server Data Protocol, see https://tintin.mudhalla.net/protocols/msdp/
+
+use anyhow::{Result, bail};
+
+pub const VAR: u8 = 1;
+pub const VAL: u8 = 2;
+const TABLE_OPEN: u8 = 3;
+const TABLE_CLOSE: u8 = 4;
+const ARRAY_OPEN: u8 = 5;
+const ARRAY_CLOSE: u8 = 6;
+
+/// Value of an MSDP variable.
+#[derive(Clone, Debug)]
+pub enum MsdpValue {
+    String(String),
+    Array(Vec<MsdpValue>),
+    /// Variables nested in a value, in the order they were sent.
+    Table(Vec<(String, MsdpValue)>),
+}
+
+/// Parse the variables of an MSDP subnegotiation.
+///
+/// A variable given several values (`VAR name VAL a VAL b`) is taken as an array of them.
+pub fn parse(data: &[u8]) -> Result<Vec<(String, MsdpValue)>> {
+    Parser { data, position: 0 }.variables(None)
+}
+
+/// Encode a variable with a plain string value, e.g. a command for the server such as `REPORT`.
+pub fn encode(name: &str, value: &str) -> Vec<u8> {
+    [&[VAR], name.as_bytes(), &[VAL], value.as_bytes()].concat()
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.position).copied()
+    }
+
+    /// Variables up to `close` (which is consumed), or to the end of the data if it is `None`.
+    fn variables(&mut self, close: Option<u8>) -> Result<Vec<(String, MsdpValue)>> {
+        let mut variables = vec![];
+
+        loop {
+            match self.peek() {
+                None if close.is_none() => break,
+                None => bail!("Table not closed"),
+                Some(byte) if Some(byte) == close => {
+                    self.position += 1;
+                    break;
+                },
+                Some(VAR) => {
+                    self.position += 1;
+                    let name = self.string();
+
+                    let mut values = vec![];
+                    while self.peek() == Some(VAL) {
+                        self.position += 1;
+                        values.push(self.value()?);
+                    }
+
+                    let value = match values.len() {
+                        0 => bail!("No value for variable {name}"),
+                        1 => values.remove(0),
+                        _ => MsdpValue::Array(values),
+                    };
+
+                    variables.push((name, value));
+                },
+                Some(byte) => bail!("Unexpected byte {byte} at {} instead of a variable", self.position),
+            }
+        }
+
+        Ok(variables)
+    }
+
+    fn value(&mut self) -> Result<MsdpValue> {
+        match self.peek() {
+            Some(TABLE_OPEN) => {
+                self.position += 1;
+                Ok(MsdpValue::Table(self.variables(Some(TABLE_CLOSE))?))
+            },
+            Some(ARRAY_OPEN) => {
+                self.position += 1;
+
+                let mut values = vec![];
+                loop {
+                    match self.peek() {
+                        Some(ARRAY_CLOSE) => {
+                            self.position += 1;
+                            break;
+                        },
+                        Some(VAL) => {
+                            self.position += 1;
+                            values.push(self.value()?);
+                        },
+                        Some(byte) => bail!("Unexpected byte {byte} at {} in an array", self.position),
+                        None => bail!("Array not closed"),
+                    }
+                }
+
+                Ok(MsdpValue::Array(values))
+            },
+            _ => Ok(MsdpValue::String(self.string())),
+        }
+    }
+
+    /// Text up to the next marker byte.
+    fn string(&mut self) -> String {
+        let start = self.position;
+
+        while self.peek().is_some_and(|byte| !(VAR..=ARRAY_CLOSE).contains(&byte)) {
+            self.position += 1;
+        }
+
+        String::from_utf8_lossy(&self.data[start..self.position]).into_owned()
+    }
+}
@@ -0,0 +1,401 @@
+//! MUD eXtension Protocol, see https://www.zuggsoft.com/zmud/mxp.htm
+//!
+//! Only the basics are supported: text styles and colors are turned into ANSI codes and `<send>` links into
+//! OSC 8 hyperlinks carrying the command (see `pipeline::OutputLine`). Other well-formed tags are dropped,
+//! while anything which does not parse as a tag is passed through as text.
+
+use crate::pipeline::strip_ansi;
+
+/// Longest tag waited for; an unfinished one longer than this is taken as text.
+const MAX_TAG_LENGTH: usize = 1024;
+
+/// Longest entity (e.g. `&quot;`) waited for, likewise.
+const MAX_ENTITY_LENGTH: usize = 10;
+
+/// Colors known by name (besides `#rrggbb`), as in HTML.
+const COLORS: [(&str, (u8, u8, u8)); 22] = [
+    ("black", (0, 0, 0)),
+    ("maroon", (128, 0, 0)),
+    ("green", (0, 128, 0)),
+    ("olive", (128, 128, 0)),
+    ("navy", (0, 0, 128)),
+    ("purple", (128, 0, 128)),
+    ("teal", (0, 128, 128)),
+    ("silver", (192, 192, 192)),
+    ("gray", (128, 128, 128)),
+    ("grey", (128, 128, 128)),
+    ("red", (255, 0, 0)),
+    ("lime", (0, 255, 0)),
+    ("yellow", (255, 255, 0)),
+    ("blue", (0, 0, 255)),
+    ("fuchsia", (255, 0, 255)),
+    ("magenta", (255, 0, 255)),
+    ("aqua", (0, 255, 255)),
+    ("cyan", (0, 255, 255)),
+    ("white", (255, 255, 255)),
+    ("orange", (255, 165, 0)),
+    ("brown", (165, 42, 42)),
+    ("pink", (255, 192, 203)),
+];
+
+/// State of MXP parsing carried over from one read to the next, as tags may be split between them.
+#[derive(Default)]
+pub struct Parser {
+    /// An unfinished tag, entity or mode escape, with the character starting it.
+    pending: String,
+    /// Open style tags: their names and the SGR parameters they apply.
+    styles: Vec<(String, String)>,
+    /// The open `<send>` link: its command, if given, and its text so far.
+    link: Option<(Option<String>, String)>,
+    /// Whether tags are taken as text (locked mode) and whether only until the end of the line.
+    locked: bool,
+    locked_line: bool,
+}
+
+impl Parser {
+    /// Turn MXP tags in server output into ANSI codes and links, keeping unfinished ones for the next call.
+    pub fn feed(&mut self, data: &str) -> String {
+        let mut output = String::new();
+
+        for ch in data.chars() {
+            self.feed_char(ch, &mut output);
+        }
+
+        output
+    }
+
+    fn feed_char(&mut self, ch: char, output: &mut String) {
+        match self.pending.chars().next() {
+            Some('<') if ch == '>' => {
+                let tag = std::mem::take(&mut self.pending);
+                self.tag(&tag[1..], output);
+                return;
+            },
+            Some('<') if self.pending.len() == 1 && !(ch.is_ascii_alphabetic() || ch == '/' || ch == '!') => {
+                /* Not a tag, e.g. `<3`, so neither is anything up to the next `>` */
+                self.pending.clear();
+                self.text("<", output);
+            },
+            Some('<') if ch != '\n' && self.pending.len() < MAX_TAG_LENGTH => {
+                self.pending.push(ch);
+                return;
+            },
+            Some('&') if ch == ';' => {
+                let entity = std::mem::take(&mut self.pending);
+
+                match decode_entity(&entity[1..]) {
+                    Some(decoded) => self.text(&decoded.to_string(), output),
+                    None => self.text(&format!("{entity};"), output),
+                }
+                return;
+            },
+            Some('&') if (ch.is_ascii_alphanumeric() || ch == '#') && self.pending.len() < MAX_ENTITY_LENGTH => {
+                self.pending.push(ch);
+                return;
+            },
+            /* Mode escapes: `ESC [ <number> z`; any other escape sequence is passed through */
+            Some('\x1b') if ch == '[' && self.pending.len() == 1 || ch.is_ascii_digit() && self.pending.len() > 1 => {
+                self.pending.push(ch);
+                return;
+            },
+            Some('\x1b') if ch == 'z' && self.pending.len() > 2 => {
+                let mode = std::mem::take(&mut self.pending)[2..].parse().unwrap_or(0);
+                self.set_mode(mode, output);
+                return;
+            },
+            /* Not what it looked like the start of, so it is text after all */
+            Some('<') => {
+                let text = std::mem::take(&mut self.pending);
+                self.not_a_tag(&text[1..], output);
+            },
+            Some(_) => {
+                let text = std::mem::take(&mut self.pending);
+                self.text(&text, output);
+            },
+            None => {},
+        }
+
+        match ch {
+            '<' | '&' if !self.locked => self.pending.push(ch),
+            '\x1b' => self.pending.push(ch),
+            '\n' => self.end_line(output),
+            _ => self.text(&ch.to_string(), output),
+        }
+    }
+
+    /// Output what looked like the start of a tag as text, which may still contain entities (or tags).
+    fn not_a_tag(&mut self, content: &str, output: &mut String) {
+        self.text("<", output);
+
+        for ch in content.chars() {
+            self.feed_char(ch, output);
+        }
+    }
+
+    /// Output text, or keep it as the text of the open link.
+    fn text(&mut self, text: &str, output: &mut String) {
+        match &mut self.link {
+            Some((_, link_text)) => link_text.push_str(text),
+            None => output.push_str(text),
+        }
+    }
+
+    /// Tags and links do not carry over to the next line, nor does a locked mode given for the line.
+    fn end_line(&mut self, output: &mut String) {
+        self.reset(output);
+
+        if self.locked_line {
+            self.locked = false;
+            self.locked_line = false;
+        }
+
+        output.push('\n');
+    }
+
+    /// Close the open link and all open style tags.
+    fn reset(&mut self, output: &mut String) {
+        self.close_link(output);
+
+        if !self.styles.is_empty() {
+            self.styles.clear();
+            output.push_str("\x1b[0m");
+        }
+    }
+
+    fn set_mode(&mut self, mode: u32, output: &mut String) {
+        match mode {
+            2 => {
+                self.locked = true;
+                self.locked_line = true;
+            },
+            3 => {
+                self.reset(output);
+                self.locked = false;
+            },
+            7 => {
+                self.locked = true;
+                self.locked_line = false;
+            },
+            0..=6 => {
+                self.locked = false;
+            },
+            /* Line tags of elements defined by the server, which are not supported */
+            _ => {},
+        }
+    }
+
+    /// Handle the contents of a tag (between `<` and `>`), taking it as text if it is not well-formed.
+    fn tag(&mut self, content: &str, output: &mut String) {
+        /* Definitions of elements and entities, which are not supported */
+        if content.starts_with('!') {
+            return;
+        }
+
+        let Some(tag) = Tag::parse(content) else {
+            self.not_a_tag(content, output);
+            self.text(">", output);
+            return;
+        };
+
+        if tag.closing {
+            match tag.name.as_str() {
+                "send" => self.close_link(output),
+                name => self.close_style(name, output),
+            }
+
+            return;
+        }
+
+        let sgr = match tag.name.as_str() {
+            "b" | "bold" | "strong" | "h" | "high" => "1".to_string(),
+            "i" | "italic" | "em" => "3".to_string(),
+            "u" | "underline" | "a" => "4".to_string(),
+            "s" | "strikeout" => "9".to_string(),
+            "c" | "color" => color_sgr(tag.attribute("fore", 0), tag.attribute("back", 1)),
+            "font" => color_sgr(tag.attribute("color", 1), tag.attribute("back", 2)),
+            "send" => {
+                self.close_link(output);
+                self.link = Some((tag.attribute("href", 0).map(str::to_string), String::new()));
+                return;
+            },
+            "br" => {
+                self.end_line(output);
+                return;
+            },
+            _ => { return; },
+        };
+
+        if !sgr.is_empty() {
+            self.text(&format!("\x1b[{sgr}m"), output);
+        }
+
+        self.styles.push((tag.name, sgr));
+    }
+
+    /// Close the last open style tag with this name, reapplying the styles of those still open.
+    fn close_style(&mut self, name: &str, output: &mut String) {
+        let Some(index) = self.styles.iter().rposition(|(open, _)| open == name) else {
+            return;
+        };
+
+        self.styles.remove(index);
+
+        let mut codes = "\x1b[0m".to_string();
+        for (_, sgr) in self.styles.iter().filter(|(_, sgr)| !sgr.is_empty()) {
+            codes.push_str(&format!("\x1b[{sgr}m"));
+        }
+
+        self.text(&codes, output);
+    }
+
+    /// Output the open link as an OSC 8 hyperlink to its command, which is its text unless given.
+    fn close_link(&mut self, output: &mut String) {
+        let Some((command, text)) = self.link.take() else {
+            return;
+        };
+
+        let plain = strip_ansi(&text);
+
+        /* Several commands make a menu; without one, the first is sent */
+        let command = match command {
+            Some(command) => command.replace("&text;", &plain),
+            None => plain,
+        };
+        let command: String = command.split('|').next().unwrap_or_default().trim().chars()
+            .filter(|ch| !ch.is_control())
+            .collect();
+
+        if command.is_empty() {
+            output.push_str(&text);
+        } else {
+            output.push_str(&format!("\x1b]8;;{command}\x1b\\{text}\x1b]8;;\x1b\\"));
+        }
+    }
+}
+
+/// A parsed tag: its name (in lowercase), whether it closes an element and its attributes
+/// (by name, or without one to be taken by position).
+struct Tag {
+    name: String,
+    closing: bool,
+    attributes: Vec<(Option<String>, String)>,
+}
+
+impl Tag {
+    fn parse(content: &str) -> Option<Tag> {
+        let (closing, content) = match content.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (false, content.strip_suffix('/').unwrap_or(content)),
+        };
+
+        let name_end = content.find(char::is_whitespace).unwrap_or(content.len());
+        let name = &content[..name_end];
+
+        if !name.starts_with(|ch: char| ch.is_ascii_alphabetic())
+            || !name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-') {
+            return None;
+        }
+
+        let attributes = parse_attributes(&content[name_end..])?;
+
+        if closing && !attributes.is_empty() {
+            return None;
+        }
+
+        Some(Tag { name: name.to_ascii_lowercase(), closing, attributes })
+    }
+
+    /// Value of the attribute given by name or, if not, by its position among those given without a name.
+    fn attribute(&self, name: &str, position: usize) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(key, _)| key.as_deref() == Some(name))
+            .or_else(|| self.attributes.iter().filter(|(key, _)| key.is_none()).nth(position))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parse `name=value`, `name="value"` and bare `value` attributes; `None` if a quote is left open.
+fn parse_attributes(mut rest: &str) -> Option<Vec<(Option<String>, String)>> {
+    let mut attributes = vec![];
+
+    loop {
+        rest = rest.trim_start();
+
+        if rest.is_empty() {
+            return Some(attributes);
+        }
+
+        if rest.starts_with(['"', '\'']) {
+            let (value, after) = quoted(rest)?;
+            attributes.push((None, value));
+            rest = after;
+            continue;
+        }
+
+        let end = rest.find(|ch: char| ch.is_whitespace() || ch == '=').unwrap_or(rest.len());
+        let word = &rest[..end];
+        rest = &rest[end..];
+
+        match rest.strip_prefix('=') {
+            Some(after) if after.starts_with(['"', '\'']) => {
+                let (value, after) = quoted(after)?;
+                attributes.push((Some(word.to_ascii_lowercase()), value));
+                rest = after;
+            },
+            Some(after) => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                attributes.push((Some(word.to_ascii_lowercase()), after[..end].to_string()));
+                rest = &after[end..];
+            },
+            None => attributes.push((None, word.to_string())),
+        }
+    }
+}
+
+/// Split a quoted value off the start of `text`, returning it (without the quotes) and what follows.
+fn quoted(text: &str) -> Option<(String, &str)> {
+    let quote = text.chars().next()?;
+    let end = text[1..].find(quote)? + 1;
+
+    Some((text[1..end].to_string(), &text[end + 1..]))
+}
+
+/// SGR parameters for text and background colors, leaving out those not given or not understood.
+fn color_sgr(fore: Option<&str>, back: Option<&str>) -> String {
+    let fore = fore.and_then(parse_color).map(|(r, g, b)| format!("38;2;{r};{g};{b}"));
+    let back = back.and_then(parse_color).map(|(r, g, b)| format!("48;2;{r};{g};{b}"));
+
+    [fore, back].into_iter().flatten().collect::<Vec<_>>().join(";")
+}
+
+/// Parse a color name or `#rrggbb`.
+fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+
+        return Some((channel(0)?, channel(2)?, channel(4)?));
+    }
+
+    COLORS.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(color))
+        .map(|(_, rgb)| *rgb)
+}
+
+/// Decode an entity by its name (without `&` and `;`), e.g. `lt` or `#65`.
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => name.strip_prefix('#')
+            .and_then(|code| code.parse().ok())
+            .and_then(char::from_u32),
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use anyhow::{Result, Context};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::{channel, Sender, Receiver};
+
+use crate::telnet::*;
+
+/// Stand-in for `telnet_connection` which never touches the network.
+///
+/// Anything sent is discarded. If a replay file is given, connecting (to any address) streams its lines
+/// as if they were received from a server, one every `delay`, so that scripts can be tested offline.
+pub fn offline_connection(replay: Option<String>, delay: Duration) -> Result<(Sender<TelnetRequest>, Receiver<TelnetEvent>)> {
+    let (req_tx, mut req_rx) = channel(1024);
+    let (ev_tx, ev_rx) = channel(1024);
+
+    tokio::spawn(async move {
+        let mut replay = replay;
+
+        while let Some(request) = req_rx.recv().await {
+            match request {
+                TelnetRequest::Connect(address, port) | TelnetRequest::ConnectTls(address, port) => {
+                    ev_tx.send(TelnetEvent::Info(format!("Offline: not connecting to {address}:{port}"))).await
+                        .context("Inform about offline connection")?;
+
+                    if let Some(path) = replay.take() {
+                        let ev_tx = ev_tx.clone();
+
+                        tokio::spawn(async move {
+                            if let Err(err) = replay_file(&path, delay, &ev_tx).await {
+                                ev_tx.send(TelnetEvent::Error(err.context("Replay"))).await
+                                    .context("Send replay error")?;
+                            }
+
+                            anyhow::Ok(())
+                        });
+                    }
+                },
+                TelnetRequest::Send(_)
+                | TelnetRequest::SendNoNewline(_)
+                | TelnetRequest::SendRaw(_)
+                | TelnetRequest::Disconnect
+                | TelnetRequest::SetQueueWhileDisconnected(_)
+                | TelnetRequest::ClearQueue
+                | TelnetRequest::SetAutoReconnect(_)
+                | TelnetRequest::MsdpReport(_)
+                | TelnetRequest::SetEncoding(_)
+                | TelnetRequest::SetKeepalive(..)
+                | TelnetRequest::WindowResize(_, _) => {},
+                TelnetRequest::Shutdown => { break; },
+            }
+        }
+
+        anyhow::Ok(())
+    });
+
+    Ok((req_tx, ev_rx))
+}
+
+async fn replay_file(path: &str, delay: Duration, ev_tx: &Sender<TelnetEvent>) -> Result<()> {
+    let file = tokio::fs::File::open(path).await
+        .context(format!("Open replay file {path}"))?;
+
+    ev_tx.send(TelnetEvent::Info(format!("Replaying {path}..."))).await
+        .context("Inform about replay")?;
+
+    let mut reader = BufReader::new(file);
+    let mut line = vec![];
+
+    /* Replayed output is decoded like a connection's, as a recording of one may well contain MXP */
+    let mut output = OutputDecoder::default();
+    output.enable_mxp();
+
+    while reader.read_until(b'\n', &mut line).await.context("Read replay file")? > 0 {
+        tokio::time::sleep(delay).await;
+
+        let (data, _) = output.decode(&String::from_utf8_lossy(&line));
+        line.clear();
+
+        let unterminated = !data.is_empty() && !data.ends_with('\n');
+
+        ev_tx.send(TelnetEvent::Data(data)).await
+            .context("Send replayed data")?;
+
+        if unterminated {
+            ev_tx.send(TelnetEvent::Prompt(output.partial_line().to_string(), false)).await
+                .context("Send replayed prompt")?;
+        }
+    }
+
+    /* The file ending without a newline still ends its last line */
+    let last_line = output.take_partial_line();
+    if !last_line.is_empty() {
+        ev_tx.send(TelnetEvent::Prompt(last_line, true)).await
+            .context("Send replayed prompt")?;
+    }
+
+    ev_tx.send(TelnetEvent::Info("Replay finished.".into())).await
+        .context("Inform about end of replay")?;
+
+    Ok(())
+}
@@ -0,0 +1,278 @@
+//! Processing of server output on its way to the screen.
+//!
+//! Each complete line of output goes through the stages in `STAGES` order, and each stage can rewrite the line,
+//! annotate it (e.g. with a background color) or drop it altogether, in which case later stages do not see it
+//! and it is not displayed. Lines kept by all stages are then displayed, which is also when presentation-only
+//! styling (such as plain-text emphasis) is applied.
+//!
+//! Stages which change what a line says come first, then those which decide whether it is shown,
+//! then those which only annotate it and finally those which react to it (e.g. triggers), so that reactions
+//! see the line as it is displayed.
+
+use std::ops::Range;
+
+use ratatui::style::Color;
+use regex::Regex;
+
+/// A line of server output (possibly with ANSI codes) and what the stages have noted about it.
+#[derive(Clone)]
+pub struct OutputLine {
+    pub text: String,
+
+    /// Background color for the whole line.
+    pub background: Option<Color>,
+
+    /// Parts of the line matching these patterns get these text colors, applied in order.
+    pub highlights: Vec<(Regex, Color)>,
+
+    /// Commands sent by clicking parts of the line (byte ranges of its plain text), e.g. MXP `<send>` links.
+    pub links: Vec<(Range<usize>, String)>,
+}
+
+impl OutputLine {
+    /// A line as received; OSC 8 hyperlinks in it are taken out of the text and kept as its links.
+    pub fn new(text: &str) -> OutputLine {
+        let (text, links) = extract_links(text);
+
+        OutputLine { text, background: None, highlights: vec![], links }
+    }
+
+    /// Text of the line with ANSI escape sequences removed, i.e. as it reads on the screen.
+    pub fn plain(&self) -> String {
+        strip_ansi(&self.text)
+    }
+}
+
+/// Remove ANSI escape sequences (including OSC ones, such as hyperlinks) from text.
+pub fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            plain.push(ch);
+            continue;
+        }
+
+        /* CSI sequences (`ESC [ ... final`) and OSC ones (`ESC ] ... ST`); any other escape is just its next character */
+        match chars.next() {
+            Some('[') => {
+                for ch in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&ch) {
+                        break;
+                    }
+                }
+            },
+            Some(']') => {
+                skip_osc(&mut chars);
+            },
+            _ => {},
+        }
+    }
+
+    plain
+}
+
+/// Skip the rest of an OSC sequence, up to its terminator (`ESC \` or BEL), returning its contents.
+fn skip_osc(chars: &mut std::str::Chars) -> String {
+    let mut contents = String::new();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\x07' => break,
+            '\x1b' => {
+                chars.next();
+                break;
+            },
+            ch => contents.push(ch),
+        }
+    }
+
+    contents
+}
+
+/// Take OSC 8 hyperlinks (`ESC ] 8 ; params ; target ST text ESC ] 8 ; ; ST`) out of text,
+/// returning the rest of it and the targets with the ranges of the plain text they were given for.
+fn extract_links(text: &str) -> (String, Vec<(Range<usize>, String)>) {
+    let mut rest = String::with_capacity(text.len());
+    let mut links = vec![];
+    let mut open: Option<(usize, String)> = None;
+    let mut chars = text.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' || !chars.as_str().starts_with("]8;") {
+            rest.push(ch);
+            continue;
+        }
+
+        chars.next();
+        let contents = skip_osc(&mut chars);
+        let target = contents.splitn(3, ';').nth(2).unwrap_or_default();
+        let position = strip_ansi(&rest).len();
+
+        if let Some((start, target)) = open.take() {
+            if start < position {
+                links.push((start..position, target));
+            }
+        }
+
+        if !target.is_empty() {
+            open = Some((position, target.to_string()));
+        }
+    }
+
+    /* A link left open runs to the end of the line */
+    if let Some((start, target)) = open {
+        let end = strip_ansi(&rest).len();
+
+        if start < end {
+            links.push((start..end, target));
+        }
+    }
+
+    (rest, links)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// Pass the line on to the next stage.
+    Keep,
+    /// Neither display the line nor pass it on.
+    Drop,
+}
+
+#[derive(Clone, Copy)]
+pub enum Stage {
+    /// Text matching a pattern is replaced (`substitute`), so that all later stages see the line as rewritten.
+    Substitute,
+    /// Lines matching a pattern are dropped (`gag`); they reach neither later stages nor the screen.
+    Gag,
+    /// Whole lines matching a pattern get a background color (`highlight_line`)
+    /// and parts matching a pattern get a text color (`highlight`).
+    Highlight,
+    /// Triggers, tick detection and sounds; never changes the line, but drops it if a trigger callback calls `gag()`.
+    Trigger,
+}
+
+/// Stages in the order they are applied to each line.
+pub const STAGES: [Stage; 4] = [Stage::Substitute, Stage::Gag, Stage::Highlight, Stage::Trigger];
+
+/// Substitute stage: every match of each pattern, in the order given, is replaced (with `$1` etc. standing for
+/// captures). Patterns are matched against the text with its ANSI codes, so that its colors are kept; failing that,
+/// against its plain text, in which case the line loses its colors. Replacements may add ANSI codes of their own.
+pub fn substitute(line: &mut OutputLine, substitutions: &[(Regex, String)]) -> Verdict {
+    for (pattern, replacement) in substitutions {
+        let rewritten = if pattern.is_match(&line.text) {
+            pattern.replace_all(&line.text, replacement.as_str()).into_owned()
+        } else {
+            let plain = line.plain();
+
+            if !pattern.is_match(&plain) {
+                continue;
+            }
+
+            pattern.replace_all(&plain, replacement.as_str()).into_owned()
+        };
+
+        /* Links are given for parts of the line as received, which may have moved */
+        line.text = rewritten;
+        line.links.clear();
+    }
+
+    Verdict::Keep
+}
+
+/// Gag stage: a line matching any of the patterns is dropped.
+pub fn gag(line: &OutputLine, gags: &[Regex]) -> Verdict {
+    let plain = line.plain();
+
+    if gags.iter().any(|pattern| pattern.is_match(&plain)) {
+        Verdict::Drop
+    } else {
+        Verdict::Keep
+    }
+}
+
+/// Highlight stage: the first of `line_highlights` matching the line's text sets its background,
+/// and all matching `highlights` are noted to color the matched parts once displayed.
+pub fn highlight(line: &mut OutputLine, line_highlights: &[(Regex, Color)], highlights: &[(Regex, Color)]) -> Verdict {
+    let plain = line.plain();
+
+    if let Some((_, color)) = line_highlights.iter().find(|(pattern, _)| pattern.is_match(&plain)) {
+        line.background = Some(*color);
+    }
+
+    line.highlights = highlights.iter()
+        .filter(|(pattern, _)| pattern.is_match(&plain))
+        .cloned()
+        .collect();
+
+    Verdict::Keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex(pattern: &str) -> Regex {
+        Regex::new(pattern).unwrap()
+    }
+
+    #[test]
+    fn gag_sees_the_line_as_substituted() {
+        let mut line = OutputLine::new("Bob tells you: hi");
+
+        substitute(&mut line, &[(regex("^Bob"), "Spammer".into())]);
+
+        assert_eq!(line.text, "Spammer tells you: hi");
+        assert!(gag(&line, &[regex("^Spammer tells")]) == Verdict::Drop);
+        assert!(gag(&line, &[regex("^Bob tells")]) == Verdict::Keep);
+    }
+
+    #[test]
+    fn substitution_keeps_colors_when_matching_the_text_with_them() {
+        let mut line = OutputLine::new("\x1b[31mred\x1b[0m dragon");
+
+        substitute(&mut line, &[(regex("dragon"), "wyrm".into())]);
+
+        assert_eq!(line.text, "\x1b[31mred\x1b[0m wyrm");
+    }
+
+    #[test]
+    fn gag_drops_a_matching_line_by_its_plain_text() {
+        let line = OutputLine::new("\x1b[1mYou are hungry.\x1b[0m");
+
+        assert!(gag(&line, &[regex("^You are hungry\\.$")]) == Verdict::Drop);
+        assert!(gag(&line, &[regex("thirsty")]) == Verdict::Keep);
+    }
+
+    #[test]
+    fn highlight_annotates_without_changing_the_text() {
+        let mut line = OutputLine::new("A goblin attacks you!");
+
+        let verdict = highlight(&mut line,
+            &[(regex("attacks you"), Color::Red), (regex("goblin"), Color::Blue)],
+            &[(regex("goblin"), Color::Green), (regex("orc"), Color::Yellow)]);
+
+        assert!(verdict == Verdict::Keep);
+        assert_eq!(line.text, "A goblin attacks you!");
+        assert_eq!(line.background, Some(Color::Red));
+        assert_eq!(line.highlights.len(), 1);
+        assert_eq!(line.highlights[0].1, Color::Green);
+    }
+
+    #[test]
+    fn strip_ansi_removes_sgr_and_osc_8() {
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m plain"), "red plain");
+        assert_eq!(strip_ansi("see \x1b]8;;https://example.com\x1b\\here\x1b]8;;\x1b\\ now"), "see here now");
+        assert_eq!(strip_ansi("bell \x1b]8;;https://example.com\x07ended\x1b]8;;\x07"), "bell ended");
+    }
+
+    #[test]
+    fn links_are_taken_out_with_their_plain_text_ranges() {
+        let line = OutputLine::new("go \x1b[32m\x1b]8;;north\x1b\\north\x1b]8;;\x1b\\\x1b[0m or \x1b]8;;south\x07south");
+
+        assert_eq!(line.plain(), "go north or south");
+        assert_eq!(line.links, vec![(3..8, "north".to_string()), (12..17, "south".to_string())]);
+    }
+}
@@ -10,9 +10,9 @@ impl<T: Clone + PartialEq> RingBuffer<T> {
         RingBuffer { buffer: vec![None; capacity], front: 0, back: 0 }
     }
 
-    /* pub fn capacity(&self) -> usize {
-        self.buffer.capacity()
-    } */
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
 
     pub fn size(&self) -> usize {
         if self.is_full() {
@@ -25,16 +25,17 @@ impl<T: Clone + PartialEq> RingBuffer<T> {
     }
 
     pub fn is_full(&self) -> bool {
-        self.front == self.back && self.buffer[self.front].is_some()
+        self.front == self.back && self.front().is_some()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.front == self.back && self.buffer[self.front].is_none()
+        self.front == self.back && self.front().is_none()
     }
 
-    /* pub fn front(&self) -> &Option<T> {
+    /// The oldest item, or `None` if the buffer is empty.
+    pub fn front(&self) -> &Option<T> {
         &self.buffer[self.front]
-    } */
+    }
 
     pub fn push_back(&mut self, value: T) {
         if self.is_full() {
@@ -45,33 +46,62 @@ impl<T: Clone + PartialEq> RingBuffer<T> {
         self.back = (self.back + 1) % self.buffer.len();
     }
 
+    pub fn back(&self) -> Option<&T> {
+        if self.is_empty() {
+            None
+        } else {
+            let index = (self.back + self.buffer.len() - 1) % self.buffer.len();
+            self.buffer[index].as_ref()
+        }
+    }
+
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        if self.is_empty() {
+            None
+        } else {
+            let index = (self.back + self.buffer.len() - 1) % self.buffer.len();
+            self.buffer[index].as_mut()
+        }
+    }
+
+    /// Change the capacity (which must be positive), keeping the items in order;
+    /// when shrinking below the size, the oldest ones are dropped.
+    pub fn resize(&mut self, capacity: usize) {
+        assert!(capacity > 0, "Ring buffer capacity must be positive");
+
+        let mut resized = RingBuffer::new(capacity);
+
+        while let Some(item) = self.pop_front() {
+            resized.push_back(item);
+        }
+
+        *self = resized;
+    }
+
+    /// Move an item equal to `value` to the back, or push `value` if there is no such item.
+    ///
+    /// Items after the moved one shift towards the front by one, so the order of the rest is kept.
+    /// This is O(n) in the size of the buffer.
     pub fn find_and_push_back(&mut self, value: T) {
         if self.is_empty() {
             self.push_back(value);
         } else if let Some(index) = self.find_forwards(|x| *x == value, self.size() - 1) {
-            let dst = if self.back == 0 {
-                self.buffer.len()
-            } else {
-                self.back
-            };
-
-            let src = (self.front + index) % self.buffer.len();
-
-            if src == dst - 1 {
-            } else if src < dst {
-                self.buffer[src..dst].rotate_left(1);
-            } else {
-                let cap = self.buffer.len();
-                self.buffer[src..cap].rotate_left(1);
-                self.buffer[0..dst].rotate_left(1);
-                self.buffer.swap(cap - 1, dst - 1);
+            let last = self.size() - 1;
+
+            for i in index..last {
+                let (current, next) = (self.physical_index(i), self.physical_index(i + 1));
+                self.buffer[current] = self.buffer[next].take();
             }
+
+            let back = self.physical_index(last);
+            self.buffer[back] = Some(value);
         } else {
             self.push_back(value);
         }
     }
 
-    /* pub fn pop_front(&mut self) -> Option<T> {
+    /// Remove and return the oldest item, or `None` if the buffer is empty.
+    pub fn pop_front(&mut self) -> Option<T> {
         if self.is_empty() {
             None
         } else {
@@ -79,48 +109,47 @@ impl<T: Clone + PartialEq> RingBuffer<T> {
             self.front = (self.front + 1) % self.buffer.len();
             item
         }
-    } */
+    }
 
-    pub fn get(&self, index: usize) -> &Option<T> {
-        &self.buffer[(self.front + index) % self.buffer.len()]
+    /// The item with a given index (counting from the front), or `None` past the back.
+    pub fn get_checked(&self, index: usize) -> Option<&T> {
+        if index < self.size() {
+            self.buffer[self.physical_index(index)].as_ref()
+        } else {
+            None
+        }
     }
 
-    pub fn iter_from_back<'a>(&'a self) -> Box<dyn Iterator<Item = T> + 'a> {
-        Box::new(RingBufferIterator::from(self, |x| x.saturating_sub(1)))
+    /// The slot of the item with a given index (counting from the front), without checking the index against the size:
+    /// past the back it wraps around, giving `None` or an item from the front. Use `get_checked` unless that is intended.
+    fn get(&self, index: usize) -> &Option<T> {
+        &self.buffer[self.physical_index(index)]
     }
 
-    pub fn find_backwards(&self, pred: impl Fn(&T) -> bool, start_at: usize) -> Option<usize> {
-        let mut iter = start_at;
-        while let Some(value) = &self.get(iter) {
-            if iter == self.buffer.len() {
-                return None;
-            }
+    /// Position in `buffer` of the item with a given index (counting from the front).
+    fn physical_index(&self, index: usize) -> usize {
+        (self.front + index) % self.buffer.len()
+    }
 
-            if pred(value) {
-                return Some(iter);
-            }
+    pub fn iter_from_back<'a>(&'a self) -> Box<dyn Iterator<Item = T> + 'a> {
+        Box::new(RingBufferIterator::from(self, self.size().saturating_sub(1), |x| x.saturating_sub(1)))
+    }
 
-            iter += 1;
-        }
+    /// Iterate from the front (oldest item) to the back.
+    pub fn iter_forwards<'a>(&'a self) -> Box<dyn Iterator<Item = T> + 'a> {
+        Box::new(RingBufferIterator::from(self, 0, |x| x + 1))
+    }
 
-        None
+    /// Index of the first item matching `pred`, going from `start_at` towards the back.
+    pub fn find_backwards(&self, pred: impl Fn(&T) -> bool, start_at: usize) -> Option<usize> {
+        (start_at..self.size())
+            .find(|&index| self.get_checked(index).is_some_and(&pred))
     }
 
+    /// Index of the first item matching `pred`, going from `start_at` (or the back, if past it) towards the front.
     pub fn find_forwards(&self, pred: impl Fn(&T) -> bool, start_at: usize) -> Option<usize> {
-        let mut iter = start_at;
-        while let Some(value) = &self.get(iter) {
-            if pred(value) {
-                return Some(iter);
-            }
-
-            if iter == 0 {
-                return None;
-            }
-
-            iter -= 1;
-        }
-
-        None
+        (0..self.size().min(start_at.saturating_add(1))).rev()
+            .find(|&index| self.get_checked(index).is_some_and(&pred))
     }
 }
 
@@ -132,10 +161,10 @@ pub struct RingBufferIterator<'a, T: Clone + PartialEq + 'a> {
 }
 
 impl <'a, T: Clone + PartialEq + 'a> RingBufferIterator<'a, T> {
-    fn from(buffer: &'a RingBuffer<T>, position_mut: fn(usize) -> usize) -> RingBufferIterator<'_, T> {
+    fn from(buffer: &'a RingBuffer<T>, position: usize, position_mut: fn(usize) -> usize) -> RingBufferIterator<'a, T> {
         RingBufferIterator {
             buffer,
-            position: buffer.size().saturating_sub(1),
+            position,
             position_mut,
             done: false,
         }
@@ -146,7 +175,8 @@ impl<'a, T: Clone + PartialEq> Iterator for RingBufferIterator<'a, T> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.done {
+        /* `get` wraps around, so positions past the back must not reach it */
+        if self.done || self.position >= self.buffer.size() {
             None
         } else if let Some(value) = self.buffer.get(self.position) {
             let new_position = (self.position_mut)(self.position);
@@ -161,4 +191,183 @@ impl<'a, T: Clone + PartialEq> Iterator for RingBufferIterator<'a, T> {
             None
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(capacity: usize, items: &[i32]) -> RingBuffer<i32> {
+        let mut ring = RingBuffer::new(capacity);
+        for &item in items {
+            ring.push_back(item);
+        }
+        ring
+    }
+
+    fn items(ring: &RingBuffer<i32>) -> Vec<i32> {
+        ring.iter_forwards().collect()
+    }
+
+    #[test]
+    fn find_and_push_back_moves_a_match_across_the_wraparound() {
+        /* 1 and 2 were dropped, so the items start in the middle of the storage */
+        let mut ring = ring(4, &[1, 2, 3, 4, 5, 6]);
+
+        ring.find_and_push_back(4);
+
+        assert_eq!(items(&ring), vec![3, 5, 6, 4]);
+    }
+
+    #[test]
+    fn find_and_push_back_moves_the_front() {
+        let mut ring = ring(3, &[1, 2, 3]);
+
+        ring.find_and_push_back(1);
+
+        assert_eq!(items(&ring), vec![2, 3, 1]);
+        assert_eq!(ring.front(), &Some(2));
+    }
+
+    #[test]
+    fn find_and_push_back_pushes_without_a_match() {
+        let mut ring = ring(3, &[1, 2]);
+
+        ring.find_and_push_back(3);
+        assert_eq!(items(&ring), vec![1, 2, 3]);
+
+        ring.find_and_push_back(4);
+        assert_eq!(items(&ring), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn find_and_push_back_keeps_the_back_in_place() {
+        let mut ring = ring(3, &[1, 2, 3]);
+
+        ring.find_and_push_back(3);
+
+        assert_eq!(items(&ring), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn resize_keeps_the_items_in_order() {
+        let mut ring = ring(3, &[1, 2, 3, 4]);
+
+        ring.resize(5);
+        ring.push_back(5);
+
+        assert_eq!(items(&ring), vec![2, 3, 4, 5]);
+        assert_eq!(ring.capacity(), 5);
+    }
+
+    #[test]
+    fn resize_below_the_size_drops_the_oldest_items() {
+        let mut ring = ring(4, &[1, 2, 3, 4, 5]);
+
+        ring.resize(2);
+
+        assert_eq!(items(&ring), vec![4, 5]);
+        assert!(ring.is_full());
+    }
+
+    #[test]
+    fn find_stays_within_the_items() {
+        /* The empty slots past the back must not be read, nor items from the front wrapped around to */
+        let ring = ring(4, &[1, 2]);
+
+        assert_eq!(ring.find_backwards(|_| true, 2), None);
+        assert_eq!(ring.find_backwards(|&x| x == 1, 1), None);
+        assert_eq!(ring.find_forwards(|&x| x == 2, 3), Some(1));
+        assert_eq!(ring.find_forwards(|&x| x == 1, 10), Some(0));
+        assert_eq!(ring.find_forwards(|&x| x == 3, 1), None);
+    }
+
+    #[test]
+    fn pop_front_until_empty() {
+        let mut ring = ring(3, &[1, 2]);
+
+        assert_eq!(ring.pop_front(), Some(1));
+        assert_eq!(ring.front(), &Some(2));
+        assert_eq!(ring.pop_front(), Some(2));
+
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop_front(), None);
+        assert_eq!(ring.front(), &None);
+    }
+
+    #[test]
+    fn pop_front_interleaved_with_push_back() {
+        let mut ring = ring(3, &[1]);
+
+        ring.push_back(2);
+        assert_eq!(ring.pop_front(), Some(1));
+        ring.push_back(3);
+        ring.push_back(4);
+        assert_eq!(ring.pop_front(), Some(2));
+        ring.push_back(5);
+
+        assert_eq!(items(&ring), vec![3, 4, 5]);
+        assert_eq!(ring.pop_front(), Some(3));
+        assert_eq!(ring.size(), 2);
+    }
+
+    #[test]
+    fn pop_front_on_a_full_wrapped_buffer() {
+        let mut ring = ring(3, &[1, 2, 3, 4, 5]);
+        assert!(ring.is_full());
+
+        assert_eq!(ring.pop_front(), Some(3));
+        assert!(!ring.is_full());
+        assert_eq!(ring.pop_front(), Some(4));
+        assert_eq!(ring.pop_front(), Some(5));
+        assert_eq!(ring.pop_front(), None);
+        assert_eq!(ring.front(), &None);
+    }
+
+    #[test]
+    fn get_checked_is_none_past_the_back() {
+        let ring = ring(4, &[1, 2]);
+
+        assert_eq!(ring.get_checked(1), Some(&2));
+        assert_eq!(ring.get_checked(ring.size()), None);
+        assert_eq!(ring.get_checked(3), None);
+        assert_eq!(ring.get_checked(100), None);
+    }
+
+    #[test]
+    fn get_checked_is_none_past_the_back_after_wraparound() {
+        /* The items start in the middle of the storage, so index 2 would wrap around to the slot of 3 */
+        let mut ring = ring(4, &[1, 2, 3, 4, 5, 6]);
+        ring.pop_front();
+        ring.pop_front();
+
+        assert_eq!(ring.get_checked(0), Some(&5));
+        assert_eq!(ring.get_checked(1), Some(&6));
+        assert_eq!(ring.get_checked(ring.size()), None);
+        assert_eq!(ring.get_checked(3), None);
+        assert_eq!(ring.get_checked(4), None);
+    }
+
+    fn assert_iterations_agree(ring: &RingBuffer<i32>, expected: &[i32]) {
+        let mut from_back: Vec<i32> = ring.iter_from_back().collect();
+        from_back.reverse();
+
+        assert_eq!(items(ring), expected);
+        assert_eq!(items(ring), from_back);
+    }
+
+    #[test]
+    fn iter_forwards_on_an_empty_buffer() {
+        assert_iterations_agree(&ring(3, &[]), &[]);
+    }
+
+    #[test]
+    fn iter_forwards_on_a_partially_filled_buffer() {
+        assert_iterations_agree(&ring(4, &[1, 2, 3]), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn iter_forwards_on_a_wrapped_full_buffer() {
+        assert_iterations_agree(&ring(3, &[1, 2, 3, 4, 5]), &[3, 4, 5]);
+    }
 }
\ No newline at end of file
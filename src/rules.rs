@@ -0,0 +1,178 @@
+use std::io::{BufRead, BufReader, Write};
+
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+
+/// First line of a rules file, identifying the format version.
+const RULES_HEADER: &str = "# draugr rules v1";
+
+/// A declarative rule, i.e. one that (unlike script callbacks) can be saved to a file and loaded back.
+#[derive(Clone)]
+pub struct Rule {
+    pub kind: RuleKind,
+    pub pattern: Regex,
+    pub action: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RuleKind {
+    /// Send `action` whenever a line matches `pattern`; `$1` etc. are replaced by capture groups.
+    Trigger,
+    /// Play the sound file at `action` whenever a line matches `pattern`.
+    Sound,
+    /// Do not display lines matching `pattern`; `action` is empty.
+    Gag,
+    /// Color the parts of lines matching `pattern` with the color named by `action`.
+    Highlight,
+    /// Color the background of whole lines matching `pattern` with the color named by `action`.
+    HighlightLine,
+    /// Replace the parts of lines matching `pattern` with `action`; `$1` etc. are replaced by capture groups.
+    Substitute,
+}
+
+impl RuleKind {
+    fn name(&self) -> &'static str {
+        match self {
+            RuleKind::Trigger => "trigger",
+            RuleKind::Sound => "sound",
+            RuleKind::Gag => "gag",
+            RuleKind::Highlight => "highlight",
+            RuleKind::HighlightLine => "highlight_line",
+            RuleKind::Substitute => "substitute",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<RuleKind> {
+        match name {
+            "trigger" => Ok(RuleKind::Trigger),
+            "sound" => Ok(RuleKind::Sound),
+            "gag" => Ok(RuleKind::Gag),
+            "highlight" => Ok(RuleKind::Highlight),
+            "highlight_line" => Ok(RuleKind::HighlightLine),
+            "substitute" => Ok(RuleKind::Substitute),
+            _ => bail!("Invalid rule kind: {name}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet { rules: vec![] }
+    }
+
+    /// Add a rule, replacing any existing rule of the same kind and pattern.
+    pub fn add(&mut self, rule: Rule) {
+        self.remove(rule.kind, rule.pattern.as_str());
+        self.rules.push(rule);
+    }
+
+    pub fn remove(&mut self, kind: RuleKind, pattern: &str) {
+        self.rules.retain(|rule| rule.kind != kind || rule.pattern.as_str() != pattern);
+    }
+
+    /// The rules, in the order they were added.
+    pub fn into_rules(self) -> Vec<Rule> {
+        self.rules
+    }
+
+    /// Actions of the rules of a given kind matching a line of output, e.g. commands to send for triggers.
+    pub fn matching_actions(&self, kind: RuleKind, line: &str) -> Vec<String> {
+        self.rules.iter()
+            .filter(|rule| rule.kind == kind)
+            .filter_map(|rule| {
+                rule.pattern.captures(line).map(|captures| {
+                    let mut action = String::new();
+                    captures.expand(&rule.action, &mut action);
+                    action
+                })
+            })
+            .collect()
+    }
+
+    /// Write all rules in the on-disk format:
+    /// a header line followed by one `kind<TAB>pattern<TAB>action` line per rule,
+    /// with backslashes, tabs and line breaks escaped as `\\`, `\t` and `\n`.
+    pub fn save(&self, writer: &mut impl Write) -> Result<()> {
+        writeln!(writer, "{RULES_HEADER}")
+            .context("Write rules header")?;
+
+        for rule in &self.rules {
+            writeln!(writer, "{}\t{}\t{}", rule.kind.name(), escape(rule.pattern.as_str()), escape(&rule.action))
+                .context("Write rule")?;
+        }
+
+        Ok(())
+    }
+
+    /// Read rules written by `save`, adding them to the set.
+    pub fn load(&mut self, reader: impl std::io::Read) -> Result<()> {
+        let mut lines = BufReader::new(reader).lines();
+
+        match lines.next() {
+            Some(header) if header.as_deref().ok() == Some(RULES_HEADER) => {},
+            _ => bail!("Not a rules file (expected \"{RULES_HEADER}\" header)"),
+        }
+
+        for (number, line) in lines.enumerate() {
+            let line = line.context("Read rule")?;
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let rule = parse_rule(&line)
+                .context(format!("Parse rule on line {}", number + 2))?;
+
+            self.add(rule);
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule> {
+    let fields: Vec<_> = line.split('\t').collect();
+
+    let [kind, pattern, action] = fields[..] else {
+        bail!("Expected 3 tab-separated fields, got {}", fields.len());
+    };
+
+    Ok(Rule {
+        kind: RuleKind::from_name(kind)?,
+        pattern: Regex::new(&unescape(pattern))
+            .context("Compile rule pattern")?,
+        action: unescape(action),
+    })
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('t') => result.push('\t'),
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+
+    result
+}
@@ -1,51 +1,258 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use regex::Regex;
-use tokio::sync::mpsc::{channel, Sender, Receiver};
+use tokio::sync::mpsc::{channel, unbounded_channel, Sender, Receiver, UnboundedSender};
 use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use anyhow::{Result, Context};
-use rhai::{Engine, EvalAltResult, Map};
+use ratatui::layout::Constraint;
+use ratatui::style::Color;
+use rhai::{Array, Blob, Engine, EvalAltResult, Map, FnPtr, AST, Dynamic, FLOAT};
 
 /* TODO
  * It's not clean that this type needs to be leaked from the tui module, but raw Map is not Send.
  */
-use crate::tui::LayoutElement;
+use crate::tui::{Emphasis, KeySpec, LayoutElement, NumpadDirections, Theme, create_constraint, parse_color};
+use crate::encoding::Encoding;
+use crate::msdp::MsdpValue;
+use crate::pipeline::{self, OutputLine, Stage, Verdict, STAGES};
+use crate::rules::*;
+use crate::telnet::Keepalive;
+use crate::tick::TickTimer;
 
 pub enum ScriptEngineRequest {
     Output(String),
-    ExecuteScriptFile(String),
+    /// The server marked the end of a prompt, so the partial line of output so far is complete.
+    EndOfPrompt,
+    /// Execute scripts one after another, each once the previous one has finished.
+    ExecuteScriptFiles(Vec<String>),
+    ExecuteConfigFile(String, oneshot::Sender<()>),
+    /// GMCP message from the server: package name and JSON body.
+    Gmcp(String, String),
+    /// MSDP variable from the server: name and value.
+    Msdp(String, MsdpValue),
+    /// MSSP server status: variable names and values.
+    Mssp(HashMap<String, String>),
+    /// A key bound with `bind()` was pressed.
+    KeyPressed(KeySpec),
+    /// Input submitted from an input pane with a target: the target and the text.
+    Input(String, String),
+    /// Load rules from the default rules file, which is also where `save_rules()` saves them.
+    LoadDefaultRules(String),
     Shutdown,
 }
 
 pub enum ScriptEngineEvent {
     Connect(String, u16),
+    ConnectTls(String, u16),
+    Disconnect,
+    /// Server output which went through the output pipeline, to be displayed.
+    Print(Vec<OutputLine>),
     Send(String),
+    /// Send without echoing the command in the output (unlike `SendSecret`, which echoes it masked).
+    SendSilent(String),
+    /// Send text without a newline, echoing it (marked as such) or not.
+    SendNoNewline(String, bool),
+    /// Bytes to write to the server as they are.
+    SendRaw(Vec<u8>),
+    SendTo(usize, String),
     SendSecret(String),
     SetLayout(LayoutElement),
+    RegisterLayout(String, LayoutElement),
+    UseLayout(String),
+    SetTheme(Theme),
+    SetCollapseDuplicates(usize, bool),
+    SetScrollLock(usize, bool),
+    SetMaxLineWidth(usize, Option<usize>),
+    SetTimestamps(usize, bool),
+    SetScrollback(usize, usize),
+    GetScrollInfo(usize, oneshot::Sender<Option<(usize, usize)>>),
+    ScrollTo(usize, usize),
+    SaveBuffer(usize, String),
+    SetPaneConstraint(usize, Constraint),
+    /// Set the text of a static pane, its lines separated by newlines.
+    SetPane(usize, String),
+    /// Print text (possibly with ANSI codes) to a pane, or the default one if there is no such pane.
+    Echo(usize, String),
+    SetClickSend(bool),
+    SetScrollFraction(f64),
+    AddGuard(Regex),
+    RemoveGuard(String),
+    ListGuards,
+    SetEmphasis(Option<Emphasis>),
+    SetSpeedwalk(bool),
+    SetPromptLine(bool),
+    SetStatusBar(bool),
+    SetSecretInput(bool),
+    SetNumpad(Option<NumpadDirections>),
+    /// Have presses of a key reported as `KeyPressed`, with a description of what it does (or not anymore).
+    SetKeyBinding(KeySpec, Option<String>),
+    DumpLayout,
+    SetQueueWhileDisconnected(bool),
+    SetAutoReconnect(bool),
+    /// Seconds of idleness before sending a keepalive (`None` disables it) and what to send.
+    SetKeepalive(Option<u64>, Keepalive),
+    SetEncoding(Encoding),
+    MsdpReport(String),
+    /// Append server output to a file, with or without ANSI codes.
+    StartLog(String, bool),
+    StopLog,
+    PlaySound(String),
     Error(anyhow::Error),
 }
 
 enum ScriptEvent {
-    Expect(String, oneshot::Sender<String>),
+    /// Wait for output matching a pattern, for at most the given time; `None` is sent back if it runs out.
+    Expect(String, Option<Duration>, oneshot::Sender<Option<String>>),
+    /// Give up on expectations whose time has run out.
+    ExpireExpects,
+    After(Duration, Callback),
+    /// Call a function every so often, until the timer with the given id is cleared.
+    SetTimer(usize, Duration, Callback),
+    ClearTimer(usize),
+    Call(Callback, Vec<Dynamic>),
+    AddRule(Rule),
+    RemoveRule(RuleKind, String),
+    SaveRules(Option<String>),
+    LoadRules(String),
+    SetSoundMuted(bool),
+    OnTick(Callback),
+    ExecuteScriptFile(String, oneshot::Sender<()>),
+    HighlightLine(Regex, Color),
+    RemoveHighlightLine(String),
+    Highlight(Regex, Color),
+    RemoveHighlight(String),
+    AddGag(Regex),
+    RemoveGag(String),
+    AddSubstitution(Regex, String),
+    RemoveSubstitution(String),
+    OnGmcp(String, Callback),
+    OnMsdp(String, Callback),
+    OnMssp(Callback),
+    Bind(KeySpec, Callback),
+    Unbind(KeySpec),
+    OnInput(String, Callback),
+    AddTrigger(Regex, Callback),
+    RemoveTrigger(String),
+}
+
+/// A script function to be called back later, e.g. when a timer fires.
+#[derive(Clone)]
+struct Callback {
+    /// Index of the script (in `ScriptEngine::scripts`) the function was defined in
+    script: usize,
+    func: FnPtr,
+}
+
+/// A compiled script, kept around for as long as its callbacks may be called.
+struct LoadedScript {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
 }
 
 struct ScriptEngine {
     ev_tx: Sender<ScriptEngineEvent>,
     i_tx: Sender<ScriptEvent>,
 
-    expects: Vec<(Regex, oneshot::Sender<String>)>,
+    /// Patterns waited for by scripts, with when to give up (if ever).
+    expects: Vec<(Regex, oneshot::Sender<Option<String>>, Option<Instant>)>,
+    scripts: Vec<LoadedScript>,
+
+    /// Output after the last newline, which goes through the pipeline once the line is complete.
+    partial_line: String,
+    /// When the partial line is taken to be a prompt if nothing follows it, for servers which send no GA or EOR.
+    partial_line_deadline: Option<tokio::time::Instant>,
+
+    rules: RuleSet,
+    rules_path: Option<String>,
+
+    sound_muted: bool,
+
+    /// Shared with scripts, so that they can ask for the time to the next tick directly.
+    tick: Arc<Mutex<TickTimer>>,
+    tick_callbacks: Vec<Callback>,
+
+    /// Background colors for whole lines of output matching a pattern.
+    line_highlights: Vec<(Regex, Color)>,
+    /// Text colors for parts of output lines matching a pattern.
+    highlights: Vec<(Regex, Color)>,
+    /// Patterns of output lines not to display.
+    gags: Vec<Regex>,
+    /// Replacements for text of output lines matching a pattern, applied in order.
+    substitutions: Vec<(Regex, String)>,
+
+    /// Functions called for every line of output matching a pattern, with the line and its captures.
+    triggers: Vec<(Regex, Callback)>,
+
+    /// Callbacks for GMCP messages, by package name.
+    gmcp_callbacks: HashMap<String, Vec<Callback>>,
+    /// Callbacks for MSDP variables, by variable name.
+    msdp_callbacks: HashMap<String, Vec<Callback>>,
+    mssp_callbacks: Vec<Callback>,
+
+    /// Functions called when a key is pressed.
+    key_bindings: HashMap<KeySpec, Callback>,
+    /// Callbacks for input submitted from input panes with a target, by target.
+    input_callbacks: HashMap<String, Vec<Callback>>,
+
+    /// Tasks of repeating timers, by id.
+    timers: HashMap<usize, JoinHandle<()>>,
+    /// Shared with scripts, so that `set_timer` can return the id right away.
+    next_timer_id: Arc<AtomicUsize>,
+
+    /// Whether commands sent by scripts and triggers are echoed in the output.
+    echo: Arc<AtomicBool>,
+
+    script_args: Vec<(String, String)>,
 }
 
 type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
 
-pub fn create_script_engine() -> Result<(Sender<ScriptEngineRequest>, Receiver<ScriptEngineEvent>)> {
-    let (req_tx, mut req_rx) = channel(256);
+thread_local! {
+    /// Where a trigger callback running on this thread reports whether its line is to be displayed, until it has.
+    static LINE_VERDICT: RefCell<Option<oneshot::Sender<Verdict>>> = const { RefCell::new(None) };
+}
+
+/// How long a partial line of output waits for the rest of it before it is processed as a prompt.
+const PARTIAL_LINE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Create the script engine; `script_args` are made available to all scripts by `args()` and `arg(name)`.
+pub fn create_script_engine(script_args: Vec<(String, String)>) -> Result<(UnboundedSender<ScriptEngineRequest>, Receiver<ScriptEngineEvent>)> {
+    /* Unbounded, so that the app never waits on the engine while the engine may be waiting on the app to take its events */
+    let (req_tx, mut req_rx) = unbounded_channel();
     let (ev_tx, ev_rx) = channel(256);
     let (i_tx, mut i_rx) = channel(256);
 
     tokio::spawn(async move {
         let mut engine = ScriptEngine {
             expects: vec![],
+            scripts: vec![],
+            partial_line: String::new(),
+            partial_line_deadline: None,
+            rules: RuleSet::new(),
+            rules_path: None,
+            sound_muted: false,
+            tick: Arc::new(Mutex::new(TickTimer::new())),
+            tick_callbacks: vec![],
+            line_highlights: vec![],
+            highlights: vec![],
+            gags: vec![],
+            substitutions: vec![],
+            triggers: vec![],
+            gmcp_callbacks: HashMap::new(),
+            msdp_callbacks: HashMap::new(),
+            mssp_callbacks: vec![],
+            key_bindings: HashMap::new(),
+            input_callbacks: HashMap::new(),
+            timers: HashMap::new(),
+            next_timer_id: Arc::new(AtomicUsize::new(1)),
+            echo: Arc::new(AtomicBool::new(true)),
+            script_args,
             ev_tx,
             i_tx,
         };
@@ -69,6 +276,13 @@ pub fn create_script_engine() -> Result<(Sender<ScriptEngineRequest>, Receiver<S
                             .context("Notify of script error")?;
                     }
                 },
+
+                _ = sleep_until_deadline(engine.partial_line_deadline), if engine.partial_line_deadline.is_some() => {
+                    if let Err(err) = engine.flush_partial_line().await {
+                        engine.ev_tx.send(ScriptEngineEvent::Error(err)).await
+                            .context("Notify of partial line error")?;
+                    }
+                },
             }
         }
 
@@ -79,31 +293,154 @@ pub fn create_script_engine() -> Result<(Sender<ScriptEngineRequest>, Receiver<S
 }
 
 impl ScriptEngine {
+    /// Run lines of output through the pipeline and send those left to be displayed.
+    async fn print_lines(&mut self, data: &str) -> Result<()> {
+        let mut lines = vec![];
+
+        for line in data.lines() {
+            if let Some(line) = self.process_line(OutputLine::new(line)).await? {
+                lines.push(line);
+            }
+        }
+
+        self.ev_tx.send(ScriptEngineEvent::Print(lines)).await
+            .context("Send processed output")
+    }
+
+    /// Process the partial line of output as it is, e.g. when it is a prompt.
+    async fn flush_partial_line(&mut self) -> Result<()> {
+        let prompt = std::mem::take(&mut self.partial_line);
+        self.partial_line_deadline = None;
+
+        self.print_lines(&prompt).await
+            .context("Print prompt")
+    }
+
     async fn handle_request(&mut self, request: ScriptEngineRequest) -> Result<bool> {
         match request {
             ScriptEngineRequest::Output(data) => {
-                let matches: Vec<_> = self.expects.iter()
-                    .enumerate()
-                    .filter(|(_, (pattern, _))| pattern.is_match(&data))
-                    .map(|(idx, _)| idx)
+                let (matches, rest) = std::mem::take(&mut self.expects).into_iter()
+                    .partition::<Vec<_>, _>(|(pattern, _, _)| pattern.is_match(&data));
+
+                self.expects = rest;
+
+                for (_, tx, _) in matches {
+                    /* The script may have given up waiting */
+                    let _ = tx.send(Some(data.clone()));
+                }
+
+                /* Output arrives in chunks which may end mid-line, so only complete lines are processed */
+                self.partial_line.push_str(&data);
+
+                let complete = match self.partial_line.rfind('\n') {
+                    Some(end) => {
+                        let rest = self.partial_line.split_off(end + 1);
+                        std::mem::replace(&mut self.partial_line, rest)
+                    },
+                    None => String::new(),
+                };
+
+                self.print_lines(&complete).await
+                    .context("Print complete lines")?;
+
+                self.partial_line_deadline = (!self.partial_line.is_empty())
+                    .then(|| tokio::time::Instant::now() + PARTIAL_LINE_TIMEOUT);
+            },
+            ScriptEngineRequest::EndOfPrompt => {
+                self.flush_partial_line().await?;
+            },
+            ScriptEngineRequest::Gmcp(package, data) => {
+                let callbacks: Vec<Callback> = self.gmcp_callbacks.iter()
+                    .filter(|(registered, _)| gmcp_package_matches(registered, &package))
+                    .flat_map(|(_, callbacks)| callbacks.iter().cloned())
+                    .collect();
+
+                if !callbacks.is_empty() {
+                    let body = parse_gmcp_body(&data)
+                        .context(format!("Parse GMCP {package} message"))?;
+
+                    for callback in callbacks {
+                        self.call(callback, vec![package.clone().into(), body.clone().into()])
+                            .context("Call GMCP callback")?;
+                    }
+                }
+            },
+            ScriptEngineRequest::Msdp(variable, value) => {
+                if let Some(callbacks) = self.msdp_callbacks.get(&variable).cloned() {
+                    let value = msdp_to_dynamic(value);
+
+                    for callback in callbacks {
+                        self.call(callback, vec![variable.clone().into(), value.clone()])
+                            .context("Call MSDP callback")?;
+                    }
+                }
+            },
+            ScriptEngineRequest::Mssp(status) => {
+                let status: Map = status.into_iter()
+                    .map(|(name, value)| (name.into(), value.into()))
                     .collect();
 
-                for idx in matches {
-                    let (_, tx) = self.expects.remove(idx);
+                for callback in self.mssp_callbacks.clone() {
+                    self.call(callback, vec![status.clone().into()])
+                        .context("Call MSSP callback")?;
+                }
+            },
+            ScriptEngineRequest::KeyPressed(key) => {
+                if let Some(callback) = self.key_bindings.get(&key).cloned() {
+                    self.call(callback, vec![])
+                        .context("Call key binding")?;
+                }
+            },
+            ScriptEngineRequest::Input(target, text) => {
+                let Some(callbacks) = self.input_callbacks.get(&target).cloned() else {
+                    anyhow::bail!("Nothing handles input for {target:?} (use on_input() in a script)");
+                };
+
+                for callback in callbacks {
+                    self.call(callback, vec![text.clone().into()])
+                        .context("Call input callback")?;
+                }
+            },
+            ScriptEngineRequest::ExecuteScriptFiles(paths) => {
+                let i_tx = self.i_tx.clone();
+
+                tokio::spawn(async move {
+                    for path in paths {
+                        let (done_tx, done_rx) = oneshot::channel();
+
+                        i_tx.send(ScriptEvent::ExecuteScriptFile(path, done_tx)).await
+                            .context("Emit execute script event")?;
+
+                        /* Errors are reported by the script engine; dropping the sender also ends the wait */
+                        let _ = done_rx.await;
+                    }
+
+                    anyhow::Ok(())
+                });
+            },
+            ScriptEngineRequest::ExecuteConfigFile(path, done) => {
+                let script = std::fs::read_to_string(&path)
+                    .context(format!("Read config file {path}"))?;
 
-                    tx.send(data.clone())
-                        .map_err(|err| anyhow::format_err!("{err}"))
-                        .context("Send expect data back to script")?;
+                /* Use the config's path as source so that `import` resolves relative to it */
+                self.execute_script(script, Some(path), Some(done))
+                    .context("Execute config")?;
+            },
+            ScriptEngineRequest::LoadDefaultRules(path) => {
+                if std::path::Path::new(&path).is_file() {
+                    self.load_rules(&path)
+                        .context("Load default rules")?;
                 }
+
+                self.rules_path = Some(path);
             },
-            ScriptEngineRequest::ExecuteScriptFile(path) => {
-                let script = std::fs::read_to_string(path)
-                    .context("Read script file")?;
+            ScriptEngineRequest::Shutdown => {
+                for (_, timer) in self.timers.drain() {
+                    timer.abort();
+                }
 
-                self.execute_script(script)
-                    .context("Execute script")?;
+                return Ok(true)
             },
-            ScriptEngineRequest::Shutdown => { return Ok(true) },
         }
 
         Ok(false)
@@ -111,79 +448,349 @@ impl ScriptEngine {
 
     fn handle_script_event(&mut self, event: ScriptEvent) -> Result<()> {
         match event {
-            ScriptEvent::Expect(pattern, tx) => {
+            ScriptEvent::Expect(pattern, timeout, tx) => {
                 let pattern = Regex::new(&pattern)
                     .context("Compile pattern expression")?;
-                self.expects.push((pattern, tx));
-            }
+
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+                self.expects.push((pattern, tx, deadline));
+
+                if let Some(timeout) = timeout {
+                    let i_tx = self.i_tx.clone();
+
+                    tokio::spawn(async move {
+                        tokio::time::sleep(timeout).await;
+
+                        i_tx.send(ScriptEvent::ExpireExpects).await
+                            .context("Emit expire expects event")
+                    });
+                }
+            },
+            ScriptEvent::ExpireExpects => {
+                let now = Instant::now();
+
+                let (expired, rest) = std::mem::take(&mut self.expects).into_iter()
+                    .partition::<Vec<_>, _>(|(_, _, deadline)| deadline.is_some_and(|deadline| deadline <= now));
+
+                self.expects = rest;
+
+                for (_, tx, _) in expired {
+                    /* The script may be gone by now, in which case there is no one to tell */
+                    let _ = tx.send(None);
+                }
+            },
+            ScriptEvent::After(delay, callback) => {
+                let i_tx = self.i_tx.clone();
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+
+                    i_tx.send(ScriptEvent::Call(callback, vec![])).await
+                        .context("Emit delayed call event")
+                });
+            },
+            ScriptEvent::SetTimer(id, period, callback) => {
+                let i_tx = self.i_tx.clone();
+
+                let timer = tokio::spawn(async move {
+                    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+                    /* Errors in the callback are reported by `call` and do not stop the timer */
+                    loop {
+                        interval.tick().await;
+
+                        if i_tx.send(ScriptEvent::Call(callback.clone(), vec![])).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                self.timers.insert(id, timer);
+            },
+            ScriptEvent::ClearTimer(id) => {
+                if let Some(timer) = self.timers.remove(&id) {
+                    timer.abort();
+                }
+            },
+            ScriptEvent::Call(callback, args) => {
+                self.call(callback, args)
+                    .context("Call script function")?;
+            },
+            ScriptEvent::AddRule(rule) => {
+                self.rules.add(rule);
+            },
+            ScriptEvent::RemoveRule(kind, pattern) => {
+                self.rules.remove(kind, &pattern);
+            },
+            ScriptEvent::SaveRules(path) => {
+                let path = path.or_else(|| self.rules_path.clone())
+                    .context("No rules file given")?;
+
+                let mut file = std::fs::File::create(&path)
+                    .context(format!("Create rules file {path}"))?;
+
+                self.saved_rules().save(&mut file)
+                    .context("Save rules")?;
+            },
+            ScriptEvent::LoadRules(path) => {
+                self.load_rules(&path)
+                    .context("Load rules")?;
+            },
+            ScriptEvent::SetSoundMuted(muted) => {
+                self.sound_muted = muted;
+            },
+            ScriptEvent::OnTick(callback) => {
+                self.tick_callbacks.push(callback);
+            },
+            ScriptEvent::HighlightLine(pattern, color) => {
+                self.line_highlights.retain(|(existing, _)| existing.as_str() != pattern.as_str());
+                self.line_highlights.push((pattern, color));
+            },
+            ScriptEvent::RemoveHighlightLine(pattern) => {
+                self.line_highlights.retain(|(existing, _)| existing.as_str() != pattern);
+            },
+            ScriptEvent::Highlight(pattern, color) => {
+                /* Re-registering a pattern changes its color but keeps its place in the order */
+                match self.highlights.iter_mut().find(|(existing, _)| existing.as_str() == pattern.as_str()) {
+                    Some(highlight) => highlight.1 = color,
+                    None => self.highlights.push((pattern, color)),
+                }
+            },
+            ScriptEvent::RemoveHighlight(pattern) => {
+                self.highlights.retain(|(existing, _)| existing.as_str() != pattern);
+            },
+            ScriptEvent::AddGag(pattern) => {
+                if !self.gags.iter().any(|existing| existing.as_str() == pattern.as_str()) {
+                    self.gags.push(pattern);
+                }
+            },
+            ScriptEvent::RemoveGag(pattern) => {
+                self.gags.retain(|existing| existing.as_str() != pattern);
+            },
+            ScriptEvent::AddSubstitution(pattern, replacement) => {
+                /* Substituting the same pattern again changes its replacement, keeping its place in the order */
+                match self.substitutions.iter_mut().find(|(existing, _)| existing.as_str() == pattern.as_str()) {
+                    Some((_, existing)) => *existing = replacement,
+                    None => self.substitutions.push((pattern, replacement)),
+                }
+            },
+            ScriptEvent::RemoveSubstitution(pattern) => {
+                self.substitutions.retain(|(existing, _)| existing.as_str() != pattern);
+            },
+            ScriptEvent::AddTrigger(pattern, callback) => {
+                self.triggers.push((pattern, callback));
+            },
+            ScriptEvent::RemoveTrigger(pattern) => {
+                self.triggers.retain(|(existing, _)| existing.as_str() != pattern);
+            },
+            ScriptEvent::OnGmcp(package, callback) => {
+                self.gmcp_callbacks.entry(package).or_default().push(callback);
+            },
+            ScriptEvent::OnMsdp(variable, callback) => {
+                self.msdp_callbacks.entry(variable).or_default().push(callback);
+            },
+            ScriptEvent::OnMssp(callback) => {
+                self.mssp_callbacks.push(callback);
+            },
+            ScriptEvent::Bind(key, callback) => {
+                self.key_bindings.insert(key, callback);
+            },
+            ScriptEvent::Unbind(key) => {
+                self.key_bindings.remove(&key);
+            },
+            ScriptEvent::OnInput(target, callback) => {
+                self.input_callbacks.entry(target).or_default().push(callback);
+            },
+            ScriptEvent::ExecuteScriptFile(path, done) => {
+                let script = std::fs::read_to_string(&path)
+                    .context(format!("Read script file {path}"))?;
+
+                self.execute_script(script, Some(path.clone()), Some(done))
+                    .context(format!("Execute script {path}"))?;
+            },
+        }
+
+        Ok(())
+    }
+
+    fn load_rules(&mut self, path: &str) -> Result<()> {
+        let file = std::fs::File::open(path)
+            .context(format!("Open rules file {path}"))?;
+
+        let mut loaded = RuleSet::new();
+        loaded.load(file)?;
+
+        /* Gags, highlights and substitutions live in the pipeline stages, so they are set up as if added by a script */
+        for rule in loaded.into_rules() {
+            let event = match rule.kind {
+                RuleKind::Trigger | RuleKind::Sound => ScriptEvent::AddRule(rule),
+                RuleKind::Gag => ScriptEvent::AddGag(rule.pattern),
+                RuleKind::Highlight => ScriptEvent::Highlight(rule.pattern, parse_color(&rule.action)?),
+                RuleKind::HighlightLine => ScriptEvent::HighlightLine(rule.pattern, parse_color(&rule.action)?),
+                RuleKind::Substitute => ScriptEvent::AddSubstitution(rule.pattern, rule.action),
+            };
+
+            self.handle_script_event(event)
+                .context("Add loaded rule")?;
         }
 
         Ok(())
     }
 
-    fn execute_script(&mut self, script: String) -> Result<()> {
+    /// All rules which can be saved: those kept as rules, as well as gags, highlights and substitutions.
+    fn saved_rules(&self) -> RuleSet {
+        let mut rules = self.rules.clone();
+
+        let stages = self.gags.iter()
+            .map(|pattern| (RuleKind::Gag, pattern, String::new()))
+            .chain(self.highlights.iter().map(|(pattern, color)| (RuleKind::Highlight, pattern, color.to_string())))
+            .chain(self.line_highlights.iter().map(|(pattern, color)| (RuleKind::HighlightLine, pattern, color.to_string())))
+            .chain(self.substitutions.iter().map(|(pattern, replacement)| (RuleKind::Substitute, pattern, replacement.clone())));
+
+        for (kind, pattern, action) in stages {
+            rules.add(Rule { kind, pattern: pattern.clone(), action });
+        }
+
+        rules
+    }
+
+    /// Run a line of output through the stages of the output pipeline; `None` if a stage dropped it.
+    async fn process_line(&mut self, mut line: OutputLine) -> Result<Option<OutputLine>> {
+        for stage in STAGES {
+            let verdict = match stage {
+                Stage::Substitute => pipeline::substitute(&mut line, &self.substitutions),
+                Stage::Gag => pipeline::gag(&line, &self.gags),
+                Stage::Highlight => pipeline::highlight(&mut line, &self.line_highlights, &self.highlights),
+                Stage::Trigger => self.notify_triggers(&line.plain()).await?,
+            };
+
+            if verdict == Verdict::Drop {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(line))
+    }
+
+    /// Trigger stage of the output pipeline; the line is dropped if a trigger callback calls `gag()`.
+    async fn notify_triggers(&mut self, line: &str) -> Result<Verdict> {
+        for action in self.rules.matching_actions(RuleKind::Trigger, line) {
+            self.ev_tx.send(send_event(action, &self.echo)).await
+                .context("Send triggered command")?;
+        }
+
+        let triggered: Vec<_> = self.triggers.iter()
+            .filter_map(|(pattern, callback)| pattern.captures(line).map(|captures| {
+                let captures: Array = captures.iter()
+                    .map(|group| group.map_or(Dynamic::UNIT, |group| group.as_str().into()))
+                    .collect();
+
+                (callback.clone(), captures)
+            }))
+            .collect();
+
+        let mut verdicts = vec![];
+
+        for (callback, captures) in triggered {
+            let (tx, rx) = oneshot::channel();
+
+            self.spawn_call(callback, vec![line.into(), captures.into()], Some(tx))
+                .context("Call trigger callback")?;
+
+            verdicts.push(rx);
+        }
+
+        let ticked = self.tick.lock()
+            .map_err(|_| anyhow::anyhow!("Tick timer poisoned"))?
+            .observe(line, Instant::now());
+
+        if ticked {
+            for callback in self.tick_callbacks.clone() {
+                self.call(callback, vec![])
+                    .context("Call tick callback")?;
+            }
+        }
+
+        if !self.sound_muted {
+            for sound in self.rules.matching_actions(RuleKind::Sound, line) {
+                self.ev_tx.send(ScriptEngineEvent::PlaySound(sound)).await
+                    .context("Play triggered sound")?;
+            }
+        }
+
+        /* Callbacks decide by returning, calling `gag()` or starting to wait (a callback which failed keeps the line) */
+        let mut verdict = Verdict::Keep;
+
+        for rx in verdicts {
+            if rx.await == Ok(Verdict::Drop) {
+                verdict = Verdict::Drop;
+            }
+        }
+
+        Ok(verdict)
+    }
+
+    /// Call a script function on a blocking task of its own, so that it can run while the script
+    /// that defined it (or other callbacks) are blocked, e.g. in `expect` or `sleep`.
+    fn call(&self, callback: Callback, args: Vec<Dynamic>) -> Result<()> {
+        self.spawn_call(callback, args, None)
+    }
+
+    /// Call a script function like `call`, giving it a line to decide the fate of with `gag()` if it is a trigger callback.
+    fn spawn_call(&self, callback: Callback, args: Vec<Dynamic>, verdict: Option<oneshot::Sender<Verdict>>) -> Result<()> {
+        let script = self.scripts.get(callback.script)
+            .context("Find script of callback")?;
+
+        let engine = script.engine.clone();
+        let ast = script.ast.clone();
         let ev_tx = self.ev_tx.clone();
-        let i_tx = self.i_tx.clone();
 
         tokio::task::spawn_blocking(move || -> Result<()> {
-            let mut engine = Engine::new();
-
-            let ev_tx_cl = ev_tx.clone();
-            engine.register_fn("connect", move |address: String, port: i64| -> ScriptResult<()> {
-                ev_tx_cl.blocking_send(ScriptEngineEvent::Connect(address, port as u16))
-                    .context("Emit connection request")
-                    .into_script_result()
-            });
-
-            let i_tx_cl = i_tx.clone();
-            engine.register_fn("expect", move |expect: String| -> ScriptResult<String> {
-                let (tx, rx) = oneshot::channel();
-
-                i_tx_cl.blocking_send(ScriptEvent::Expect(expect, tx))
-                    .context("Emit expect event")
-                    .into_script_result()?;
-
-                rx.blocking_recv()
-                    .context("Wait for expectation to be satisfied")
-                    .into_script_result()
-            });
-
-            let ev_tx_cl = ev_tx.clone();
-            engine.register_fn("send", move |text: String| -> ScriptResult<()> {
-                ev_tx_cl.blocking_send(ScriptEngineEvent::Send(text))
-                    .context("Emit send event")
-                    .into_script_result()
-            });
-
-            let ev_tx_cl = ev_tx.clone();
-            engine.register_fn("send_secret", move |text: String| -> ScriptResult<()> {
-                ev_tx_cl.blocking_send(ScriptEngineEvent::SendSecret(text))
-                    .context("Emit send secret event")
-                    .into_script_result()
-            });
-
-            let ev_tx_cl = ev_tx.clone();
-            engine.register_fn("set_layout", move |layout: Map| -> ScriptResult<()> {
-                let mut layout = LayoutElement::from(layout)
-                    .context("Parse layout data")
-                    .into_script_result()?;
-
-                if layout.input().is_none() {
-                    return Err("Layout must include an input".into());
-                }
+            LINE_VERDICT.with(|pending| *pending.borrow_mut() = verdict);
 
-                if layout.pane(1).is_none() { // TODO: it must be a scroll pane
-                    return Err("Layout must include default pane (id = 1)".into());
-                }
+            let result = callback.func.call::<Dynamic>(&engine, &ast, args);
+            decide_line(Verdict::Keep);
+
+            if let Err(err) = result {
+                ev_tx.blocking_send(ScriptEngineEvent::Error(
+                    anyhow::format_err!("{err}").context(format!("Call {}", callback.func.fn_name()))))?;
+            }
+
+            Ok(())
+        });
+
+        Ok(())
+    }
+
+    fn execute_script(&mut self, script: String, source: Option<String>, done: Option<oneshot::Sender<()>>) -> Result<()> {
+        let engine = self.create_engine(self.scripts.len());
+
+        let mut ast = engine.compile(&script)
+            .context("Compile script")?;
+
+        let name = source.clone().unwrap_or_else(|| "script".into());
+
+        if let Some(source) = source {
+            ast.set_source(source);
+        }
+
+        let engine = Arc::new(engine);
+        let ast = Arc::new(ast);
+
+        self.scripts.push(LoadedScript { engine: engine.clone(), ast: ast.clone() });
 
-                ev_tx_cl.blocking_send(ScriptEngineEvent::SetLayout(layout))
-                    .context("Emit set layout event")
-                    .into_script_result()
-            });
+        let ev_tx = self.ev_tx.clone();
+
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let result = engine.run_ast(&ast);
+
+            if let Some(done) = done {
+                let _ = done.send(());
+            }
 
-            if let Err(err) = engine.run(&script) {
+            if let Err(err) = result {
                 ev_tx.blocking_send(ScriptEngineEvent::Error(
-                    anyhow::format_err!("{err}").context("Run script engine")))?;
+                    anyhow::format_err!("{err}").context(format!("Run {name}"))))?;
             }
 
             Ok(())
@@ -191,6 +798,1117 @@ impl ScriptEngine {
 
         Ok(())
     }
+
+    /// Create a script engine with all the host functions registered.
+    fn create_engine(&self, script_id: usize) -> Engine {
+        let ev_tx = self.ev_tx.clone();
+        let i_tx = self.i_tx.clone();
+
+        let mut engine = Engine::new();
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("connect", move |address: String, port: i64| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::Connect(address, port as u16))
+                .context("Emit connection request")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("connect_tls", move |address: String, port: i64| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::ConnectTls(address, port as u16))
+                .context("Emit TLS connection request")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("expect", move |expect: String| -> ScriptResult<String> {
+            /* The output expected cannot come while the current line waits for this callback */
+            decide_line(Verdict::Keep);
+
+            let (tx, rx) = oneshot::channel();
+
+            i_tx_cl.blocking_send(ScriptEvent::Expect(expect, None, tx))
+                .context("Emit expect event")
+                .into_script_result()?;
+
+            rx.blocking_recv()
+                .map(Option::unwrap_or_default)
+                .context("Wait for expectation to be satisfied")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("expect_timeout", move |expect: String, seconds: FLOAT| -> ScriptResult<Dynamic> {
+            expect_timeout(&i_tx_cl, expect, duration_from_secs(seconds)?)
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("expect_timeout", move |expect: String, seconds: i64| -> ScriptResult<Dynamic> {
+            expect_timeout(&i_tx_cl, expect, duration_from_int_secs(seconds)?)
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        let echo = self.echo.clone();
+        engine.register_fn("send", move |text: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(send_event(text, &echo))
+                .context("Emit send event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("send_silent", move |text: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SendSilent(text))
+                .context("Emit send silent event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        let echo = self.echo.clone();
+        engine.register_fn("send_raw_text", move |text: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SendNoNewline(text, echo.load(Ordering::Relaxed)))
+                .context("Emit send without newline event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("send_raw", move |data: Blob| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SendRaw(data))
+                .context("Emit send raw event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("send_raw", move |hex: &str| -> ScriptResult<()> {
+            let data = parse_hex(hex)
+                .context("Parse hex bytes")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SendRaw(data))
+                .context("Emit send raw event")
+                .into_script_result()
+        });
+
+        let echo = self.echo.clone();
+        engine.register_fn("set_command_echo", move |enabled: bool| {
+            echo.store(enabled, Ordering::Relaxed);
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("send_to", move |session: i64, text: String| -> ScriptResult<()> {
+            let session = usize::try_from(session)
+                .context("Parse session id")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SendTo(session, text))
+                .context("Emit send to session event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("send_secret", move |text: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SendSecret(text))
+                .context("Emit send secret event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_layout", move |layout: Map| -> ScriptResult<()> {
+            let layout = parse_layout(layout)?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetLayout(layout))
+                .context("Emit set layout event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("register_layout", move |name: String, layout: Map| -> ScriptResult<()> {
+            let layout = parse_layout(layout)?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::RegisterLayout(name, layout))
+                .context("Emit register layout event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("use_layout", move |name: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::UseLayout(name))
+                .context("Emit use layout event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_theme", move |name: &str| -> ScriptResult<()> {
+            let theme = Theme::named(name)
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetTheme(theme))
+                .context("Emit set theme event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_theme", move |theme: Map| -> ScriptResult<()> {
+            let theme = Theme::from(theme)
+                .context("Parse theme data")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetTheme(theme))
+                .context("Emit set theme event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_collapse_duplicates", move |pane_id: i64, enabled: bool| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetCollapseDuplicates(pane_id, enabled))
+                .context("Emit set collapse duplicates event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_scroll_lock", move |pane_id: i64, locked: bool| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetScrollLock(pane_id, locked))
+                .context("Emit set scroll lock event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("scroll_info", move |pane_id: i64| -> ScriptResult<Map> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            let (tx, rx) = oneshot::channel();
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::GetScrollInfo(pane_id, tx))
+                .context("Emit get scroll info event")
+                .into_script_result()?;
+
+            let (offset, size) = rx.blocking_recv()
+                .context("Wait for scroll info")
+                .into_script_result()?
+                .ok_or(format!("No pane with id = {pane_id}"))?;
+
+            let mut info = Map::new();
+            info.insert("offset".into(), (offset as i64).into());
+            info.insert("size".into(), (size as i64).into());
+
+            Ok(info)
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("scroll_to", move |pane_id: i64, offset: i64| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            let offset = usize::try_from(offset)
+                .context("Parse scroll offset")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::ScrollTo(pane_id, offset))
+                .context("Emit scroll to event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("scroll_bottom", move |pane_id: i64| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::ScrollTo(pane_id, 0))
+                .context("Emit scroll to event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_click_send", move |enabled: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetClickSend(enabled))
+                .context("Emit set click send event")
+                .into_script_result()
+        });
+
+        /* 0 = unlimited */
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_max_line_width", move |pane_id: i64, max_width: i64| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            if max_width < 0 {
+                return Err(format!("Max line width must not be negative (0 for unlimited), got {max_width}").into());
+            }
+
+            /* 0 means no limit */
+            let max_width = if max_width > 0 { Some(max_width as usize) } else { None };
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetMaxLineWidth(pane_id, max_width))
+                .context("Emit set max line width event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("echo", move |pane_id: i64, text: String| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::Echo(pane_id, text))
+                .context("Emit echo event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("resize_pane", move |pane_id: i64, constraint: Array| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            let constraint = create_constraint(&constraint.into())
+                .context("Parse constraint")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetPaneConstraint(pane_id, constraint))
+                .context("Emit set pane constraint event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_pane", move |pane_id: i64, lines: Array| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            let text = lines.iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetPane(pane_id, text))
+                .context("Emit set pane event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("save_buffer", move |pane_id: i64, path: String| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SaveBuffer(pane_id, path))
+                .context("Emit save buffer event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_timestamps", move |pane_id: i64, enabled: bool| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetTimestamps(pane_id, enabled))
+                .context("Emit set timestamps event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_scrollback", move |pane_id: i64, lines: i64| -> ScriptResult<()> {
+            let pane_id = usize::try_from(pane_id)
+                .context("Parse pane id")
+                .into_script_result()?;
+
+            if lines < 1 {
+                return Err(format!("Scrollback must be at least 1 line, got {lines}").into());
+            }
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetScrollback(pane_id, lines as usize))
+                .context("Emit set scrollback event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_scroll_fraction", move |fraction: f64| -> ScriptResult<()> {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                return Err(format!("Scroll fraction must be above 0 and at most 1, got {fraction}").into());
+            }
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetScrollFraction(fraction))
+                .context("Emit set scroll fraction event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("disconnect", move || -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::Disconnect)
+                .context("Emit disconnect event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_keepalive", move |seconds: i64| -> ScriptResult<()> {
+            let seconds = u64::try_from(seconds)
+                .map_err(|_| format!("Keepalive interval must not be negative, got {seconds}"))?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetKeepalive(Some(seconds), Keepalive::Nop))
+                .context("Emit set keepalive event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_keepalive", move |seconds: i64, kind: &str| -> ScriptResult<()> {
+            let seconds = u64::try_from(seconds)
+                .map_err(|_| format!("Keepalive interval must not be negative, got {seconds}"))?;
+
+            let keepalive = match kind {
+                "nop" => Keepalive::Nop,
+                "newline" => Keepalive::BlankLine,
+                _ => { return Err(format!("Unknown keepalive {kind:?} (expected nop or newline)").into()); },
+            };
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetKeepalive(Some(seconds), keepalive))
+                .context("Emit set keepalive event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_keepalive", move |_: ()| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetKeepalive(None, Keepalive::Nop))
+                .context("Emit set keepalive event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_auto_reconnect", move |enabled: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetAutoReconnect(enabled))
+                .context("Emit set auto reconnect event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("start_log", move |path: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::StartLog(path, false))
+                .context("Emit start log event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("start_log", move |path: String, keep_ansi: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::StartLog(path, keep_ansi))
+                .context("Emit start log event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("stop_log", move || -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::StopLog)
+                .context("Emit stop log event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_encoding", move |name: &str| -> ScriptResult<()> {
+            let Some(encoding) = Encoding::from_name(name) else {
+                return Err(format!("Unknown encoding {name:?} (expected utf-8, latin1 or cp437)").into());
+            };
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetEncoding(encoding))
+                .context("Emit set encoding event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_queue_while_disconnected", move |enabled: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetQueueWhileDisconnected(enabled))
+                .context("Emit set queue while disconnected event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("guard", move |pattern: &str| -> ScriptResult<()> {
+            let guard = Regex::new(pattern)
+                .context("Compile guard pattern")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::AddGuard(guard))
+                .context("Emit add guard event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("remove_guard", move |pattern: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::RemoveGuard(pattern))
+                .context("Emit remove guard event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("list_guards", move || -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::ListGuards)
+                .context("Emit list guards event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("highlight_line", move |pattern: &str, color: &str| -> ScriptResult<()> {
+            let pattern = Regex::new(pattern)
+                .context("Compile highlight pattern")
+                .into_script_result()?;
+
+            let color = parse_color(color)
+                .into_script_result()?;
+
+            i_tx_cl.blocking_send(ScriptEvent::HighlightLine(pattern, color))
+                .context("Emit highlight line event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("remove_highlight_line", move |pattern: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::RemoveHighlightLine(pattern))
+                .context("Emit remove highlight line event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("highlight", move |pattern: &str, color: &str| -> ScriptResult<()> {
+            let pattern = Regex::new(pattern)
+                .context("Compile highlight pattern")
+                .into_script_result()?;
+
+            let color = parse_color(color)
+                .into_script_result()?;
+
+            i_tx_cl.blocking_send(ScriptEvent::Highlight(pattern, color))
+                .context("Emit highlight event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("remove_highlight", move |pattern: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::RemoveHighlight(pattern))
+                .context("Emit remove highlight event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("gag", move |pattern: &str| -> ScriptResult<()> {
+            let pattern = Regex::new(pattern)
+                .context("Compile gag pattern")
+                .into_script_result()?;
+
+            i_tx_cl.blocking_send(ScriptEvent::AddGag(pattern))
+                .context("Emit gag event")
+                .into_script_result()
+        });
+
+        engine.register_fn("gag", || -> ScriptResult<()> {
+            if decide_line(Verdict::Drop) {
+                Ok(())
+            } else {
+                Err("gag() without a pattern can only be called by a trigger callback, before it waits".into())
+            }
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("remove_gag", move |pattern: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::RemoveGag(pattern))
+                .context("Emit remove gag event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("substitute", move |pattern: &str, replacement: String| -> ScriptResult<()> {
+            let pattern = Regex::new(pattern)
+                .context("Compile substitute pattern")
+                .into_script_result()?;
+
+            i_tx_cl.blocking_send(ScriptEvent::AddSubstitution(pattern, replacement))
+                .context("Emit substitute event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("remove_substitute", move |pattern: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::RemoveSubstitution(pattern))
+                .context("Emit remove substitute event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_emphasis", move |enabled: bool| -> ScriptResult<()> {
+            let emphasis = if enabled { Some(Emphasis::default()) } else { None };
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetEmphasis(emphasis))
+                .context("Emit set emphasis event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_emphasis", move |markers: Map| -> ScriptResult<()> {
+            let emphasis = Emphasis::from(markers)
+                .context("Parse emphasis markers")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetEmphasis(Some(emphasis)))
+                .context("Emit set emphasis event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_speedwalk", move |enabled: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetSpeedwalk(enabled))
+                .context("Emit set speedwalk event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_secret_input", move |secret: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetSecretInput(secret))
+                .context("Emit set secret input event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_prompt_line", move |enabled: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetPromptLine(enabled))
+                .context("Emit set prompt line event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_status_bar", move |enabled: bool| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetStatusBar(enabled))
+                .context("Emit set status bar event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_numpad", move |enabled: bool| -> ScriptResult<()> {
+            let numpad = if enabled { Some(NumpadDirections::default()) } else { None };
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetNumpad(numpad))
+                .context("Emit set numpad event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("set_numpad_directions", move |commands: Map| -> ScriptResult<()> {
+            let numpad = NumpadDirections::from(commands)
+                .context("Parse numpad directions")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetNumpad(Some(numpad)))
+                .context("Emit set numpad event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("dump_layout", move || -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::DumpLayout)
+                .context("Emit dump layout event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("trigger", move |pattern: &str, func: FnPtr| -> ScriptResult<()> {
+            let pattern = Regex::new(pattern)
+                .context("Compile trigger pattern")
+                .into_script_result()?;
+
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::AddTrigger(pattern, callback))
+                .context("Emit add trigger event")
+                .into_script_result()
+        });
+
+        /* Removes all triggers with the pattern, as several functions may be bound to one */
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("remove_trigger", move |pattern: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::RemoveTrigger(pattern))
+                .context("Emit remove trigger event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("trigger_rule", move |pattern: &str, command: String| -> ScriptResult<()> {
+            let rule = Rule {
+                kind: RuleKind::Trigger,
+                pattern: Regex::new(pattern)
+                    .context("Compile rule pattern")
+                    .into_script_result()?,
+                action: command,
+            };
+
+            i_tx_cl.blocking_send(ScriptEvent::AddRule(rule))
+                .context("Emit add rule event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("remove_trigger_rule", move |pattern: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::RemoveRule(RuleKind::Trigger, pattern))
+                .context("Emit remove rule event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("play_sound", move |pattern: &str, path: String| -> ScriptResult<()> {
+            let rule = Rule {
+                kind: RuleKind::Sound,
+                pattern: Regex::new(pattern)
+                    .context("Compile rule pattern")
+                    .into_script_result()?,
+                action: path,
+            };
+
+            i_tx_cl.blocking_send(ScriptEvent::AddRule(rule))
+                .context("Emit add rule event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("remove_play_sound", move |pattern: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::RemoveRule(RuleKind::Sound, pattern))
+                .context("Emit remove rule event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("set_sound_muted", move |muted: bool| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::SetSoundMuted(muted))
+                .context("Emit set sound muted event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("save_rules", move || -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::SaveRules(None))
+                .context("Emit save rules event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("save_rules", move |path: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::SaveRules(Some(path)))
+                .context("Emit save rules event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("load_rules", move |path: String| -> ScriptResult<()> {
+            i_tx_cl.blocking_send(ScriptEvent::LoadRules(path))
+                .context("Emit load rules event")
+                .into_script_result()
+        });
+
+        let regexes = RegexCache::new();
+
+        let regexes_cl = regexes.clone();
+        engine.register_fn("matches", move |pattern: &str, text: &str| -> ScriptResult<bool> {
+            Ok(regexes_cl.get(pattern)?.is_match(text))
+        });
+
+        /* The whole match followed by the groups (`()` for those that did not participate), or an empty array */
+        let regexes_cl = regexes.clone();
+        engine.register_fn("capture", move |pattern: &str, text: &str| -> ScriptResult<Array> {
+            let result = regexes_cl.get(pattern)?
+                .captures(text)
+                .map(|captures| captures.iter()
+                    .map(|group| group.map_or(Dynamic::UNIT, |group| group.as_str().into()))
+                    .collect())
+                .unwrap_or_default();
+
+            Ok(result)
+        });
+
+        let tick = self.tick.clone();
+        engine.register_fn("set_tick_pattern", move |pattern: &str| -> ScriptResult<()> {
+            let pattern = Regex::new(pattern)
+                .context("Compile tick pattern")
+                .into_script_result()?;
+
+            tick.lock()
+                .map_err(|_| "Tick timer poisoned")?
+                .set_pattern(pattern);
+
+            Ok(())
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("on_tick", move |func: FnPtr| -> ScriptResult<()> {
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::OnTick(callback))
+                .context("Emit on tick event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("on_gmcp", move |package: String, func: FnPtr| -> ScriptResult<()> {
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::OnGmcp(package, callback))
+                .context("Emit on GMCP event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("on_msdp", move |variable: String, func: FnPtr| -> ScriptResult<()> {
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::OnMsdp(variable, callback))
+                .context("Emit on MSDP event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("on_mssp", move |func: FnPtr| -> ScriptResult<()> {
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::OnMssp(callback))
+                .context("Emit on MSSP event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        let ev_tx_cl = ev_tx.clone();
+        let bind = move |key: &str, func: FnPtr, description: String| -> ScriptResult<()> {
+            let key = KeySpec::parse(key)
+                .context(format!("Parse key {key:?}"))
+                .into_script_result()?;
+
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::Bind(key, callback))
+                .context("Emit bind event")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetKeyBinding(key, Some(description)))
+                .context("Emit set key binding event")
+                .into_script_result()
+        };
+
+        let bind_cl = bind.clone();
+        engine.register_fn("bind", move |key: &str, func: FnPtr| -> ScriptResult<()> {
+            /* Closures have no name worth showing */
+            let description = if func.is_anonymous() { "(function)".to_string() } else { func.fn_name().to_string() };
+
+            bind_cl(key, func, description)
+        });
+
+        engine.register_fn("bind", move |key: &str, func: FnPtr, description: String| -> ScriptResult<()> {
+            bind(key, func, description)
+        });
+
+        let i_tx_cl = i_tx.clone();
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("unbind", move |key: &str| -> ScriptResult<()> {
+            let key = KeySpec::parse(key)
+                .context(format!("Parse key {key:?}"))
+                .into_script_result()?;
+
+            i_tx_cl.blocking_send(ScriptEvent::Unbind(key))
+                .context("Emit unbind event")
+                .into_script_result()?;
+
+            ev_tx_cl.blocking_send(ScriptEngineEvent::SetKeyBinding(key, None))
+                .context("Emit set key binding event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("on_input", move |target: String, func: FnPtr| -> ScriptResult<()> {
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::OnInput(target, callback))
+                .context("Emit on input event")
+                .into_script_result()
+        });
+
+        let ev_tx_cl = ev_tx.clone();
+        engine.register_fn("msdp_report", move |variable: String| -> ScriptResult<()> {
+            ev_tx_cl.blocking_send(ScriptEngineEvent::MsdpReport(variable))
+                .context("Emit MSDP report event")
+                .into_script_result()
+        });
+
+        /* Seconds, or `()` while the tick interval is still being learned */
+        let tick = self.tick.clone();
+        engine.register_fn("time_to_tick", move || -> ScriptResult<Dynamic> {
+            let time_to_tick = tick.lock()
+                .map_err(|_| "Tick timer poisoned")?
+                .time_to_tick(Instant::now());
+
+            Ok(time_to_tick.map_or(Dynamic::UNIT, |time| (time.as_secs_f64() as FLOAT).into()))
+        });
+
+        engine.register_fn("version", || crate::VERSION);
+
+        let script_args: Map = self.script_args.iter()
+            .map(|(key, value)| (key.as_str().into(), value.clone().into()))
+            .collect();
+
+        let script_args_cl = script_args.clone();
+        engine.register_fn("args", move || script_args_cl.clone());
+
+        /* `()` if there is no such argument */
+        engine.register_fn("arg", move |name: &str| script_args.get(name).cloned().unwrap_or(Dynamic::UNIT));
+
+        /* Blocks only the calling script (or callback); everything else keeps running */
+        engine.register_fn("sleep", |seconds: FLOAT| -> ScriptResult<()> {
+            decide_line(Verdict::Keep);
+            std::thread::sleep(duration_from_secs(seconds)?);
+            Ok(())
+        });
+
+        engine.register_fn("sleep", |seconds: i64| -> ScriptResult<()> {
+            decide_line(Verdict::Keep);
+            std::thread::sleep(duration_from_int_secs(seconds)?);
+            Ok(())
+        });
+
+        /* Non-blocking: returns immediately and calls the function once the time has passed */
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("after", move |seconds: FLOAT, func: FnPtr| -> ScriptResult<()> {
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::After(duration_from_secs(seconds)?, callback))
+                .context("Emit after event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("after", move |seconds: i64, func: FnPtr| -> ScriptResult<()> {
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::After(duration_from_int_secs(seconds)?, callback))
+                .context("Emit after event")
+                .into_script_result()
+        });
+
+        let i_tx_cl = i_tx.clone();
+        let next_timer_id = self.next_timer_id.clone();
+        engine.register_fn("set_timer", move |seconds: FLOAT, func: FnPtr| -> ScriptResult<i64> {
+            if !seconds.is_finite() || seconds <= 0.0 {
+                return Err(format!("Timer period must be above 0, got {seconds}").into());
+            }
+
+            let period = duration_from_secs(seconds)?;
+            let id = next_timer_id.fetch_add(1, Ordering::Relaxed);
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::SetTimer(id, period, callback))
+                .context("Emit set timer event")
+                .into_script_result()?;
+
+            Ok(id as i64)
+        });
+
+        let i_tx_cl = i_tx.clone();
+        let next_timer_id = self.next_timer_id.clone();
+        engine.register_fn("set_timer", move |seconds: i64, func: FnPtr| -> ScriptResult<i64> {
+            if seconds <= 0 {
+                return Err(format!("Timer period must be above 0, got {seconds}").into());
+            }
+
+            let id = next_timer_id.fetch_add(1, Ordering::Relaxed);
+            let callback = Callback { script: script_id, func };
+
+            i_tx_cl.blocking_send(ScriptEvent::SetTimer(id, Duration::from_secs(seconds as u64), callback))
+                .context("Emit set timer event")
+                .into_script_result()?;
+
+            Ok(id as i64)
+        });
+
+        let i_tx_cl = i_tx.clone();
+        engine.register_fn("clear_timer", move |id: i64| -> ScriptResult<()> {
+            let id = usize::try_from(id)
+                .context("Parse timer id")
+                .into_script_result()?;
+
+            i_tx_cl.blocking_send(ScriptEvent::ClearTimer(id))
+                .context("Emit clear timer event")
+                .into_script_result()
+        });
+
+        engine
+    }
+}
+
+/// A number of seconds given by a script as a duration, or an error if it is negative or too large to be one.
+fn duration_from_secs(seconds: FLOAT) -> ScriptResult<Duration> {
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|err| format!("Invalid number of seconds {seconds}: {err}").into())
+}
+
+/// A whole number of seconds given by a script as a duration, or an error if it is negative.
+fn duration_from_int_secs(seconds: i64) -> ScriptResult<Duration> {
+    u64::try_from(seconds)
+        .map(Duration::from_secs)
+        .map_err(|_| format!("Invalid number of seconds {seconds}: must not be negative").into())
+}
+
+/// Wait for output matching a pattern for at most `timeout`, returning it or `()` if none came in time.
+fn expect_timeout(i_tx: &Sender<ScriptEvent>, expect: String, timeout: Duration) -> ScriptResult<Dynamic> {
+    decide_line(Verdict::Keep);
+
+    let (tx, rx) = oneshot::channel();
+
+    i_tx.blocking_send(ScriptEvent::Expect(expect, Some(timeout), tx))
+        .context("Emit expect event")
+        .into_script_result()?;
+
+    rx.blocking_recv()
+        .map(|data| data.map_or(Dynamic::UNIT, Dynamic::from))
+        .context("Wait for expectation to be satisfied")
+        .into_script_result()
+}
+
+/// Compiled regular expressions by pattern, so that scripts can use the same patterns over and over cheaply.
+#[derive(Clone)]
+struct RegexCache {
+    regexes: Arc<Mutex<HashMap<String, Regex>>>,
+}
+
+impl RegexCache {
+    /// Patterns are only ever added, so start over once there are this many (e.g. generated by a script).
+    const MAX_SIZE: usize = 256;
+
+    fn new() -> RegexCache {
+        RegexCache { regexes: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    fn get(&self, pattern: &str) -> ScriptResult<Regex> {
+        let mut regexes = self.regexes.lock()
+            .map_err(|_| "Regex cache poisoned")?;
+
+        if let Some(regex) = regexes.get(pattern) {
+            return Ok(regex.clone());
+        }
+
+        let regex = Regex::new(pattern)
+            .context(format!("Compile pattern {pattern}"))
+            .into_script_result()?;
+
+        if regexes.len() >= RegexCache::MAX_SIZE {
+            regexes.clear();
+        }
+
+        regexes.insert(pattern.to_string(), regex.clone());
+
+        Ok(regex)
+    }
+}
+
+/// Whether a GMCP package registered with `on_gmcp` covers a received one, e.g. `Char` covers `Char.Vitals`.
+fn gmcp_package_matches(registered: &str, package: &str) -> bool {
+    match package.get(..registered.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(registered) => {
+            package.len() == registered.len() || package[registered.len()..].starts_with('.')
+        },
+        _ => false,
+    }
+}
+
+/// Parse the JSON body of a GMCP message; bodies other than objects (e.g. a number) are wrapped as `value`.
+fn parse_gmcp_body(data: &str) -> Result<Map> {
+    let engine = Engine::new_raw();
+
+    let result = match data.trim() {
+        "" => Ok(Map::new()),
+        data if data.starts_with('{') => engine.parse_json(data, true),
+        data => engine.parse_json(format!(r#"{{"value": {data}}}"#), true),
+    };
+
+    result.map_err(|err| anyhow::format_err!("{err}"))
+}
+
+/// Decide whether the line of the trigger callback running on this thread is displayed, unless that is already decided.
+/// Returns whether it was undecided.
+fn decide_line(verdict: Verdict) -> bool {
+    match LINE_VERDICT.with(|pending| pending.borrow_mut().take()) {
+        Some(tx) => {
+            /* The engine may have stopped waiting, e.g. when shutting down */
+            let _ = tx.send(verdict);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Wait until the deadline, or forever if there is none.
+async fn sleep_until_deadline(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Event for sending a command from a script or trigger, echoed or not depending on `set_command_echo`.
+fn send_event(text: String, echo: &AtomicBool) -> ScriptEngineEvent {
+    if echo.load(Ordering::Relaxed) {
+        ScriptEngineEvent::Send(text)
+    } else {
+        ScriptEngineEvent::SendSilent(text)
+    }
+}
+
+/// Bytes written in hex, optionally separated by whitespace (e.g. `"ff fa c9"`).
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    let digits = hex.chars()
+        .filter(|ch| !ch.is_whitespace())
+        .map(|ch| ch.to_digit(16).context(format!("Invalid hex digit {ch:?}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    if !digits.len().is_multiple_of(2) {
+        anyhow::bail!("Odd number of hex digits in {hex:?}");
+    }
+
+    Ok(digits.chunks(2)
+        .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+        .collect())
+}
+
+/// MSDP value as a script value: tables become maps and arrays arrays.
+fn msdp_to_dynamic(value: MsdpValue) -> Dynamic {
+    match value {
+        MsdpValue::String(text) => text.into(),
+        MsdpValue::Array(values) => values.into_iter()
+            .map(msdp_to_dynamic)
+            .collect::<Array>()
+            .into(),
+        MsdpValue::Table(variables) => variables.into_iter()
+            .map(|(name, value)| (name.into(), msdp_to_dynamic(value)))
+            .collect::<Map>()
+            .into(),
+    }
+}
+
+/// Parse and validate a layout passed from a script.
+fn parse_layout(layout: Map) -> ScriptResult<LayoutElement> {
+    let mut layout = LayoutElement::from(layout)
+        .context("Parse layout data")
+        .into_script_result()?;
+
+    if layout.inputs().is_empty() {
+        return Err("Layout must include an input".into());
+    }
+
+    if layout.pane(1).is_none() { // TODO: it must be a scroll pane
+        return Err("Layout must include default pane (id = 1)".into());
+    }
+
+    Ok(layout)
 }
 
 trait ResultExt<T> {
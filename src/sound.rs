@@ -0,0 +1,31 @@
+use anyhow::Result;
+#[cfg(feature = "sound")]
+use anyhow::Context;
+
+/// Play an audio file to completion on the default output device.
+///
+/// This blocks for as long as the sound plays, so call it from a blocking task.
+#[cfg(feature = "sound")]
+pub fn play(path: &str) -> Result<()> {
+    let file = std::fs::File::open(path)
+        .context(format!("Open sound file {path}"))?;
+
+    let source = rodio::Decoder::new(std::io::BufReader::new(file))
+        .context(format!("Decode sound file {path}"))?;
+
+    let (_stream, handle) = rodio::OutputStream::try_default()
+        .context("Open audio output device")?;
+
+    let sink = rodio::Sink::try_new(&handle)
+        .context("Create audio sink")?;
+
+    sink.append(source);
+    sink.sleep_until_end();
+
+    Ok(())
+}
+
+#[cfg(not(feature = "sound"))]
+pub fn play(path: &str) -> Result<()> {
+    anyhow::bail!("Cannot play {path}: Draugr was built without the \"sound\" feature")
+}
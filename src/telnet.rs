@@ -1,32 +1,168 @@
-use anyhow::{Result, Context, anyhow};
-use telnet::{Event, TelnetOption};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, Context, anyhow, bail};
+use telnet::{Event, TelnetOption, ZlibStream};
 use tokio::sync::mpsc::{channel, Sender, Receiver};
 
+use crate::encoding::Encoding;
+use crate::msdp::{self, MsdpValue};
+use crate::mxp;
+use crate::tls::TlsStream;
+
+/// Generic MUD Communication Protocol, see https://tintin.mudhalla.net/protocols/gmcp/
+const GMCP: u8 = 201;
+
+/// Mud Server Data Protocol, see https://tintin.mudhalla.net/protocols/msdp/
+const MSDP: u8 = 69;
+
+/// Mud Server Status Protocol, see https://tintin.mudhalla.net/protocols/mssp/
+const MSSP: u8 = 70;
+
+/// MUD eXtension Protocol, see https://www.zuggsoft.com/zmud/mxp.htm
+const MXP: u8 = 91;
+
+/// Commands marking the end of a prompt: IAC GO AHEAD and IAC END OF RECORD (once EOR is agreed on).
+const GA: u8 = 249;
+const EOR: u8 = 239;
+
+/// Interpret As Command, introducing telnet commands, and NO OPERATION, sent with it to keep an idle connection alive.
+const IAC: u8 = 255;
+const NOP: u8 = 241;
+
+/// GMCP packages the server is asked to send once GMCP is enabled.
+const GMCP_SUPPORTS: &str = r#"["Char 1", "Room 1", "Comm 1"]"#;
+
+/// Answers to the server's terminal type requests, in order (see https://tintin.mudhalla.net/protocols/mtts/):
+/// client name, terminal type and MTTS flags (1 = ANSI, 4 = UTF-8, 8 = 256 colors).
+/// The last one is repeated, which tells the server that the list has ended.
+const TTYPE_ANSWERS: [&str; 3] = ["DRAUGR", "ANSI-256COLOR", "MTTS 13"];
+
+/// Character set negotiation, see https://tintin.mudhalla.net/protocols/charset/
+const CHARSET: u8 = 42;
+
+/// CHARSET subnegotiation commands.
+const CHARSET_REQUEST: u8 = 1;
+const CHARSET_ACCEPTED: u8 = 2;
+const CHARSET_REJECTED: u8 = 3;
+
+/// TTYPE subnegotiation commands.
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+
+/// Size of the telnet parser's buffer.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Delay before the first reconnection attempt; it doubles with every failed one, up to `MAX_RECONNECT_DELAY`.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
 pub enum TelnetRequest {
     Connect(String, u16),
+    /// Connect over TLS, validating the server's certificate.
+    ConnectTls(String, u16),
     Send(String),
-    #[allow(dead_code)] // TODO
+    /// Send text without a newline after it, e.g. a single-key answer to a menu.
+    SendNoNewline(String),
+    /// Write bytes to the server as they are, e.g. telnet commands (no newline is added, nor are IAC bytes doubled).
+    SendRaw(Vec<u8>),
+    /// Close the connection, keeping the task running for a later `Connect`.
     Disconnect,
+    /// Whether to queue data sent while disconnected (instead of failing) and send it once connected.
+    SetQueueWhileDisconnected(bool),
+    ClearQueue,
+    /// Whether to reconnect to the last server (with increasing delays) when the connection drops.
+    SetAutoReconnect(bool),
+    /// Ask the server to send updates of an MSDP variable.
+    MsdpReport(String),
+    /// Encoding of text received from and sent to the server, unless it agrees to UTF-8 (CHARSET).
+    SetEncoding(Encoding),
+    /// The terminal now has this many columns and rows; the server is told if it asked (NAWS).
+    WindowResize(u16, u16),
+    /// Send a keepalive after this many seconds without sending anything (`None` or `0` disables it).
+    SetKeepalive(Option<u64>, Keepalive),
     Shutdown,
 }
 
+/// What to send to keep an idle connection from being dropped.
+#[derive(Clone, Copy)]
+pub enum Keepalive {
+    /// IAC NOP, which the server ignores.
+    Nop,
+    /// An empty line, for servers (or proxies) that only count actual input.
+    BlankLine,
+}
+
 pub enum TelnetEvent {
     Data(String),
     Unhandled(Event),
     Info(String),
     Warning(String),
     Error(anyhow::Error),
+    /// Number of commands waiting to be sent once connected.
+    QueueSize(usize),
+    /// GMCP message: package name (e.g. `Char.Vitals`) and JSON body (empty if there is none).
+    Gmcp(String, String),
+    /// MSDP variable: name and value.
+    Msdp(String, MsdpValue),
+    /// MSSP server status: variable names and values.
+    Mssp(HashMap<String, String>),
+    /// The text of the line so far, as the server marked the end of a prompt (with GA or EOR)
+    /// or the output received ended without a newline; and whether it was marked (i.e. the line is complete).
+    Prompt(String, bool),
+    /// Whether the server echoes input (ECHO), i.e. it should not be shown locally, as with passwords.
+    EchoState(bool),
+    ConnectionState(ConnectionState),
+    /// Time from sending a command until the server's next output.
+    Latency(Duration),
 }
 
-pub fn telnet_connection() -> Result<(Sender<TelnetRequest>, Receiver<TelnetEvent>)> {
+/// Whether there is a connection, and to which server (e.g. `mud.example.com:4000 (TLS)`).
+#[derive(Clone)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting(String),
+    Connected(String),
+}
+
+/// Create the connection task; if `trace` is given, telnet protocol events are logged to that file.
+pub fn telnet_connection(trace: Option<String>) -> Result<(Sender<TelnetRequest>, Receiver<TelnetEvent>)> {
     let (req_tx, req_rx) = channel(1024);
     let (ev_tx, ev_rx) = channel(1024);
 
+    let trace = match trace {
+        Some(path) => Some(Trace::create(&path)
+            .context(format!("Create telnet trace file {path}"))?),
+        None => None,
+    };
+
     tokio::task::spawn_blocking(move || {
         let mut telnet = TelnetConnection {
             telnet: None,
+            raw_stream: Weak::new(),
             rx: req_rx,
             tx: ev_tx,
+            queue_while_disconnected: false,
+            queue: VecDeque::new(),
+            trace,
+            auto_reconnect: false,
+            last_server: None,
+            reconnect: None,
+            keepalive: None,
+            last_sent: Instant::now(),
+            window_size: None,
+            naws: false,
+            ttype_index: 0,
+            encoding: Encoding::Utf8,
+            pending: vec![],
+            output: OutputDecoder::default(),
+            server_echo: false,
+            awaiting_reply: None,
         };
 
         loop {
@@ -34,6 +170,9 @@ pub fn telnet_connection() -> Result<(Sender<TelnetRequest>, Receiver<TelnetEven
             telnet.handle_telnet_recv()
                 .context("Handle telnet recv")?;
 
+            telnet.handle_reconnect()
+                .context("Handle reconnection")?;
+
             // Handle requests and sending to socket
             if telnet.handle_request()
                 .context("Handle request")? {
@@ -49,39 +188,367 @@ pub fn telnet_connection() -> Result<(Sender<TelnetRequest>, Receiver<TelnetEven
 
 struct TelnetConnection {
     telnet: Option<telnet::Telnet>,
+    /// The stream `telnet` reads from, for writing raw bytes past it; gone once the connection is dropped.
+    raw_stream: Weak<RefCell<Box<dyn telnet::Stream>>>,
     rx: Receiver<TelnetRequest>,
     tx: Sender<TelnetEvent>,
+
+    queue_while_disconnected: bool,
+    queue: VecDeque<String>,
+
+    trace: Option<Trace>,
+
+    auto_reconnect: bool,
+    /// Address, port and whether TLS was used for the last successful connection, to reconnect the same way.
+    last_server: Option<(String, u16, bool)>,
+    /// When to make the next reconnection attempt and the delay it was scheduled with.
+    reconnect: Option<(Instant, Duration)>,
+
+    /// How long the connection may stay idle before a keepalive is sent, and what to send.
+    keepalive: Option<(Duration, Keepalive)>,
+    /// When anything was last sent to the server (or the connection was made).
+    last_sent: Instant,
+
+    /// Columns and rows of the terminal, as last reported.
+    window_size: Option<(u16, u16)>,
+    /// Whether the server asked for the window size on this connection.
+    naws: bool,
+    /// Which of `TTYPE_ANSWERS` to send on the next request from the server.
+    ttype_index: usize,
+
+    encoding: Encoding,
+    /// Bytes of a UTF-8 character split between reads, to be prepended to the next one.
+    pending: Vec<u8>,
+    /// Turns what is received into output, keeping what is carried over from one read to the next.
+    output: OutputDecoder,
+    /// Whether the server agreed to echo input on this connection.
+    server_echo: bool,
+    /// When the oldest command not yet followed by output was sent, to measure latency.
+    awaiting_reply: Option<Instant>,
 }
 
 impl TelnetConnection {
-    fn connect(&mut self, address: String, port: u16) -> Result<()> {
-        self.send_info(format!("Connecting to {address}:{port}..."))
+    fn connect(&mut self, address: String, port: u16, tls: bool) -> Result<()> {
+        self.send_state(ConnectionState::Connecting(server_name(&address, port, tls)))
+            .context("Report connecting")?;
+
+        let result = self.connect_impl(address, port, tls);
+
+        /* A failed attempt leaves the previous connection, if any, as it was */
+        self.send_state(self.state())
+            .context("Report connection state")?;
+
+        result
+    }
+
+    fn connect_impl(&mut self, address: String, port: u16, tls: bool) -> Result<()> {
+        /* Resolve explicitly, so that a slow resolver shows and failing to resolve is told apart from failing to connect */
+        self.send_info(format!("Resolving {address}..."))
+            .context("Inform about resolving address")?;
+
+        let addresses: Vec<_> = (address.as_str(), port).to_socket_addrs()
+            .context(format!("Could not resolve {address}"))?
+            .collect();
+
+        if addresses.is_empty() {
+            bail!("No addresses found for {address}");
+        }
+
+        self.send_info(format!("Connecting to {}...", server_name(&address, port, tls)))
             .context("Inform about connection attempt")?;
 
-        self.telnet = Some(
-            telnet::Telnet::connect((address, port), 1024*1024)
-                .context("Connect to server")?);
+        let stream = TcpStream::connect(&addresses[..])
+            .map_err(|err| {
+                let reason = match err.kind() {
+                    ErrorKind::ConnectionRefused => "connection refused (is the port right?)",
+                    ErrorKind::TimedOut => "timed out",
+                    _ => "could not connect",
+                };
+
+                anyhow!(err).context(format!("Connect to {address}:{port}: {reason}"))
+            })?;
+
+        let stream: Box<dyn telnet::Stream> = if tls {
+            Box::new(TlsStream::connect(&address, stream)
+                .context(format!("Secure connection to {address}:{port}"))?)
+        } else {
+            Box::new(stream)
+        };
+
+        let stream = Rc::new(RefCell::new(stream));
+        self.raw_stream = Rc::downgrade(&stream);
+
+        self.telnet = Some(telnet::Telnet::from_stream(Box::new(ZlibStream::from_stream(ServerStream(stream))), BUFFER_SIZE));
+        self.last_server = Some((address, port, tls));
+        self.last_sent = Instant::now();
+        self.naws = false;
+        self.ttype_index = 0;
+        self.pending.clear();
+        self.output = OutputDecoder::default();
+        self.awaiting_reply = None;
+        self.set_server_echo(false)
+            .context("Reset echo state")?;
 
         self.send_info("Connected.".into())
             .context("Inform about successful connection")?;
 
+        self.flush_queue()
+            .context("Send queued commands")?;
+
+        Ok(())
+    }
+
+    fn write_line(&mut self, data: &str) -> Result<()> {
+        self.write_text(data)?;
+
+        if let Some(telnet) = &mut self.telnet {
+            telnet.write(b"\n")
+                .context("Write newline to socket")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_text(&mut self, data: &str) -> Result<()> {
+        if let Some(telnet) = &mut self.telnet {
+            telnet.write(&self.encoding.encode(data))
+                .context("Write data to socket")?;
+
+            self.last_sent = Instant::now();
+
+            Ok(())
+        } else {
+            Err(anyhow!("Connection is closed"))
+        }
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        let Some(stream) = self.raw_stream.upgrade() else {
+            return Err(anyhow!("Connection is closed"));
+        };
+
+        stream.borrow_mut().write_all(data)
+            .context("Write raw data to socket")?;
+
+        self.last_sent = Instant::now();
+
+        if let Some(trace) = &mut self.trace {
+            trace.log("SENT", format!("RAW {}", hex_dump(data)))
+                .context("Trace sent raw data")?;
+        }
+
         Ok(())
     }
 
+    /// Send a negotiation (e.g. `DO EOR`), tracing it.
+    fn negotiate(&mut self, action: &telnet::Action, option: TelnetOption) -> Result<()> {
+        let Some(telnet) = &mut self.telnet else {
+            bail!("Connection is closed");
+        };
+
+        telnet.negotiate(action, option)
+            .context("Write negotiation to socket")?;
+
+        if let Some(trace) = &mut self.trace {
+            trace.log("SENT", format!("{} {}", action_name(action), option_name(option)))
+                .context("Trace sent negotiation")?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a subnegotiation, tracing it (in hex, like received ones).
+    fn subnegotiate(&mut self, option: TelnetOption, data: &[u8]) -> Result<()> {
+        let Some(telnet) = &mut self.telnet else {
+            bail!("Connection is closed");
+        };
+
+        telnet.subnegotiate(option, data)
+            .context("Write subnegotiation to socket")?;
+
+        if let Some(trace) = &mut self.trace {
+            trace.log("SENT", format!("SB {}: {}", option_name(option), hex_dump(data)))
+                .context("Trace sent subnegotiation")?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_queue(&mut self) -> Result<()> {
+        if self.queue.is_empty() {
+            return Ok(());
+        }
+
+        self.send_info(format!("Sending {} queued command(s)...", self.queue.len()))
+            .context("Inform about sending queued commands")?;
+
+        while let Some(data) = self.queue.pop_front() {
+            self.write_line(&data)
+                .context("Send queued command")?;
+        }
+
+        self.send_queue_size()
+    }
+
+    /// The connection state as it is now.
+    fn state(&self) -> ConnectionState {
+        match (&self.telnet, &self.last_server) {
+            (Some(_), Some((address, port, tls))) => ConnectionState::Connected(server_name(address, *port, *tls)),
+            _ => ConnectionState::Disconnected,
+        }
+    }
+
+    fn send_state(&mut self, state: ConnectionState) -> Result<()> {
+        self.tx.blocking_send(TelnetEvent::ConnectionState(state))
+            .context("Send connection state")
+    }
+
+    fn send_queue_size(&mut self) -> Result<()> {
+        self.tx.blocking_send(TelnetEvent::QueueSize(self.queue.len()))
+            .context("Send queue size")
+    }
+
     fn reset_connection(&mut self) -> Result<()> {
         self.telnet = None;
+        self.awaiting_reply = None;
+
+        self.send_state(ConnectionState::Disconnected)
+            .context("Report disconnection")?;
+
+        self.set_server_echo(false)
+            .context("Reset echo state")?;
 
         self.send_warning("Disconnected.".into())
             .context("Warn about broken connection")?;
 
+        if self.auto_reconnect && self.last_server.is_some() {
+            self.schedule_reconnect(MIN_RECONNECT_DELAY)
+                .context("Schedule reconnection")?;
+        }
+
         Ok(())
     }
 
+    fn schedule_reconnect(&mut self, delay: Duration) -> Result<()> {
+        self.reconnect = Some((Instant::now() + delay, delay));
+
+        self.send_info(format!("Reconnecting in {}s...", delay.as_secs()))
+            .context("Inform about reconnection")
+    }
+
+    /// Make a scheduled reconnection attempt once it is due, scheduling the next one (after a longer delay) if it fails.
+    fn handle_reconnect(&mut self) -> Result<()> {
+        let delay = match self.reconnect {
+            Some((at, delay)) if at <= Instant::now() => delay,
+            _ => { return Ok(()); },
+        };
+
+        self.reconnect = None;
+
+        let Some((address, port, tls)) = self.last_server.clone() else {
+            return Ok(());
+        };
+
+        if let Err(err) = self.connect(address, port, tls) {
+            self.send_error(err.context("Reconnect"))
+                .context("Notify of reconnection error")?;
+
+            self.schedule_reconnect((delay * 2).min(MAX_RECONNECT_DELAY))
+                .context("Schedule next reconnection")?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a keepalive if nothing was sent for longer than the keepalive interval.
+    fn handle_keepalive(&mut self) -> Result<()> {
+        let keepalive = match self.keepalive {
+            Some((interval, keepalive)) if self.last_sent.elapsed() >= interval => keepalive,
+            _ => { return Ok(()); },
+        };
+
+        match keepalive {
+            Keepalive::Nop => self.write_raw(&[IAC, NOP])
+                .context("Send NOP"),
+            Keepalive::BlankLine => self.write_line("")
+                .context("Send blank line"),
+        }
+    }
+
     fn send_info(&mut self, data: String) -> Result<()> {
         self.tx.blocking_send(TelnetEvent::Info(data))
             .context("Send info from telnet")
     }
 
+    /// Pass decoded server output on, with line endings normalized and stray control characters removed.
+    fn send_data(&mut self, data: &str) -> Result<()> {
+        if let Some(sent) = self.awaiting_reply.take() {
+            self.tx.blocking_send(TelnetEvent::Latency(sent.elapsed()))
+                .context("Send latency over channel")?;
+        }
+
+        let (s, dropped) = self.output.decode(data);
+
+        if let (Some(trace), false) = (&mut self.trace, dropped.is_empty()) {
+            trace.log("DROP", format!("control characters: {}", hex_dump(&dropped)))
+                .context("Trace dropped control characters")?;
+        }
+
+        let unterminated = !s.is_empty() && !s.ends_with('\n');
+
+        self.tx.blocking_send(TelnetEvent::Data(s))
+            .context("Send data over channel")?;
+
+        /* Servers which mark prompts neither with GA nor EOR still tend to end a batch of output with one */
+        if unterminated {
+            self.tx.blocking_send(TelnetEvent::Prompt(self.output.partial_line().to_string(), false))
+                .context("Send prompt over channel")?;
+        }
+
+        Ok(())
+    }
+
+    fn set_server_echo(&mut self, echo: bool) -> Result<()> {
+        if echo == self.server_echo {
+            return Ok(());
+        }
+
+        self.server_echo = echo;
+
+        self.tx.blocking_send(TelnetEvent::EchoState(echo))
+            .context("Send echo state over channel")
+    }
+
+    /// Pass the line received so far on as a prompt, as the server marked its end.
+    fn send_prompt(&mut self) -> Result<()> {
+        if self.output.partial_line().is_empty() {
+            return Ok(());
+        }
+
+        self.tx.blocking_send(TelnetEvent::Prompt(self.output.take_partial_line(), true))
+            .context("Send prompt over channel")
+    }
+
+    /// Tell the server the window size, if it asked for it and the size is known.
+    fn send_window_size(&mut self) -> Result<()> {
+        if let (true, true, Some((columns, rows))) = (self.telnet.is_some(), self.naws, self.window_size) {
+            let mut data = vec![];
+
+            /* A 255 byte would read as IAC, so it has to be doubled */
+            for byte in [columns.to_be_bytes(), rows.to_be_bytes()].concat() {
+                data.push(byte);
+                if byte == 255 {
+                    data.push(byte);
+                }
+            }
+
+            self.subnegotiate(TelnetOption::NAWS, &data)
+                .context("Send NAWS subnegotiation")?;
+        }
+
+        Ok(())
+    }
+
     fn send_warning(&mut self, data: String) -> Result<()> {
         self.tx.blocking_send(TelnetEvent::Warning(data))
             .context("Send warning from telnet")
@@ -94,9 +561,18 @@ impl TelnetConnection {
 
     fn handle_telnet_recv(&mut self) -> Result<()> {
         if let Err(err) = self.handle_telnet_recv_impl() {
-            // Assume socket is bad
-            self.send_error(err)
-                .context("Send error information")?;
+            if is_closed_by_server(&err) {
+                let server = self.last_server.as_ref()
+                    .map(|(address, port, _)| format!(" {address}:{port}"))
+                    .unwrap_or_default();
+
+                self.send_info(format!("Server{server} closed the connection"))
+                    .context("Inform about closed connection")?;
+            } else {
+                // Assume socket is bad
+                self.send_error(err)
+                    .context("Send error information")?;
+            }
 
             self.reset_connection()
                 .context("Reset connection")?;
@@ -110,22 +586,124 @@ impl TelnetConnection {
             let event = telnet.read_timeout(std::time::Duration::from_millis(20))
                 .context("Read from socket")?;
 
+            if let Some(trace) = &mut self.trace {
+                trace.received(&event)
+                    .context("Trace received event")?;
+            }
+
             match event {
-                Event::TimedOut => {},
+                Event::TimedOut => {
+                    self.handle_keepalive()
+                        .context("Handle keepalive")?;
+                },
+                Event::Data(data) if self.encoding != Encoding::Utf8 => {
+                    let s = self.encoding.decode(&data);
+
+                    self.send_data(&s)
+                        .context("Send decoded data")?;
+                },
                 Event::Data(data) => {
-                    let s = String::from_utf8(data.into())
-                        .context("Decode data to UTF-8 string")?;
-                    self.tx.blocking_send(TelnetEvent::Data(s))
-                        .context("Send data over channel")?;
+                    let (s, warning) = decode_utf8(&data, &mut self.pending);
+
+                    if let Some(warning) = warning {
+                        self.tx.blocking_send(TelnetEvent::Warning(warning))
+                            .context("Warn about invalid UTF-8")?;
+                    }
+
+                    self.send_data(&s)
+                        .context("Send decoded data")?;
+                },
+                Event::UnknownIAC(GA | EOR) => {
+                    self.send_prompt()
+                        .context("Send prompt")?;
+                },
+                Event::Negotiation(telnet::Action::Will, TelnetOption::EOR) => {
+                    self.negotiate(&telnet::Action::Do, TelnetOption::EOR)
+                        .context("Negotiate EOR")?;
                 },
-                Event::UnknownIAC(249) => { /* IAC GO AHEAD - used as end-of-prompt signal in some MUDs */},
                 Event::Negotiation(telnet::Action::Will, TelnetOption::Compress2) => {
                     self.tx.blocking_send(TelnetEvent::Info("Server supports MCCP2".into()))
                         .context("Inform of MCCP2 capability")?;
 
-                    telnet.negotiate(&telnet::Action::Do, TelnetOption::Compress2)
+                    self.negotiate(&telnet::Action::Do, TelnetOption::Compress2)
                         .context("Negotiate MCCP2")?;
                 },
+                Event::Negotiation(telnet::Action::Will, TelnetOption::UnknownOption(GMCP)) => {
+                    self.tx.blocking_send(TelnetEvent::Info("Server supports GMCP".into()))
+                        .context("Inform of GMCP capability")?;
+
+                    self.negotiate(&telnet::Action::Do, TelnetOption::UnknownOption(GMCP))
+                        .context("Negotiate GMCP")?;
+
+                    /* Servers expect the client to introduce itself and say what it wants to receive */
+                    let hello = format!(r#"Core.Hello {{"client": "Draugr", "version": "{}"}}"#, env!("CARGO_PKG_VERSION"));
+                    let supports = format!("Core.Supports.Set {GMCP_SUPPORTS}");
+
+                    for message in [hello, supports] {
+                        self.subnegotiate(TelnetOption::UnknownOption(GMCP), message.as_bytes())
+                            .context("Send GMCP message")?;
+                    }
+                },
+                Event::Negotiation(telnet::Action::Do, TelnetOption::NAWS) => {
+                    self.negotiate(&telnet::Action::Will, TelnetOption::NAWS)
+                        .context("Negotiate NAWS")?;
+
+                    self.naws = true;
+
+                    self.send_window_size()
+                        .context("Send initial window size")?;
+                },
+                Event::Negotiation(telnet::Action::Will, TelnetOption::UnknownOption(MSDP)) => {
+                    self.tx.blocking_send(TelnetEvent::Info("Server supports MSDP".into()))
+                        .context("Inform of MSDP capability")?;
+
+                    self.negotiate(&telnet::Action::Do, TelnetOption::UnknownOption(MSDP))
+                        .context("Negotiate MSDP")?;
+
+                    /* The answer tells scripts what they can ask to be reported */
+                    self.subnegotiate(TelnetOption::UnknownOption(MSDP), &msdp::encode("LIST", "REPORTABLE_VARIABLES"))
+                        .context("Ask for MSDP variables")?;
+                },
+                Event::Negotiation(telnet::Action::Will, TelnetOption::UnknownOption(MSSP)) => {
+                    self.negotiate(&telnet::Action::Do, TelnetOption::UnknownOption(MSSP))
+                        .context("Negotiate MSSP")?;
+                },
+                Event::Negotiation(telnet::Action::Do, TelnetOption::UnknownOption(CHARSET)) => {
+                    self.negotiate(&telnet::Action::Will, TelnetOption::UnknownOption(CHARSET))
+                        .context("Negotiate CHARSET")?;
+                },
+                /* Servers differ in which side they take to offer MXP, so either is agreed to (once, to avoid a loop) */
+                Event::Negotiation(action @ (telnet::Action::Will | telnet::Action::Do), TelnetOption::UnknownOption(MXP)) => {
+                    if !self.output.mxp_enabled() {
+                        let reply = if matches!(action, telnet::Action::Will) { telnet::Action::Do } else { telnet::Action::Will };
+
+                        self.negotiate(&reply, TelnetOption::UnknownOption(MXP))
+                            .context("Negotiate MXP")?;
+
+                        self.output.enable_mxp();
+
+                        self.send_info("MXP enabled".into())
+                            .context("Inform of MXP enabled")?;
+                    }
+                },
+                Event::Negotiation(telnet::Action::Do, TelnetOption::TTYPE) => {
+                    self.negotiate(&telnet::Action::Will, TelnetOption::TTYPE)
+                        .context("Negotiate TTYPE")?;
+                },
+                Event::Negotiation(action @ (telnet::Action::Will | telnet::Action::Wont), TelnetOption::Echo) => {
+                    let echo = matches!(action, telnet::Action::Will);
+
+                    /* Only answer changes, as answering a confirmation would start a negotiation loop */
+                    if echo != self.server_echo {
+                        let reply = if echo { telnet::Action::Do } else { telnet::Action::Dont };
+
+                        self.negotiate(&reply, TelnetOption::Echo)
+                            .context("Negotiate ECHO")?;
+
+                        self.set_server_echo(echo)
+                            .context("Change echo state")?;
+                    }
+                },
                 Event::Negotiation(_, _) => {},
                 Event::Subnegotiation(TelnetOption::Compress2, _) => {
                     telnet.begin_zlib();
@@ -133,6 +711,71 @@ impl TelnetConnection {
                     self.tx.blocking_send(TelnetEvent::Info("MCCP2 enabled".into()))
                         .context("Inform of MCCP2 enabled")?;
                 },
+                Event::Subnegotiation(TelnetOption::UnknownOption(GMCP), data) => {
+                    let message = String::from_utf8_lossy(&data);
+
+                    let (package, body) = message.split_once(|ch: char| ch.is_ascii_whitespace())
+                        .unwrap_or((&message, ""));
+
+                    self.tx.blocking_send(TelnetEvent::Gmcp(package.to_string(), body.trim().to_string()))
+                        .context("Send GMCP message over channel")?;
+                },
+                Event::Subnegotiation(TelnetOption::UnknownOption(MSDP), data) => {
+                    match msdp::parse(&data) {
+                        Ok(variables) => {
+                            for (name, value) in variables {
+                                self.tx.blocking_send(TelnetEvent::Msdp(name, value))
+                                    .context("Send MSDP variable over channel")?;
+                            }
+                        },
+                        Err(err) => {
+                            self.send_warning(format!("Skipping malformed MSDP message: {err} ({})", hex_dump(&data)))
+                                .context("Warn about malformed MSDP")?;
+                        },
+                    }
+                },
+                Event::Subnegotiation(TelnetOption::UnknownOption(MSSP), data) => {
+                    self.tx.blocking_send(TelnetEvent::Mssp(parse_mssp(&data)))
+                        .context("Send MSSP status over channel")?;
+                },
+                Event::Subnegotiation(TelnetOption::UnknownOption(CHARSET), data) if data.first() == Some(&CHARSET_REQUEST) => {
+                    /* The first byte after REQUEST separates the offered character sets; UTF-8 is preferred to the others */
+                    let offered: Vec<(String, Encoding)> = match data.get(1) {
+                        Some(separator) => data[2..].split(|byte| byte == separator)
+                            .map(|name| String::from_utf8_lossy(name).into_owned())
+                            .filter_map(|name| Encoding::from_name(&name).map(|encoding| (name, encoding)))
+                            .collect(),
+                        None => vec![],
+                    };
+
+                    let accepted = offered.iter()
+                        .find(|(_, encoding)| *encoding == Encoding::Utf8)
+                        .or(offered.first())
+                        .cloned();
+
+                    let reply = match &accepted {
+                        Some((name, _)) => [&[CHARSET_ACCEPTED], name.as_bytes()].concat(),
+                        None => vec![CHARSET_REJECTED],
+                    };
+
+                    self.subnegotiate(TelnetOption::UnknownOption(CHARSET), &reply)
+                        .context("Reply to character set request")?;
+
+                    if let Some((name, encoding)) = accepted {
+                        self.encoding = encoding;
+                        self.pending.clear();
+
+                        self.send_info(format!("Server agreed to {name}"))
+                            .context("Inform of character set agreed")?;
+                    }
+                },
+                Event::Subnegotiation(TelnetOption::TTYPE, data) if data.first() == Some(&TTYPE_SEND) => {
+                    let answer = TTYPE_ANSWERS[self.ttype_index];
+                    self.ttype_index = (self.ttype_index + 1).min(TTYPE_ANSWERS.len() - 1);
+
+                    self.subnegotiate(TelnetOption::TTYPE, &[&[TTYPE_IS], answer.as_bytes()].concat())
+                        .context("Send terminal type")?;
+                },
                 Event::Subnegotiation(_, _) => {},
                 _ => {
                     self.tx.blocking_send(TelnetEvent::Unhandled(event))
@@ -159,26 +802,83 @@ impl TelnetConnection {
         if let Ok(request) = self.rx.try_recv() {
             match request {
                 TelnetRequest::Connect(address, port) => {
-                    self.connect(address, port)
+                    self.reconnect = None;
+
+                    self.connect(address, port, false)
+                        .context("Connect to server")?;
+                },
+                TelnetRequest::ConnectTls(address, port) => {
+                    self.reconnect = None;
+
+                    self.connect(address, port, true)
                         .context("Connect to server")?;
                 },
                 TelnetRequest::Send(data) => {
-                    if let Some(telnet) = &mut self.telnet {
-                        telnet.write(data.as_bytes())
-                            .context("Write data to socket")?;
-                        telnet.write(b"\n")
-                            .context("Write newline to socket")?;
+                    if self.telnet.is_none() && self.queue_while_disconnected {
+                        self.queue.push_back(data);
+                        self.send_queue_size()?;
                     } else {
-                        return Err(anyhow!("Connection is closed"));
+                        self.write_line(&data)?;
+                        self.awaiting_reply.get_or_insert_with(Instant::now);
                     }
                 },
+                TelnetRequest::SendNoNewline(data) => {
+                    self.write_text(&data)?;
+                },
+                TelnetRequest::SendRaw(data) => {
+                    self.write_raw(&data)?;
+                },
                 TelnetRequest::Disconnect => {
-                    if self.telnet.is_some() {
-                        return Ok(true);
+                    self.reconnect = None;
+
+                    if self.telnet.take().is_some() {
+                        self.awaiting_reply = None;
+
+                        self.set_server_echo(false)
+                            .context("Reset echo state")?;
+
+                        self.send_state(ConnectionState::Disconnected)
+                            .context("Report disconnection")?;
+
+                        self.send_info("Disconnected by user".into())
+                            .context("Inform about disconnecting")?;
                     } else {
                         return Err(anyhow!("Connection is closed"));
                     }
                 },
+                TelnetRequest::SetQueueWhileDisconnected(enabled) => {
+                    self.queue_while_disconnected = enabled;
+                },
+                TelnetRequest::ClearQueue => {
+                    self.queue.clear();
+                    self.send_queue_size()?;
+                },
+                TelnetRequest::MsdpReport(variable) => {
+                    self.subnegotiate(TelnetOption::UnknownOption(MSDP), &msdp::encode("REPORT", &variable))
+                        .context("Ask for MSDP variable to be reported")?;
+                },
+                TelnetRequest::SetEncoding(encoding) => {
+                    self.encoding = encoding;
+                    self.pending.clear();
+                },
+                TelnetRequest::WindowResize(columns, rows) => {
+                    self.window_size = Some((columns, rows));
+
+                    self.send_window_size()
+                        .context("Send changed window size")?;
+                },
+                TelnetRequest::SetKeepalive(seconds, keepalive) => {
+                    self.keepalive = seconds
+                        .filter(|&seconds| seconds > 0)
+                        .map(|seconds| (Duration::from_secs(seconds), keepalive));
+                },
+                TelnetRequest::SetAutoReconnect(enabled) => {
+                    self.auto_reconnect = enabled;
+
+                    if !enabled {
+                        self.reconnect = None;
+                    }
+                },
                 TelnetRequest::Shutdown => {
                     return Ok(true);
                 }
@@ -188,3 +888,368 @@ impl TelnetConnection {
         Ok(false)
     }
 }
+
+/// The connection to the server, shared by the telnet parser and raw writes.
+///
+/// The server closing the connection is reported as an `UnexpectedEof` error,
+/// as the telnet parser would take an empty read for one without any events.
+struct ServerStream(Rc<RefCell<Box<dyn telnet::Stream>>>);
+
+impl Read for ServerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.borrow_mut().read(buf)? {
+            0 if !buf.is_empty() => Err(io::Error::new(ErrorKind::UnexpectedEof, "Connection closed by server")),
+            size => Ok(size),
+        }
+    }
+}
+
+impl Write for ServerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+impl telnet::Stream for ServerStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.borrow().set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.0.borrow().set_read_timeout(dur)
+    }
+}
+
+/// Whether an error is (caused by) the server closing the connection, rather than a failure.
+fn is_closed_by_server(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| err.kind() == ErrorKind::UnexpectedEof)
+}
+
+/// Parse MSSP variables (`VAR name VAL value`); several values of a variable (e.g. ports) are joined with commas.
+fn parse_mssp(data: &[u8]) -> HashMap<String, String> {
+    let mut status: HashMap<String, String> = HashMap::new();
+
+    for variable in data.split(|&byte| byte == msdp::VAR).skip(1) {
+        let mut parts = variable.split(|&byte| byte == msdp::VAL)
+            .map(|part| String::from_utf8_lossy(part).into_owned());
+
+        let Some(name) = parts.next() else { continue };
+        let values: Vec<_> = parts.collect();
+
+        status.entry(name)
+            .and_modify(|existing| existing.extend(values.iter().map(|value| format!(", {value}"))))
+            .or_insert_with(|| values.join(", "));
+    }
+
+    status
+}
+
+/// Decode UTF-8 received after the bytes `pending` from the previous read, leaving in `pending` those of a character
+/// cut short at the end, to wait for the rest of it. Invalid bytes are replaced, with a warning of where they are.
+fn decode_utf8(data: &[u8], pending: &mut Vec<u8>) -> (String, Option<String>) {
+    let mut data = [std::mem::take(pending), data.to_vec()].concat();
+    let complete = data.len() - incomplete_utf8_suffix(&data);
+    *pending = data.split_off(complete);
+
+    match std::str::from_utf8(&data) {
+        Ok(s) => (s.to_string(), None),
+        Err(err) => {
+            let warning = err.error_len().map(|error_len| {
+                let start = err.valid_up_to();
+
+                format!("Invalid UTF-8 at byte {start}: {}",
+                    hex_dump(&data[start..(start + error_len.max(16)).min(data.len())]))
+            });
+
+            (String::from_utf8_lossy(&data).into_owned(), warning)
+        },
+    }
+}
+
+/// Number of bytes at the end of `data` which start a UTF-8 character without completing it.
+fn incomplete_utf8_suffix(data: &[u8]) -> usize {
+    /* Characters are at most 4 bytes long, so an incomplete one has its lead byte among the last 3 */
+    for (back, &byte) in data.iter().rev().take(3).enumerate() {
+        let length = match byte {
+            0x80..=0xbf => continue,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return 0,
+        };
+
+        return if back + 1 < length { back + 1 } else { 0 };
+    }
+
+    0
+}
+
+/// Turns text received from the server into output: line endings are normalized, stray control characters
+/// dropped and MXP tags parsed (once enabled). What is split between reads is carried over to the next one.
+#[derive(Default)]
+pub struct OutputDecoder {
+    /// Whether the last data received ended with a CR, so that an LF starting the next data completes a CRLF.
+    pending_cr: bool,
+    /// Text received since the last newline, i.e. the prompt once the server marks its end.
+    partial_line: String,
+    /// Parser of MXP tags in the output, once the server agreed to send them.
+    mxp: Option<mxp::Parser>,
+}
+
+impl OutputDecoder {
+    /// Decode received text, returning the output and the control characters dropped from it.
+    pub fn decode(&mut self, data: &str) -> (String, Vec<u8>) {
+        let s = normalize_line_endings(data, &mut self.pending_cr);
+
+        let (s, dropped) = strip_control_chars(&s);
+
+        let s = match &mut self.mxp {
+            Some(mxp) => mxp.feed(&s),
+            None => s,
+        };
+
+        match s.rfind('\n') {
+            Some(end) => self.partial_line = s[end + 1..].to_string(),
+            None => self.partial_line.push_str(&s),
+        }
+
+        (s, dropped)
+    }
+
+    pub fn partial_line(&self) -> &str {
+        &self.partial_line
+    }
+
+    /// The text received since the last newline, which now counts as a complete line.
+    pub fn take_partial_line(&mut self) -> String {
+        std::mem::take(&mut self.partial_line)
+    }
+
+    pub fn mxp_enabled(&self) -> bool {
+        self.mxp.is_some()
+    }
+
+    pub fn enable_mxp(&mut self) {
+        self.mxp = Some(mxp::Parser::default());
+    }
+}
+
+/// Drop NUL characters and turn CRLF as well as a lone CR (sent by some servers) into LF.
+///
+/// `pending_cr` carries a CR at the end of `data` over to the next call, as CRLF may be split between reads.
+fn normalize_line_endings(data: &str, pending_cr: &mut bool) -> String {
+    let mut result = String::with_capacity(data.len());
+    let mut chars = data.chars().peekable();
+
+    if *pending_cr && chars.peek() == Some(&'\n') {
+        chars.next();
+    }
+
+    *pending_cr = false;
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\0' => {},
+            '\r' => {
+                match chars.peek() {
+                    Some('\n') => {},
+                    Some(_) => result.push('\n'),
+                    None => {
+                        result.push('\n');
+                        *pending_cr = true;
+                    },
+                }
+            },
+            _ => result.push(ch),
+        }
+    }
+
+    result
+}
+
+/// Remove C0 control characters (e.g. protocol artifacts left over after negotiation races) which would otherwise
+/// show up as garbage, returning the cleaned data and the removed bytes.
+///
+/// Line breaks and tabs are kept, as are ESC (starting ANSI sequences) and BEL (ending some of them).
+fn strip_control_chars(data: &str) -> (String, Vec<u8>) {
+    let mut dropped = vec![];
+
+    let cleaned = data.chars()
+        .filter(|&ch| {
+            let keep = !ch.is_ascii_control() || matches!(ch, '\n' | '\t' | '\x1b' | '\x07');
+
+            if !keep {
+                dropped.push(ch as u8);
+            }
+
+            keep
+        })
+        .collect();
+
+    (cleaned, dropped)
+}
+
+/// Log of telnet protocol events, for debugging protocol support.
+struct Trace {
+    file: File,
+    start: Instant,
+}
+
+impl Trace {
+    fn create(path: &str) -> Result<Trace> {
+        Ok(Trace {
+            file: File::create(path)?,
+            start: Instant::now(),
+        })
+    }
+
+    fn log(&mut self, direction: &str, description: String) -> Result<()> {
+        writeln!(self.file, "[{:10.3}] {direction} {description}", self.start.elapsed().as_secs_f64())
+            .context("Write to trace file")
+    }
+
+    fn received(&mut self, event: &Event) -> Result<()> {
+        let description = match event {
+            Event::TimedOut | Event::NoData => { return Ok(()); },
+            Event::Data(data) => format!("DATA ({} bytes)", data.len()),
+            Event::UnknownIAC(command) => format!("IAC {}", command_name(*command)),
+            Event::Negotiation(action, option) => format!("{} {}", action_name(action), option_name(*option)),
+            Event::Subnegotiation(option, data) => format!("SB {}: {}", option_name(*option), hex_dump(data)),
+            Event::Error(err) => format!("ERROR {err:?}"),
+        };
+
+        self.log("RECV", description)
+    }
+}
+
+fn action_name(action: &telnet::Action) -> &'static str {
+    match action {
+        telnet::Action::Will => "WILL",
+        telnet::Action::Wont => "WONT",
+        telnet::Action::Do => "DO",
+        telnet::Action::Dont => "DONT",
+    }
+}
+
+/// How a server is shown to the user, e.g. `mud.example.com:4000 (TLS)`.
+fn server_name(address: &str, port: u16, tls: bool) -> String {
+    format!("{address}:{port}{}", if tls { " (TLS)" } else { "" })
+}
+
+/// Name of a telnet option, including the MUD-specific ones the telnet crate does not know about.
+fn option_name(option: TelnetOption) -> String {
+    match option {
+        TelnetOption::Compress2 => "MCCP2".into(),
+        TelnetOption::UnknownOption(CHARSET) => "CHARSET".into(),
+        TelnetOption::UnknownOption(MSDP) => "MSDP".into(),
+        TelnetOption::UnknownOption(MSSP) => "MSSP".into(),
+        TelnetOption::UnknownOption(90) => "MSP".into(),
+        TelnetOption::UnknownOption(MXP) => "MXP".into(),
+        TelnetOption::UnknownOption(GMCP) => "GMCP".into(),
+        TelnetOption::UnknownOption(byte) => format!("option {byte}"),
+        _ => format!("{option:?}").to_uppercase(),
+    }
+}
+
+fn command_name(command: u8) -> String {
+    match command {
+        239 => "EOR".into(),
+        241 => "NOP".into(),
+        242 => "DM".into(),
+        243 => "BRK".into(),
+        244 => "IP".into(),
+        245 => "AO".into(),
+        246 => "AYT".into(),
+        247 => "EC".into(),
+        248 => "EL".into(),
+        249 => "GA".into(),
+        _ => format!("command {command}"),
+    }
+}
+
+/// Format bytes as space-separated hex values, e.g. "c3 28".
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_utf8_joins_a_character_split_between_reads() {
+        let mut pending = vec![];
+
+        /* "ł" is 0xc5 0x82 */
+        assert_eq!(decode_utf8(b"Bia\xc5", &mut pending), ("Bia".to_string(), None));
+        assert_eq!(pending, b"\xc5");
+
+        let (s, warning) = decode_utf8(b"\x82ystok", &mut pending);
+        assert_eq!(s, "łystok");
+        assert_eq!(s.chars().next(), Some('ł'));
+        assert!(warning.is_none());
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn decode_utf8_replaces_invalid_bytes_with_a_warning() {
+        let mut pending = vec![];
+
+        let (s, warning) = decode_utf8(b"a\xffb", &mut pending);
+        assert_eq!(s, "a\u{fffd}b");
+        assert!(warning.is_some_and(|warning| warning.starts_with("Invalid UTF-8 at byte 1")));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn normalize_line_endings_turns_crlf_and_lone_cr_into_lf() {
+        let mut pending_cr = false;
+
+        assert_eq!(normalize_line_endings("a\r\nb\rc\n", &mut pending_cr), "a\nb\nc\n");
+        assert!(!pending_cr);
+    }
+
+    #[test]
+    fn normalize_line_endings_drops_nul() {
+        let mut pending_cr = false;
+
+        assert_eq!(normalize_line_endings("a\0b\r\0\n", &mut pending_cr), "ab\n\n");
+    }
+
+    #[test]
+    fn normalize_line_endings_joins_crlf_split_between_reads() {
+        let mut pending_cr = false;
+
+        assert_eq!(normalize_line_endings("a\r", &mut pending_cr), "a\n");
+        assert!(pending_cr);
+
+        assert_eq!(normalize_line_endings("\nb", &mut pending_cr), "b");
+        assert!(!pending_cr);
+    }
+
+    #[test]
+    fn normalize_line_endings_keeps_lf_after_a_carried_over_cr_only_once() {
+        let mut pending_cr = false;
+
+        normalize_line_endings("a\r", &mut pending_cr);
+
+        assert_eq!(normalize_line_endings("\n\nb", &mut pending_cr), "\nb");
+    }
+
+    #[test]
+    fn strip_control_chars_keeps_line_breaks_tabs_and_ansi() {
+        let (cleaned, dropped) = strip_control_chars("a\x01\tb\x1b[1mc\x07\x08\n");
+
+        assert_eq!(cleaned, "a\tb\x1b[1mc\x07\n");
+        assert_eq!(dropped, vec![0x01, 0x08]);
+    }
+}
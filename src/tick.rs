@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+/// Number of recent tick intervals the estimate is based on.
+const INTERVAL_SAMPLES: usize = 5;
+
+/// Ticks closer together than this are taken to be the same tick (e.g. a tick line repeated in a prompt).
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Follows the game "tick", as observed by lines of output matching a pattern.
+pub struct TickTimer {
+    pattern: Option<Regex>,
+    last_tick: Option<Instant>,
+    intervals: VecDeque<Duration>,
+}
+
+impl TickTimer {
+    pub fn new() -> TickTimer {
+        TickTimer { pattern: None, last_tick: None, intervals: VecDeque::new() }
+    }
+
+    /// Start following ticks signalled by a new pattern, forgetting what was learned so far.
+    pub fn set_pattern(&mut self, pattern: Regex) {
+        *self = TickTimer { pattern: Some(pattern), ..TickTimer::new() };
+    }
+
+    /// Check a line of output for a tick, returning whether one happened.
+    pub fn observe(&mut self, line: &str, now: Instant) -> bool {
+        if !self.pattern.as_ref().is_some_and(|pattern| pattern.is_match(line)) {
+            return false;
+        }
+
+        if let Some(last_tick) = self.last_tick {
+            let interval = now.duration_since(last_tick);
+
+            if interval < MIN_INTERVAL {
+                return false;
+            }
+
+            self.intervals.push_back(interval);
+
+            if self.intervals.len() > INTERVAL_SAMPLES {
+                self.intervals.pop_front();
+            }
+        }
+
+        self.last_tick = Some(now);
+
+        true
+    }
+
+    /// Average of the recent tick intervals, if at least one has been observed.
+    pub fn interval(&self) -> Option<Duration> {
+        if self.intervals.is_empty() {
+            None
+        } else {
+            Some(self.intervals.iter().sum::<Duration>() / self.intervals.len() as u32)
+        }
+    }
+
+    /// Estimated time until the next tick; `None` while the interval is still being learned.
+    pub fn time_to_tick(&self, now: Instant) -> Option<Duration> {
+        let interval = self.interval()?;
+        let since_tick = now.duration_since(self.last_tick?);
+
+        /* Ticks may have been missed (e.g. while disconnected), so count from the last expected one */
+        let into_interval = Duration::from_secs_f64(since_tick.as_secs_f64() % interval.as_secs_f64());
+
+        Some(interval - into_interval)
+    }
+}
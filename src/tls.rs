@@ -0,0 +1,52 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use native_tls::{HandshakeError, TlsConnector};
+
+/// A TLS connection over TCP, usable as a stream by the telnet parser.
+pub struct TlsStream(native_tls::TlsStream<TcpStream>);
+
+impl TlsStream {
+    /// Perform the TLS handshake on an established connection, validating the server's certificate for `domain`.
+    pub fn connect(domain: &str, stream: TcpStream) -> Result<TlsStream> {
+        let connector = TlsConnector::new()
+            .context("Create TLS connector")?;
+
+        let stream = connector.connect(domain, stream)
+            .map_err(|err| match err {
+                /* Including certificate validation failures */
+                HandshakeError::Failure(err) => anyhow!(err).context("TLS handshake failed"),
+                HandshakeError::WouldBlock(_) => anyhow!("TLS handshake interrupted"),
+            })?;
+
+        Ok(TlsStream(stream))
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl telnet::Stream for TlsStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.0.get_ref().set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.0.get_ref().set_read_timeout(dur)
+    }
+}
@@ -0,0 +1,149 @@
+use std::env;
+
+use ratatui::{buffer::Buffer, style::Color};
+
+/// How many colors a terminal can display, from the fewest to the most.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, clap::ValueEnum)]
+pub enum ColorSupport {
+    /// No colors at all, only attributes such as bold.
+    None,
+    /// The 16 named colors.
+    Basic,
+    /// The 256-color palette.
+    #[value(name = "256")]
+    Palette,
+    /// Any RGB color.
+    #[value(name = "truecolor")]
+    TrueColor,
+}
+
+/// What the terminal is able to do, as far as Draugr is concerned.
+#[derive(Clone, Copy)]
+pub struct Capabilities {
+    pub color: ColorSupport,
+    pub mouse: bool,
+}
+
+impl Capabilities {
+    /// Guess the capabilities from the `COLORTERM` and `TERM` environment variables.
+    pub fn detect() -> Capabilities {
+        let term = env::var("TERM").unwrap_or_default();
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+
+        let color = if term == "dumb" {
+            ColorSupport::None
+        } else if colorterm == "truecolor" || colorterm == "24bit" || term.ends_with("-direct") {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Palette
+        } else if term.is_empty() && cfg!(windows) {
+            /* The Windows console does not set TERM, but has supported RGB colors for long */
+            ColorSupport::TrueColor
+        } else {
+            ColorSupport::Basic
+        };
+
+        /* The Linux console only gets a mouse through gpm, which does not report it the xterm way */
+        let mouse = term != "dumb" && term != "linux";
+
+        Capabilities { color, mouse }
+    }
+}
+
+/// Replace the colors of a rendered buffer with ones the terminal can display.
+pub fn downgrade_colors(buffer: &mut Buffer, support: ColorSupport) {
+    if support == ColorSupport::TrueColor {
+        return;
+    }
+
+    for cell in &mut buffer.content {
+        cell.fg = downgrade_color(cell.fg, support);
+        cell.bg = downgrade_color(cell.bg, support);
+    }
+}
+
+fn downgrade_color(color: Color, support: ColorSupport) -> Color {
+    match (support, color) {
+        (ColorSupport::TrueColor, _) | (_, Color::Reset) => color,
+        (ColorSupport::None, _) => Color::Reset,
+        (ColorSupport::Palette, Color::Rgb(r, g, b)) => Color::Indexed(palette_index(r, g, b)),
+        (ColorSupport::Palette, _) => color,
+        (ColorSupport::Basic, Color::Rgb(r, g, b)) => nearest_basic((r, g, b)),
+        (ColorSupport::Basic, Color::Indexed(index)) => match index {
+            0..=15 => BASIC_COLORS[index as usize].0,
+            _ => nearest_basic(palette_rgb(index)),
+        },
+        (ColorSupport::Basic, _) => color,
+    }
+}
+
+/// Levels of each component in the 6x6x6 color cube of the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Closest entry of the 256-color palette, from either the color cube or the gray ramp.
+fn palette_index(r: u8, g: u8, b: u8) -> u8 {
+    let cube_level = |component: u8| (0..6)
+        .min_by_key(|&level| (CUBE_LEVELS[level] as i32 - component as i32).abs())
+        .unwrap_or(0);
+
+    let (ri, gi, bi) = (cube_level(r), cube_level(g), cube_level(b));
+    let cube = (16 + 36 * ri + 6 * gi + bi) as u8;
+
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray = 232 + ((average.saturating_sub(8) + 5) / 10).min(23) as u8;
+
+    if distance((r, g, b), palette_rgb(gray)) < distance((r, g, b), palette_rgb(cube)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// RGB value of an entry of the 256-color palette.
+fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => BASIC_COLORS[index as usize].1,
+        16..=231 => {
+            let index = (index - 16) as usize;
+            (CUBE_LEVELS[index / 36], CUBE_LEVELS[index / 6 % 6], CUBE_LEVELS[index % 6])
+        },
+        _ => {
+            let level = 8 + (index - 232) * 10;
+            (level, level, level)
+        },
+    }
+}
+
+/// The 16 named colors, in palette order, with their usual (xterm) RGB values.
+const BASIC_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+fn nearest_basic(rgb: (u8, u8, u8)) -> Color {
+    BASIC_COLORS.iter()
+        .min_by_key(|(_, basic)| distance(rgb, *basic))
+        .map(|(color, _)| *color)
+        .unwrap_or(Color::Reset)
+}
+
+/// Squared distance between two colors.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
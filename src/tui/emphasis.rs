@@ -0,0 +1,114 @@
+use anyhow::{Context, Result, bail};
+use ratatui::prelude::*;
+use regex::Regex;
+use rhai::Map;
+
+/// Styling of text marked up with plain-text emphasis, e.g. `*bold*` or `_underlined_`,
+/// for servers which use such conventions instead of ANSI codes.
+pub struct Emphasis {
+    markers: Vec<(Regex, Modifier)>,
+}
+
+impl Default for Emphasis {
+    fn default() -> Emphasis {
+        Emphasis {
+            markers: vec![
+                (marker_regex('*'), Modifier::BOLD),
+                (marker_regex('_'), Modifier::UNDERLINED),
+            ],
+        }
+    }
+}
+
+impl Emphasis {
+    /// Create from a script map of markers to styles, e.g. `#{ "*": "bold", "/": "italic" }`.
+    pub fn from(markers: Map) -> Result<Emphasis> {
+        let mut result = vec![];
+
+        for (marker, style) in markers {
+            let mut chars = marker.chars();
+
+            let (Some(marker), None) = (chars.next(), chars.next()) else {
+                bail!("Emphasis marker must be a single character: {marker}");
+            };
+
+            let modifier = parse_modifier(&style.to_string())
+                .context(format!("Parse style of emphasis marker {marker}"))?;
+
+            result.push((marker_regex(marker), modifier));
+        }
+
+        Ok(Emphasis { markers: result })
+    }
+
+    /// Style emphasized text of a line, in spans which are not styled otherwise (e.g. by ANSI codes).
+    pub fn apply(&self, line: &mut Line<'static>) {
+        for (regex, modifier) in &self.markers {
+            line.spans = std::mem::take(&mut line.spans)
+                .into_iter()
+                .flat_map(|span| emphasize(span, regex, *modifier))
+                .collect();
+        }
+    }
+}
+
+/// Text between a pair of markers, not starting or ending with a space, e.g. `*a few words*`.
+fn marker_regex(marker: char) -> Regex {
+    let marker = regex::escape(&marker.to_string());
+
+    Regex::new(&format!("{marker}([^\\s{marker}](?:[^{marker}]*[^\\s{marker}])?){marker}"))
+        .expect("Marker pattern should be valid")
+}
+
+fn emphasize(span: Span<'static>, regex: &Regex, modifier: Modifier) -> Vec<Span<'static>> {
+    if span.style != Style::default() {
+        return vec![span];
+    }
+
+    let text = span.content.as_ref();
+    let mut result = vec![];
+    let mut rest_start = 0;
+
+    for captures in regex.captures_iter(text) {
+        let (Some(whole), Some(inner)) = (captures.get(0), captures.get(1)) else {
+            continue;
+        };
+
+        /* Only at word boundaries, so that e.g. `2*3*4` or `snake_case_name` are left alone */
+        let before = text[..whole.start()].chars().next_back();
+        let after = text[whole.end()..].chars().next();
+
+        if before.is_some_and(|ch| ch.is_alphanumeric()) || after.is_some_and(|ch| ch.is_alphanumeric()) {
+            continue;
+        }
+
+        if whole.start() > rest_start {
+            result.push(Span::raw(text[rest_start..whole.start()].to_string()));
+        }
+
+        result.push(Span::styled(inner.as_str().to_string(), Style::default().add_modifier(modifier)));
+        rest_start = whole.end();
+    }
+
+    if rest_start == 0 {
+        return vec![span];
+    }
+
+    if rest_start < text.len() {
+        result.push(Span::raw(text[rest_start..].to_string()));
+    }
+
+    result
+}
+
+fn parse_modifier(style: &str) -> Result<Modifier> {
+    match style {
+        "bold" => Ok(Modifier::BOLD),
+        "dim" => Ok(Modifier::DIM),
+        "italic" => Ok(Modifier::ITALIC),
+        "underline" | "underlined" => Ok(Modifier::UNDERLINED),
+        "reversed" => Ok(Modifier::REVERSED),
+        "crossed_out" => Ok(Modifier::CROSSED_OUT),
+        _ => bail!("Invalid style: {style}"),
+    }
+}
@@ -1,20 +1,108 @@
+use std::ops::Range;
+
 use ratatui::{
     prelude::*,
-    widgets::*,
+    widgets::{*, block::*},
 };
 
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
 use crate::ring::RingBuffer;
+use crate::tui::theme::Theme;
+
+/// How input history is kept.
+#[derive(Clone, Copy)]
+pub struct HistoryConfig {
+    pub size: usize,
+    pub dedup: HistoryDedup,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig { size: 1000, dedup: HistoryDedup::MoveToBack }
+    }
+}
+
+/// What to do when submitting input that is already in the history.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum HistoryDedup {
+    /// Keep every entry, in chronological order
+    None,
+    /// Move the earlier entry to the back, i.e. keep each entry once, most recently used last
+    MoveToBack,
+    /// Only skip repeating the last entry
+    Consecutive,
+}
 
 pub struct InputPane {
     state: InputState,
+    /// What was typed before going through the history, given back when leaving it with Esc or past the newest entry.
+    draft: String,
 
     history: RingBuffer<String>,
+    history_dedup: HistoryDedup,
+
+    /// In multi-line mode Enter inserts a line break and Up/Down move between the lines being edited.
+    multiline: bool,
+
+    /// Number of commands waiting to be sent once connected, shown in the title.
+    queued: usize,
+
+    /// What the input is currently asked for, when not a command (e.g. a search term), shown in the title.
+    prompt: Option<String>,
+    /// The server's last prompt (as marked with GA or EOR), shown in the title when the input is not asked for anything else.
+    server_prompt: Option<String>,
+    /// Show the server's prompt on a line of its own above the input, rather than in the title.
+    prompt_line: bool,
+
+    /// Show what is typed as bullets, e.g. while logging in; submitted input is then sent as a secret.
+    secret: bool,
+
+    /// Text last removed by `kill_to_end`/`kill_to_start`, for `yank` to insert back.
+    killed: String,
+
+    /// Word completion in progress, so that completing again goes on to the next candidate.
+    completion: Option<Completion>,
+
+    /// What submitted input is for, when not commands for the server (e.g. `"chat"`), as set in the layout.
+    target: Option<String>,
+    /// Whether this is the input being typed into, when there are several; only it shows the cursor.
+    focused: bool,
+
+    last_seen_area: Rect,
+}
+
+struct Completion {
+    /// Character position where the completed word starts.
+    start: usize,
+    /// What was typed of the word before completing it.
+    prefix: String,
+    /// Index in the candidates of the completion inserted last.
+    index: usize,
 }
 
 #[derive(Clone)]
 enum InputState {
     Typing { buffer: String, cursor_position: usize },
-    HistorySearch { search_term: String, index: usize },
+    HistorySearch { search_term: String, index: usize, kind: MatchKind },
+}
+
+/// How history entries are matched against the search term.
+#[derive(Clone, Copy, PartialEq)]
+enum MatchKind {
+    /// Entries starting with it (Up/Down), shown as a completion of what was typed.
+    Prefix,
+    /// Entries containing it anywhere (Ctrl+R), shown whole with the match highlighted.
+    Substring,
+}
+
+impl MatchKind {
+    fn matches(self, entry: &str, search_term: &str) -> bool {
+        match self {
+            MatchKind::Prefix => entry.starts_with(search_term),
+            MatchKind::Substring => entry.contains(search_term),
+        }
+    }
 }
 
 impl InputState {
@@ -32,28 +120,157 @@ impl InputPane {
     pub fn new() -> InputPane {
         InputPane {
             state: InputState::empty_typing(),
+            draft: String::new(),
+
+            history: RingBuffer::new(HistoryConfig::default().size),
+            history_dedup: HistoryConfig::default().dedup,
+
+            multiline: false,
+
+            queued: 0,
+
+            prompt: None,
+            server_prompt: None,
+            prompt_line: false,
+            secret: false,
+
+            killed: String::new(),
+
+            completion: None,
+
+            target: None,
+            focused: true,
+
+            last_seen_area: Rect::new(0, 0, 1, 1),
+        }
+    }
+
+    /// Create an input pane with a history preloaded with `history` (oldest first), keeping up to `capacity` entries.
+    pub fn with_history(history: Vec<String>, capacity: usize) -> InputPane {
+        let mut input = InputPane::new();
+
+        input.history = RingBuffer::new(capacity.max(1));
+        for entry in history {
+            input.history.push_back(entry);
+        }
+
+        input
+    }
+
+    /// Entries of the history, oldest first.
+    pub fn history(&self) -> Vec<String> {
+        self.history.iter_forwards().collect()
+    }
+
+    /// Apply a history configuration; changing the size starts a new (empty) history.
+    pub fn set_history_config(&mut self, config: HistoryConfig) {
+        if config.size != self.history.capacity() {
+            self.history = RingBuffer::new(config.size.max(1));
+        }
+
+        self.history_dedup = config.dedup;
+    }
+
+    pub fn target(&self) -> Option<String> {
+        self.target.clone()
+    }
+
+    pub fn set_target(&mut self, target: Option<String>) {
+        self.target = target;
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// Whether a position on the screen is within the area the pane was last rendered in.
+    pub fn contains(&self, column: u16, row: u16) -> bool {
+        let area = self.last_seen_area;
+
+        column >= area.left() && column < area.right() && row >= area.top() && row < area.bottom()
+    }
+
+    /// Whether nothing has been typed (and no history entry is being shown).
+    pub fn is_empty(&self) -> bool {
+        matches!(&self.state, InputState::Typing { buffer, cursor_position: _ } if buffer.is_empty())
+    }
+
+    pub fn is_multiline(&self) -> bool {
+        self.multiline
+    }
+
+    pub fn toggle_multiline(&mut self) {
+        self.multiline = !self.multiline;
+    }
+
+    pub fn is_secret(&self) -> bool {
+        self.secret
+    }
+
+    pub fn set_secret(&mut self, secret: bool) {
+        self.secret = secret;
+    }
 
-            history: RingBuffer::new(1000),
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    pub fn set_queued(&mut self, queued: usize) {
+        self.queued = queued;
+    }
+
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.prompt = prompt;
+    }
+
+    pub fn server_prompt(&self) -> Option<String> {
+        self.server_prompt.clone()
+    }
+
+    pub fn set_server_prompt(&mut self, prompt: Option<String>) {
+        self.server_prompt = prompt;
+    }
+
+    pub fn set_prompt_line(&mut self, enabled: bool) {
+        self.prompt_line = enabled;
+    }
+
+    /// The server's prompt, if it is shown on a line of its own.
+    fn prompt_line(&self) -> Option<&str> {
+        self.server_prompt.as_deref().filter(|_| self.prompt_line)
+    }
+
+    /// Number of lines of text the input needs to display (including the prompt line, if shown).
+    pub fn height(&self) -> u16 {
+        let prompt_line = if self.prompt_line().is_some() { 1 } else { 0 };
+
+        prompt_line + match &self.state {
+            InputState::Typing { buffer, cursor_position: _ } => {
+                buffer.split('\n').count() as u16
+            },
+            InputState::HistorySearch { search_term: _, index: _, kind: _ } => 1,
         }
     }
 
     pub fn get_and_submit(&mut self) -> String {
         let (result, new_state) = match &mut self.state {
             InputState::Typing { buffer, cursor_position: _ } => {
-                if !buffer.is_empty() {
-                    self.history.find_and_push_back(buffer.clone());
+                let submit = buffer.clone();
+
+                if !submit.is_empty() {
+                    self.remember(submit.clone());
                 }
 
-                (buffer.clone(), InputState::empty_typing())
+                (submit, InputState::empty_typing())
             },
-            InputState::HistorySearch { search_term, index } => {
-                let submit = if search_term.is_empty() {
-                    self.history.get(*index).clone().unwrap_or_default()
+            InputState::HistorySearch { search_term, index, kind } => {
+                let submit = if search_term.is_empty() || *kind == MatchKind::Substring {
+                    self.history.get_checked(*index).cloned().unwrap_or_default()
                 } else {
                     search_term.to_string()
                 };
 
-                self.history.find_and_push_back(submit.clone());
+                self.remember(submit.clone());
                 (submit, InputState::empty_typing())
             },
         };
@@ -62,12 +279,25 @@ impl InputPane {
         result.clone()
     }
 
+    /// Add submitted input to the history, according to the dedup policy.
+    pub fn remember(&mut self, entry: String) {
+        match self.history_dedup {
+            HistoryDedup::None => self.history.push_back(entry),
+            HistoryDedup::MoveToBack => self.history.find_and_push_back(entry),
+            HistoryDedup::Consecutive => {
+                if self.history.back() != Some(&entry) {
+                    self.history.push_back(entry);
+                }
+            },
+        }
+    }
+
     pub fn get_and_clear(&mut self) -> String {
         let (result, new_state) = match &mut self.state {
             InputState::Typing { buffer, cursor_position: _ } => {
                 (buffer.clone(), InputState::empty_typing())
             },
-            InputState::HistorySearch { search_term: term, index: _ } => {
+            InputState::HistorySearch { search_term: term, index: _, kind: _ } => {
                 (term.clone(), InputState::empty_typing())
             },
         };
@@ -76,26 +306,107 @@ impl InputPane {
         result.clone()
     }
 
-    pub fn render(&self, frame: &mut Frame<'_>, area: Rect) {
+    pub fn render(&mut self, frame: &mut Frame<'_>, area: Rect, theme: &Theme) {
+        self.last_seen_area = area;
+
+        let mut title = vec![];
+
+        if let Some(target) = &self.target {
+            let color = if self.focused { theme.title_active } else { theme.title_inactive };
+            title.push(format!(" → {target} ").fg(color));
+        }
+
+        if let Some(prompt) = &self.prompt {
+            title.push(format!(" {prompt} ").fg(theme.title_active));
+        } else if let (Some(prompt), false) = (&self.server_prompt, self.prompt_line) {
+            title.push(format!(" {prompt} ").fg(theme.title_inactive));
+        }
+
+        if self.secret {
+            title.push(" 🔒 secret (Ctrl+P to end) ".fg(theme.warning));
+        }
+
+        if let Some(search_term) = self.history_search_term() {
+            title.push(format!(" history containing {search_term:?} (Ctrl+R for older) ").fg(theme.title_inactive));
+        }
+
+        if self.multiline {
+            title.push(" multi-line (Ctrl+D to send) ".fg(theme.title_inactive));
+        }
+
+        if self.queued > 0 {
+            title.push(format!(" {} queued ", self.queued).fg(theme.warning));
+        }
+
+        let title = Title::from(title);
+
+        /* Long lines are scrolled sideways, so that the cursor stays in view */
+        let window = self.visible_window(area.width as usize);
+        let marker_style = Style::default().fg(theme.title_inactive);
+
+        let mut text = Text::from(self.as_text(theme).lines.into_iter()
+            .map(|line| windowed_line(line, window.clone(), marker_style))
+            .collect::<Vec<_>>());
+
+        if let Some(prompt) = self.prompt_line() {
+            text.lines.insert(0, Line::from(prompt.fg(theme.title_inactive)));
+        }
+
         frame.render_widget(
-            Paragraph::new(self.as_line())
+            Paragraph::new(text)
                 .block(Block::default().borders(Borders::TOP)
-                .border_style(Style::default().fg(Color::Yellow))),
+                .title(title)
+                .border_style(Style::default().fg(theme.border))),
             area
         );
 
+        if !self.focused {
+            return;
+        }
+
+        let (row, _) = self.cursor_row_column();
+        let column = self.cursor_display_column() - window.start;
+        let prompt_line = if self.prompt_line().is_some() { 1 } else { 0 };
+
         frame.set_cursor(
-            area.left() + self.cursor_position() as u16,
-            area.bottom());
+            area.left() + column as u16,
+            area.top() + 1 /* top bar */ + prompt_line + row as u16);
+    }
+
+    pub fn as_text(&self, theme: &Theme) -> Text<'_> {
+        match &self.state {
+            InputState::Typing { buffer, cursor_position: _ } => {
+                Text::from(buffer.split('\n')
+                    .map(|line| Line::from(self.masked(line).fg(theme.text)))
+                    .collect::<Vec<_>>())
+            },
+            InputState::HistorySearch { search_term: _, index: _, kind: _ } => {
+                Text::from(self.as_line(theme))
+            },
+        }
     }
 
-    pub fn as_line(&self) -> Line {
+    pub fn as_line(&self, theme: &Theme) -> Line<'_> {
         match &self.state {
             InputState::Typing { buffer, cursor_position: _ } => {
-                buffer.clone().white().into()
+                self.masked(buffer).fg(theme.text).into()
             },
-            InputState::HistorySearch { search_term, index } => {
-                let history_entry = self.history.get(*index).as_deref().unwrap_or_default();
+            InputState::HistorySearch { search_term, index, kind: MatchKind::Substring } => {
+                let history_entry = self.history.get_checked(*index).map(String::as_str).unwrap_or_default();
+
+                let Some(start) = history_entry.find(search_term.as_str()).filter(|_| !search_term.is_empty()) else {
+                    return history_entry.fg(theme.text).into();
+                };
+                let end = start + search_term.len();
+
+                Line::from(vec![
+                    history_entry[..start].fg(theme.text),
+                    history_entry[start..end].fg(theme.text).reversed(),
+                    history_entry[end..].fg(theme.text),
+                ])
+            },
+            InputState::HistorySearch { search_term, index, kind: MatchKind::Prefix } => {
+                let history_entry = self.history.get_checked(*index).map(String::as_str).unwrap_or_default();
 
                 let (input, completion) = if search_term.is_empty() {
                     (history_entry, "")
@@ -104,21 +415,30 @@ impl InputPane {
                 };
 
                 Line::from(vec![
-                    input.white(),
-                    completion.cyan()
+                    input.fg(theme.text),
+                    completion.fg(theme.completion)
                 ])
             }
         }
     }
 
+    /// Text as displayed: as it is, or a bullet per character in secret mode.
+    fn masked(&self, text: &str) -> String {
+        if self.secret {
+            "•".repeat(text.chars().count())
+        } else {
+            text.to_string()
+        }
+    }
+
     pub fn cursor_position(&self) -> usize {
         match &self.state {
             InputState::Typing { buffer: _, cursor_position } => {
                 *cursor_position
             },
-            InputState::HistorySearch { search_term, index } => {
-                if search_term.is_empty() {
-                    self.history.get(*index).as_deref().unwrap_or_default().len()
+            InputState::HistorySearch { search_term, index, kind } => {
+                if search_term.is_empty() || *kind == MatchKind::Substring {
+                    self.history.get_checked(*index).map(String::as_str).unwrap_or_default().chars().count()
                 } else {
                     search_term.chars().count()
                 }
@@ -126,6 +446,62 @@ impl InputPane {
         }
     }
 
+    /// Cursor position as (line, character within the line).
+    pub fn cursor_row_column(&self) -> (usize, usize) {
+        match &self.state {
+            InputState::Typing { buffer, cursor_position } => {
+                row_column(buffer, *cursor_position)
+            },
+            InputState::HistorySearch { search_term: _, index: _, kind: _ } => {
+                (0, self.cursor_position())
+            },
+        }
+    }
+
+    /// Column of the cursor on the terminal, within its line: unlike the character within the line,
+    /// this counts wide characters (e.g. CJK) as two columns and combining characters as none.
+    pub fn cursor_display_column(&self) -> usize {
+        let (_, column) = self.cursor_row_column();
+        let before_cursor: String = self.cursor_line().chars().take(column).collect();
+
+        self.masked(&before_cursor).width()
+    }
+
+    /// Display columns of the input shown in a pane `width` columns wide: from the start of the lines,
+    /// unless the cursor would be past the right edge, in which case they are scrolled to keep it in view.
+    ///
+    /// A column at either edge is taken by a marker when there is more of a line beyond it (see `windowed_line`),
+    /// so the cursor is kept off those.
+    pub fn visible_window(&self, width: usize) -> Range<usize> {
+        let line_width = self.masked(self.cursor_line()).width();
+        let column = self.cursor_display_column();
+
+        let start = if line_width < width || column + 2 <= width {
+            0
+        } else {
+            /* Not past the cursor, however narrow the pane */
+            (column + 2 - width).min(column)
+        };
+
+        start..start + width
+    }
+
+    /// The line (of those being edited) the cursor is on, as typed or recalled from the history.
+    fn cursor_line(&self) -> &str {
+        match &self.state {
+            InputState::Typing { buffer, cursor_position: _ } => {
+                let (row, _) = self.cursor_row_column();
+                buffer.split('\n').nth(row).unwrap_or_default()
+            },
+            InputState::HistorySearch { search_term, index, kind: _ } => {
+                self.history.get_checked(*index)
+                    .map(String::as_str)
+                    .filter(|entry| !entry.is_empty())
+                    .unwrap_or(search_term)
+            },
+        }
+    }
+
     pub fn type_string(&mut self, stuff: String) {
         self.cancel_history_search();
 
@@ -140,9 +516,9 @@ impl InputPane {
     }
 
     pub fn cancel_history_search(&mut self) {
-        if let InputState::HistorySearch { search_term, index } = &self.state {
-            let buffer = if search_term.is_empty() {
-                self.history.get(*index).clone().unwrap_or_default()
+        if let InputState::HistorySearch { search_term, index, kind } = &self.state {
+            let buffer = if search_term.is_empty() || *kind == MatchKind::Substring {
+                self.history.get_checked(*index).cloned().unwrap_or_default()
             } else {
                 search_term.to_string()
             };
@@ -166,13 +542,53 @@ impl InputPane {
                     *cursor_position += 1;
                 }
             },
-            InputState::HistorySearch { search_term: _, index } => {
-                let buffer = self.history.get(*index).clone().unwrap_or_default();
+            InputState::HistorySearch { search_term: _, index, kind: _ } => {
+                let buffer = self.history.get_checked(*index).cloned().unwrap_or_default();
                 self.state = InputState::typing_from_buffer(buffer);
             },
         }
     }
 
+    /// Move the cursor to the start of the current or previous word.
+    pub fn word_left(&mut self) {
+        self.cancel_history_search();
+
+        if let InputState::Typing { buffer, cursor_position } = &mut self.state {
+            let chars: Vec<_> = buffer.chars().collect();
+            let mut position = (*cursor_position).min(chars.len());
+
+            while position > 0 && chars[position - 1].is_whitespace() {
+                position -= 1;
+            }
+
+            while position > 0 && !chars[position - 1].is_whitespace() {
+                position -= 1;
+            }
+
+            *cursor_position = position;
+        }
+    }
+
+    /// Move the cursor to the start of the next word (or the end of the input).
+    pub fn word_right(&mut self) {
+        self.cancel_history_search();
+
+        if let InputState::Typing { buffer, cursor_position } = &mut self.state {
+            let chars: Vec<_> = buffer.chars().collect();
+            let mut position = *cursor_position;
+
+            while position < chars.len() && !chars[position].is_whitespace() {
+                position += 1;
+            }
+
+            while position < chars.len() && chars[position].is_whitespace() {
+                position += 1;
+            }
+
+            *cursor_position = position;
+        }
+    }
+
     pub fn home(&mut self) {
         self.cancel_history_search();
 
@@ -186,13 +602,103 @@ impl InputPane {
             InputState::Typing { buffer, cursor_position } => {
                 *cursor_position = buffer.chars().count();
             },
-            InputState::HistorySearch { search_term: _, index } => {
-                let buffer = self.history.get(*index).clone().unwrap_or_default();
+            InputState::HistorySearch { search_term: _, index, kind: _ } => {
+                let buffer = self.history.get_checked(*index).cloned().unwrap_or_default();
                 self.state = InputState::typing_from_buffer(buffer);
             },
         }
     }
 
+    /// Complete the word before the cursor to the first of `candidates` starting with it (ignoring case);
+    /// completing again right away replaces it with the next such candidate, wrapping around.
+    /// Does nothing if there is no word before the cursor or no candidate for it.
+    pub fn complete_word(&mut self, candidates: &[String]) {
+        self.cancel_history_search();
+
+        let InputState::Typing { buffer, cursor_position } = &mut self.state else {
+            return;
+        };
+
+        let chars: Vec<_> = buffer.chars().collect();
+
+        let (start, prefix, skip) = match &self.completion {
+            Some(completion) => (completion.start, completion.prefix.clone(), completion.index + 1),
+            None => {
+                let mut start = (*cursor_position).min(chars.len());
+                while start > 0 && !chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+
+                (start, chars[start..*cursor_position].iter().collect::<String>(), 0)
+            },
+        };
+
+        if prefix.is_empty() {
+            return;
+        }
+
+        let prefix_lower = prefix.to_lowercase();
+        let matching = |candidate: &String| candidate.to_lowercase().starts_with(&prefix_lower)
+            && candidate.chars().count() > prefix.chars().count();
+
+        /* The next matching candidate after the last one inserted, or the first again */
+        let found = candidates.iter().enumerate().skip(skip).find(|(_, candidate)| matching(candidate))
+            .or_else(|| candidates.iter().enumerate().find(|(_, candidate)| matching(candidate)));
+
+        let Some((index, candidate)) = found else {
+            return;
+        };
+
+        let before: String = chars[..start].iter().collect();
+        let after: String = chars[*cursor_position..].iter().collect();
+
+        *buffer = format!("{before}{candidate}{after}");
+        *cursor_position = start + candidate.chars().count();
+
+        self.completion = Some(Completion { start, prefix, index });
+    }
+
+    /// Stop cycling through completions, so that the next `complete_word` starts from what is typed.
+    pub fn end_completion(&mut self) {
+        self.completion = None;
+    }
+
+    /// Remove the text from the cursor to the end of the input, keeping it for `yank`.
+    pub fn kill_to_end(&mut self) {
+        self.cancel_history_search();
+
+        if let InputState::Typing { buffer, cursor_position } = &mut self.state {
+            let byte_position = buffer.char_indices().nth(*cursor_position).map_or(buffer.len(), |(idx, _)| idx);
+            let killed = buffer.split_off(byte_position);
+
+            if !killed.is_empty() {
+                self.killed = killed;
+            }
+        }
+    }
+
+    /// Remove the text from the start of the input to the cursor, keeping it for `yank`.
+    pub fn kill_to_start(&mut self) {
+        self.cancel_history_search();
+
+        if let InputState::Typing { buffer, cursor_position } = &mut self.state {
+            let byte_position = buffer.char_indices().nth(*cursor_position).map_or(buffer.len(), |(idx, _)| idx);
+            let rest = buffer.split_off(byte_position);
+            let killed = std::mem::replace(buffer, rest);
+
+            *cursor_position = 0;
+
+            if !killed.is_empty() {
+                self.killed = killed;
+            }
+        }
+    }
+
+    /// Insert the last killed text at the cursor.
+    pub fn yank(&mut self) {
+        self.type_string(self.killed.clone());
+    }
+
     pub fn backspace(&mut self) {
         self.cancel_history_search();
 
@@ -217,6 +723,19 @@ impl InputPane {
     }
 
     pub fn up(&mut self) {
+        if self.multiline {
+            if let InputState::Typing { buffer, cursor_position } = &mut self.state {
+                let (row, column) = row_column(buffer, *cursor_position);
+                if row > 0 {
+                    *cursor_position = position_of(buffer, row - 1, column);
+                }
+
+                return;
+            }
+        }
+
+        self.save_draft();
+
         self.state = match &mut self.state {
             InputState::Typing { buffer, cursor_position: _ } => {
                 if self.history.is_empty() {
@@ -224,18 +743,18 @@ impl InputPane {
                 } else if let Some(index) = self.history.find_forwards(
                     |x| x.starts_with(buffer.as_str()),
                     self.history.size() - 1) {
-                    InputState::HistorySearch { search_term: buffer.clone(), index }
+                    InputState::HistorySearch { search_term: buffer.clone(), index, kind: MatchKind::Prefix }
                 } else {
                     self.state.clone()
                 }
             },
-            InputState::HistorySearch { search_term, index } => {
+            InputState::HistorySearch { search_term, index, kind } => {
                 if *index == 0 {
                     self.state.clone()
                 } else if let Some(find_index) = self.history.find_forwards(
-                    |x| x.starts_with(search_term.as_str()),
+                    |x| kind.matches(x, search_term),
                     *index - 1) {
-                    InputState::HistorySearch { search_term: search_term.clone(), index: find_index }
+                    InputState::HistorySearch { search_term: search_term.clone(), index: find_index, kind: *kind }
                 } else {
                     self.state.clone()
                 }
@@ -244,17 +763,28 @@ impl InputPane {
     }
 
     pub fn down(&mut self) {
+        if self.multiline {
+            if let InputState::Typing { buffer, cursor_position } = &mut self.state {
+                let (row, column) = row_column(buffer, *cursor_position);
+                if row + 1 < buffer.split('\n').count() {
+                    *cursor_position = position_of(buffer, row + 1, column);
+                }
+
+                return;
+            }
+        }
+
         self.state = match &mut self.state {
             InputState::Typing { buffer: _, cursor_position: _ } => {
                 self.state.clone()
             },
-            InputState::HistorySearch { search_term, index } => {
+            InputState::HistorySearch { search_term, index, kind } => {
                 if *index == self.history.size() {
                     InputState::Typing { buffer: search_term.clone(), cursor_position: search_term.chars().count() }
                 } else if let Some(find_index) = self.history.find_backwards(
-                    |x| x.starts_with(search_term.as_str()),
+                    |x| kind.matches(x, search_term),
                     *index + 1) {
-                    InputState::HistorySearch { search_term: search_term.clone(), index: find_index }
+                    InputState::HistorySearch { search_term: search_term.clone(), index: find_index, kind: *kind }
                 } else {
                     InputState::typing_from_buffer(search_term.clone())
                 }
@@ -262,16 +792,157 @@ impl InputPane {
         };
     }
 
+    /// Go to the previous history entry containing what has been typed anywhere in it (unlike `up`, which only
+    /// goes through entries starting with it); while going through them, go on to the next older one.
+    pub fn search_history(&mut self) {
+        self.save_draft();
+
+        let (search_term, start_at) = match &self.state {
+            InputState::Typing { buffer, cursor_position: _ } => (buffer.clone(), self.history.size().checked_sub(1)),
+            InputState::HistorySearch { search_term, index, kind: _ } => (search_term.clone(), index.checked_sub(1)),
+        };
+
+        let found = start_at.and_then(|start_at| self.history.find_forwards(
+            |x| MatchKind::Substring.matches(x, &search_term),
+            start_at));
+
+        if let Some(index) = found {
+            self.state = InputState::HistorySearch { search_term, index, kind: MatchKind::Substring };
+        }
+    }
+
+    /// The text searched for in the history with `search_history`, while going through the entries containing it.
+    fn history_search_term(&self) -> Option<&str> {
+        match &self.state {
+            InputState::HistorySearch { search_term, index: _, kind: MatchKind::Substring } => Some(search_term),
+            _ => None,
+        }
+    }
+
+    /// Go to the previous history entry, regardless of what has been typed (unlike `up`, which only goes
+    /// through entries starting with the typed text).
+    pub fn history_up(&mut self) {
+        self.save_draft();
+
+        self.state = match &self.state {
+            InputState::Typing { buffer: _, cursor_position: _ } => {
+                if self.history.is_empty() {
+                    self.state.clone()
+                } else {
+                    InputState::HistorySearch { search_term: String::new(), index: self.history.size() - 1, kind: MatchKind::Prefix }
+                }
+            },
+            InputState::HistorySearch { search_term: _, index, kind: _ } => {
+                InputState::HistorySearch { search_term: String::new(), index: index.saturating_sub(1), kind: MatchKind::Prefix }
+            },
+        };
+    }
+
+    /// Go to the next history entry, regardless of what has been typed; past the newest one what was typed is back.
+    pub fn history_down(&mut self) {
+        self.state = match &self.state {
+            InputState::Typing { buffer: _, cursor_position: _ } => {
+                self.state.clone()
+            },
+            InputState::HistorySearch { search_term: _, index, kind: _ } => {
+                if index + 1 < self.history.size() {
+                    InputState::HistorySearch { search_term: String::new(), index: index + 1, kind: MatchKind::Prefix }
+                } else {
+                    InputState::typing_from_buffer(std::mem::take(&mut self.draft))
+                }
+            },
+        };
+    }
+
     pub fn cancel(&mut self) {
         self.state = match &mut self.state {
             InputState::Typing { buffer: _, cursor_position: _ } => {
                 InputState::empty_typing()
             },
-            InputState::HistorySearch { search_term: term, index: _ } => {
-                InputState::typing_from_buffer(term.clone())
+            InputState::HistorySearch { search_term: _, index: _, kind: _ } => {
+                InputState::typing_from_buffer(std::mem::take(&mut self.draft))
             }
         };
     }
+
+    /// Keep what has been typed when starting to go through the history, to give it back afterwards.
+    fn save_draft(&mut self) {
+        if let InputState::Typing { buffer, cursor_position: _ } = &self.state {
+            self.draft = buffer.clone();
+        }
+    }
+}
+
+/// Part of a line within a window of display columns. When there is more of the line to the left or right of it,
+/// the first or last column shows a `<` or `>` marker instead; wide characters cut by an edge are left out.
+fn windowed_line(line: Line<'_>, window: Range<usize>, marker_style: Style) -> Line<'static> {
+    let line_width = line.width();
+
+    let left_marker = window.start > 0 && line_width > 0;
+    let right_marker = line_width > window.end;
+
+    let from = window.start + usize::from(left_marker);
+    let to = window.end.saturating_sub(usize::from(right_marker)).max(from);
+
+    let mut spans = vec![];
+    if left_marker {
+        spans.push(Span::styled("<", marker_style));
+    }
+
+    let mut column = 0;
+    for span in line.spans {
+        let mut content = String::new();
+
+        for ch in span.content.chars() {
+            let width = ch.width().unwrap_or(0);
+
+            if column >= from && column + width <= to {
+                content.push(ch);
+            } else if column < to && column + width > from {
+                /* A wide character cut by an edge: blank the columns of it which are shown */
+                content.push_str(&" ".repeat((column + width).min(to) - column.max(from)));
+            }
+
+            column += width;
+        }
+
+        spans.push(Span::styled(content, span.style));
+    }
+
+    if right_marker {
+        spans.push(Span::styled(">", marker_style));
+    }
+
+    Line::from(spans)
+}
+
+/// Convert a character position in a (possibly multi-line) string into a (line, character within the line) pair.
+fn row_column(source: &str, position: usize) -> (usize, usize) {
+    let before: Vec<_> = source.chars().take(position).collect();
+
+    let row = before.iter().filter(|&&ch| ch == '\n').count();
+    let column = before.iter().rev().take_while(|&&ch| ch != '\n').count();
+
+    (row, column)
+}
+
+/// Convert a (line, character within the line) pair into a character position in a multi-line string.
+///
+/// The character is clamped to the length of the line.
+fn position_of(source: &str, row: usize, column: usize) -> usize {
+    let mut position = 0;
+
+    for (index, line) in source.split('\n').enumerate() {
+        let line_len = line.chars().count();
+
+        if index == row {
+            return position + column.min(line_len);
+        }
+
+        position += line_len + 1; /* +1 for the line break */
+    }
+
+    position.saturating_sub(1)
 }
 
 /// Insert a string into another at a given character (not byte) position.
@@ -310,4 +981,158 @@ fn del_from_string(source: String, position: usize) -> String {
     let (before, after) = source.split_at(byte_position);
 
     format!("{before}{}", after.chars().skip(1).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input_with_history(history: &[&str]) -> InputPane {
+        InputPane::with_history(history.iter().map(|entry| entry.to_string()).collect(), 10)
+    }
+
+    #[test]
+    fn word_left_goes_to_the_start_of_the_current_or_previous_word() {
+        let mut input = InputPane::new();
+        input.type_string("cast  magic missile".into());
+
+        input.word_left();
+        assert_eq!(input.cursor_position(), 12);
+
+        input.word_left();
+        assert_eq!(input.cursor_position(), 6);
+
+        input.word_left();
+        input.word_left();
+        assert_eq!(input.cursor_position(), 0);
+    }
+
+    #[test]
+    fn word_right_goes_to_the_start_of_the_next_word_or_the_end() {
+        let mut input = InputPane::new();
+        input.type_string("cast  magic missile".into());
+        input.home();
+
+        input.word_right();
+        assert_eq!(input.cursor_position(), 6);
+
+        input.word_right();
+        input.word_right();
+        assert_eq!(input.cursor_position(), 19);
+    }
+
+    #[test]
+    fn with_history_keeps_the_newest_entries() {
+        let input = InputPane::with_history(vec!["a".into(), "b".into(), "c".into()], 2);
+
+        assert_eq!(input.history(), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn up_goes_through_entries_starting_with_what_was_typed() {
+        let mut input = input_with_history(&["look", "north", "lock door", "say hi"]);
+        input.type_string("lo".into());
+
+        input.up();
+        input.up();
+        input.end();
+
+        assert_eq!(input.get_and_submit(), "look");
+    }
+
+    #[test]
+    fn up_stops_at_the_oldest_entry() {
+        let mut input = input_with_history(&["a", "b"]);
+
+        input.up();
+        input.up();
+        input.up();
+
+        assert_eq!(input.get_and_submit(), "a");
+    }
+
+    #[test]
+    fn submitting_an_entry_moves_it_to_the_back() {
+        let mut input = input_with_history(&["a", "b", "c"]);
+
+        input.history_up();
+        input.history_up();
+        input.history_up();
+
+        assert_eq!(input.get_and_submit(), "a");
+        assert_eq!(input.history(), vec!["b", "c", "a"]);
+    }
+
+    fn submit_all(input: &mut InputPane, entries: &[&str]) {
+        for entry in entries {
+            input.type_string(entry.to_string());
+            input.get_and_submit();
+        }
+    }
+
+    #[test]
+    fn without_dedup_every_entry_is_kept_in_order() {
+        let mut input = input_with_history(&["a", "b"]);
+        input.set_history_config(HistoryConfig { size: 10, dedup: HistoryDedup::None });
+
+        submit_all(&mut input, &["a", "c", "c"]);
+
+        assert_eq!(input.history(), vec!["a", "b", "a", "c", "c"]);
+    }
+
+    #[test]
+    fn consecutive_dedup_only_skips_repeating_the_last_entry() {
+        let mut input = input_with_history(&["a", "b"]);
+        input.set_history_config(HistoryConfig { size: 10, dedup: HistoryDedup::Consecutive });
+
+        submit_all(&mut input, &["b", "a", "a", "b"]);
+
+        assert_eq!(input.history(), vec!["a", "b", "a", "b"]);
+    }
+
+    #[test]
+    fn history_down_past_the_newest_entry_gives_back_what_was_typed() {
+        let mut input = input_with_history(&["a", "b"]);
+        input.type_string("draft".into());
+
+        input.history_up();
+        input.history_up();
+        input.history_down();
+        input.history_down();
+
+        assert_eq!(input.get_and_submit(), "draft");
+    }
+
+    #[test]
+    fn cancel_gives_back_what_was_typed() {
+        let mut input = input_with_history(&["a", "b"]);
+        input.type_string("draft".into());
+
+        input.history_up();
+        input.cancel();
+
+        assert_eq!(input.get_and_submit(), "draft");
+    }
+
+    #[test]
+    fn search_history_finds_entries_containing_what_was_typed() {
+        let mut input = input_with_history(&["kill orc", "look", "cast fireball", "kill rat"]);
+        input.type_string("fire".into());
+
+        input.search_history();
+
+        assert_eq!(input.get_and_submit(), "cast fireball");
+    }
+
+    #[test]
+    fn search_history_again_goes_to_older_entries() {
+        let mut input = input_with_history(&["kill orc", "look", "kill rat"]);
+        input.type_string("kill".into());
+
+        input.search_history();
+        input.search_history();
+        input.search_history();
+
+        assert_eq!(input.get_and_submit(), "kill orc");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+use std::fmt;
+
+use anyhow::{Result, bail};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A key with modifiers, as bound by scripts, e.g. `alt+1` or `f2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeySpec {
+    modifiers: KeyModifiers,
+    code: KeyCode,
+}
+
+impl KeySpec {
+    /// Parse a key such as `f2`, `ctrl+k` or `alt+shift+up` (in any case): modifiers (`ctrl`, `alt`, `shift`)
+    /// followed by a character or the name of a key.
+    pub fn parse(spec: &str) -> Result<KeySpec> {
+        let spec = spec.to_lowercase();
+        let parts: Vec<_> = spec.split('+').map(str::trim).collect();
+
+        let Some((key, modifier_names)) = parts.split_last() else {
+            bail!("No key given");
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in modifier_names {
+            modifiers |= match *modifier {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => bail!("Unknown modifier {modifier:?} (expected ctrl, alt or shift)"),
+            };
+        }
+
+        let code = match *key {
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "esc" | "escape" => KeyCode::Esc,
+            "backspace" => KeyCode::Backspace,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "insert" => KeyCode::Insert,
+            "delete" => KeyCode::Delete,
+            key if key.starts_with('f') && key.len() > 1 => match key[1..].parse() {
+                Ok(number @ 1..=24) => KeyCode::F(number),
+                _ => bail!("Unknown key {key:?}"),
+            },
+            key => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => KeyCode::Char(ch),
+                    _ => bail!("Unknown key {key:?}"),
+                }
+            },
+        };
+
+        Ok(KeySpec::new(modifiers, code))
+    }
+
+    /// Terminals report characters typed with Shift in uppercase, so those are kept that way.
+    fn new(modifiers: KeyModifiers, code: KeyCode) -> KeySpec {
+        let code = match code {
+            KeyCode::Char(ch) if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::Char(ch.to_ascii_uppercase()),
+            code => code,
+        };
+
+        KeySpec { modifiers, code }
+    }
+}
+
+/// Written the way `parse` reads it, e.g. `ctrl+alt+f1`.
+impl fmt::Display for KeySpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in [(KeyModifiers::CONTROL, "ctrl"), (KeyModifiers::ALT, "alt"), (KeyModifiers::SHIFT, "shift")] {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{name}+")?;
+            }
+        }
+
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(ch) => write!(f, "{}", ch.to_lowercase()),
+            KeyCode::F(number) => write!(f, "f{number}"),
+            KeyCode::Esc => write!(f, "esc"),
+            KeyCode::PageUp => write!(f, "pageup"),
+            KeyCode::PageDown => write!(f, "pagedown"),
+            code => write!(f, "{}", format!("{code:?}").to_lowercase()),
+        }
+    }
+}
+
+impl From<KeyEvent> for KeySpec {
+    fn from(key: KeyEvent) -> KeySpec {
+        KeySpec::new(key.modifiers, key.code)
+    }
+}
@@ -1,13 +1,19 @@
 use std::any::type_name;
+use std::fmt;
+use std::rc::Rc;
 use rhai::{Map, Dynamic};
 use anyhow::{Context, Result, bail, anyhow};
 use ratatui::prelude::*;
 
 use crate::tui::{
     input::*,
-    panes::*
+    panes::*,
+    theme::{Theme, parse_color},
 };
 
+/// Smallest size (in rows or columns) a pane can be resized to, i.e. its top bar and a line of text.
+const MIN_PANE_SIZE: u16 = 2;
+
 pub enum LayoutElement {
     VerticalStack {
         children: Vec<LayoutElement>,
@@ -20,10 +26,12 @@ pub enum LayoutElement {
     Pane(LayoutPane),
 }
 
+#[allow(clippy::enum_variant_names)] // Named after the pane types
 pub enum LayoutPane {
     ScrollPane { id: Option<usize>, pane: ScrollPane, },
-    // StaticPane { id: Option<usize>, pane: StaticPane, },
-    InputPane(InputPane),
+    StaticPane { id: Option<usize>, pane: StaticPane, },
+    /* Boxed, as an input pane is much larger than the other kinds */
+    InputPane(Box<InputPane>),
 }
 
 impl LayoutElement {
@@ -56,12 +64,43 @@ impl LayoutElement {
                     None
                 };
 
-                Ok(LayoutElement::Pane(LayoutPane::ScrollPane {
-                    id,
-                    pane: ScrollPane::new(1000)
-                }))
+                let capacity = if let Some(capacity) = layout.get("capacity") {
+                    let capacity = capacity.as_int()
+                        .map_err(|err| anyhow!(err))
+                        .context("Parse pane capacity as int")?;
+
+                    if capacity < 1 {
+                        bail!("Pane capacity must be positive, got {capacity}");
+                    }
+
+                    capacity as usize
+                } else {
+                    ScrollPane::DEFAULT_CAPACITY
+                };
+
+                let border = if let Some(border) = layout.get("border") {
+                    border.as_bool()
+                        .map_err(|err| anyhow!(err))
+                        .context("Parse pane border as bool")?
+                } else {
+                    true
+                };
+
+                let title = layout.get("title").map(|title| title.to_string());
+
+                let border_color = if let Some(color) = layout.get("border_color") {
+                    Some(parse_color(&color.to_string())
+                        .context("Parse pane border color")?)
+                } else {
+                    None
+                };
+
+                let mut pane = ScrollPane::new(capacity);
+                pane.set_top_bar(border, title, border_color);
+
+                Ok(LayoutElement::Pane(LayoutPane::ScrollPane { id, pane }))
             },
-            /*"static" => {
+            "static" => {
                 let id = if let Some(id) = layout.get("id") {
                     Some(id.as_int()
                         .map_err(|err| anyhow!(err))
@@ -72,11 +111,16 @@ impl LayoutElement {
 
                 Ok(LayoutElement::Pane(LayoutPane::StaticPane {
                     id,
-                    pane: StaticPane {},
+                    pane: StaticPane::new(),
                 }))
-            },*/
+            },
             "input" => {
-                Ok(LayoutElement::Pane(LayoutPane::InputPane(InputPane::new())))
+                let target = layout.get("target").map(|target| target.to_string());
+
+                let mut pane = InputPane::new();
+                pane.set_target(target);
+
+                Ok(LayoutElement::Pane(LayoutPane::InputPane(Box::new(pane))))
             }
             _ => {
                 bail!("Invalid layout element type: {element_type}");
@@ -84,41 +128,249 @@ impl LayoutElement {
         }
     }
 
-    pub fn render(&mut self, frame: &mut Frame<'_>, area: Rect, active_pane: usize) {
+    /// Create a copy of the layout with the same structure, but with fresh (empty) panes.
+    pub fn empty_copy(&self) -> LayoutElement {
         match self {
             LayoutElement::VerticalStack { children, constraints } => {
-                let chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints(constraints.clone())
-                    .split(area);
-
-                for (i, child) in children.iter_mut().enumerate() {
-                    child.render(frame, chunks[i], active_pane);
+                LayoutElement::VerticalStack {
+                    children: children.iter().map(|child| child.empty_copy()).collect(),
+                    constraints: constraints.clone(),
                 }
             },
             LayoutElement::HorizontalStack { children, constraints } => {
-                let chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints(constraints.clone())
-                    .split(area);
+                LayoutElement::HorizontalStack {
+                    children: children.iter().map(|child| child.empty_copy()).collect(),
+                    constraints: constraints.clone(),
+                }
+            },
+            LayoutElement::Pane(LayoutPane::ScrollPane { id, pane }) => {
+                LayoutElement::Pane(LayoutPane::ScrollPane { id: *id, pane: pane.empty_copy() })
+            },
+            LayoutElement::Pane(LayoutPane::StaticPane { id, pane: _ }) => {
+                LayoutElement::Pane(LayoutPane::StaticPane { id: *id, pane: StaticPane::new() })
+            },
+            LayoutElement::Pane(LayoutPane::InputPane(input_pane)) => {
+                let mut pane = InputPane::new();
+                pane.set_target(input_pane.target());
+
+                LayoutElement::Pane(LayoutPane::InputPane(Box::new(pane)))
+            },
+        }
+    }
+
+    /// Write the element in the script map form that `from` accepts.
+    fn write_map(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        let (element_type, children, constraints) = match self {
+            LayoutElement::VerticalStack { children, constraints } => ("vstack", children, constraints),
+            LayoutElement::HorizontalStack { children, constraints } => ("hstack", children, constraints),
+            LayoutElement::Pane(LayoutPane::ScrollPane { id, pane }) => {
+                write!(f, "#{{ type: \"scroll\"")?;
+
+                if let Some(id) = id {
+                    write!(f, ", id: {id}")?;
+                }
+
+                if pane.capacity() != ScrollPane::DEFAULT_CAPACITY {
+                    write!(f, ", capacity: {}", pane.capacity())?;
+                }
+
+                write!(f, "{}", pane.top_bar_properties())?;
+
+                return write!(f, " }}");
+            },
+            LayoutElement::Pane(LayoutPane::StaticPane { id: Some(id), pane: _ }) => {
+                return write!(f, "#{{ type: \"static\", id: {id} }}");
+            },
+            LayoutElement::Pane(LayoutPane::StaticPane { id: None, pane: _ }) => {
+                return write!(f, "#{{ type: \"static\" }}");
+            },
+            LayoutElement::Pane(LayoutPane::InputPane(input_pane)) => {
+                if let Some(target) = input_pane.target() {
+                    return write!(f, "#{{ type: \"input\", target: {target:?} }}");
+                }
+
+                return write!(f, "#{{ type: \"input\" }}");
+            },
+        };
+
+        let padding = "    ".repeat(indent + 1);
+
+        writeln!(f, "#{{")?;
+        writeln!(f, "{padding}type: \"{element_type}\",")?;
+
+        let constraints: Vec<_> = constraints.iter().map(constraint_to_string).collect();
+        writeln!(f, "{padding}constraints: [{}],", constraints.join(", "))?;
+
+        writeln!(f, "{padding}children: [")?;
+        for child in children {
+            write!(f, "{padding}    ")?;
+            child.write_map(f, indent + 2)?;
+            writeln!(f, ",")?;
+        }
+        writeln!(f, "{padding}],")?;
+
+        write!(f, "{}}}", "    ".repeat(indent))
+    }
 
-                for (i, child) in children.iter_mut().enumerate() {
-                    child.render(frame, chunks[i], active_pane);
+    pub fn render(&mut self, frame: &mut Frame<'_>, area: Rect, active_pane: usize, theme: &Theme) {
+        match self {
+            LayoutElement::VerticalStack { .. } | LayoutElement::HorizontalStack { .. } => {
+                let chunks = self.split(area);
+
+                /* Layouts are validated when parsed, but never index past the chunks in case they are built otherwise */
+                for (child, chunk) in self.children_mut().iter_mut().zip(chunks.iter()) {
+                    child.render(frame, *chunk, active_pane, theme);
                 }
             },
             LayoutElement::Pane(pane) => match pane {
                 LayoutPane::ScrollPane { id, pane } => {
-                    pane.render(frame, area, *id, *id == Some(active_pane));
+                    pane.render(frame, area, *id, *id == Some(active_pane), theme);
                 },
                 LayoutPane::InputPane(input_pane) => {
-                    input_pane.render(frame, area);
+                    input_pane.render(frame, area, theme);
+                },
+                LayoutPane::StaticPane { id, pane } => {
+                    pane.render(frame, area, *id, theme);
                 },
-                // LayoutPane::StaticPane { id: _, pane: _ } => { /* TODO */},
             },
         }
 
     }
 
+    /// Areas of a stack's children, as rendered in `area`.
+    fn split(&self, area: Rect) -> Rc<[Rect]> {
+        match self {
+            LayoutElement::VerticalStack { children, constraints } => {
+                /* Let panes that need more room (e.g. multi-line input) grow */
+                let constraints: Vec<_> = constraints.iter()
+                    .enumerate()
+                    .map(|(i, constraint)| children.get(i)
+                        .and_then(|child| child.desired_height())
+                        .map_or(*constraint, Constraint::Min))
+                    .collect();
+
+                Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints(constraints)
+                    .split(area)
+            },
+            LayoutElement::HorizontalStack { children: _, constraints } => {
+                Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints(constraints.clone())
+                    .split(area)
+            },
+            LayoutElement::Pane(_) => Rc::new([area]),
+        }
+    }
+
+    fn children_mut(&mut self) -> &mut [LayoutElement] {
+        match self {
+            LayoutElement::VerticalStack { children, constraints: _ } => children,
+            LayoutElement::HorizontalStack { children, constraints: _ } => children,
+            LayoutElement::Pane(_) => &mut [],
+        }
+    }
+
+    /// Grow (or, with a negative `delta`, shrink) the pane with the given id by `delta` rows or columns,
+    /// depending on `direction`, by changing the constraint of the innermost stack in that direction containing it.
+    ///
+    /// The constraint becomes a fixed length, based on the size the pane had in `area`.
+    /// Returns `false` if there is no such pane, or no stack to resize it in.
+    pub fn resize_pane(&mut self, area: Rect, pane_id: usize, direction: Direction, delta: i32) -> bool {
+        let stack_direction = match self {
+            LayoutElement::VerticalStack { .. } => Direction::Vertical,
+            LayoutElement::HorizontalStack { .. } => Direction::Horizontal,
+            LayoutElement::Pane(_) => { return false; },
+        };
+
+        let chunks = self.split(area);
+        let children = self.children_mut();
+
+        let Some(index) = children.iter_mut().position(|child| child.pane(pane_id).is_some()) else {
+            return false;
+        };
+
+        let Some(chunk) = chunks.get(index) else {
+            return false;
+        };
+
+        if children[index].resize_pane(*chunk, pane_id, direction, delta) {
+            return true;
+        }
+
+        if stack_direction != direction {
+            return false;
+        }
+
+        let (size, available) = match direction {
+            Direction::Vertical => (chunk.height, area.height),
+            Direction::Horizontal => (chunk.width, area.width),
+        };
+
+        /* Leave every other child some room, so that no pane can vanish */
+        let max_size = available.saturating_sub(MIN_PANE_SIZE * (children.len() as u16 - 1)).max(MIN_PANE_SIZE);
+        let size = (size as i32 + delta).clamp(MIN_PANE_SIZE as i32, max_size as i32) as u16;
+
+        if let LayoutElement::VerticalStack { children: _, constraints }
+            | LayoutElement::HorizontalStack { children: _, constraints } = self {
+            if let Some(constraint) = constraints.get_mut(index) {
+                *constraint = Constraint::Length(size);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Set the constraint of the slot holding the pane with the given id in its stack, keeping the rest of the layout.
+    ///
+    /// Returns `false` if there is no such pane.
+    pub fn set_pane_constraint(&mut self, pane_id: usize, constraint: Constraint) -> Result<bool> {
+        let (LayoutElement::VerticalStack { children, constraints }
+            | LayoutElement::HorizontalStack { children, constraints }) = self else {
+            return Ok(false);
+        };
+
+        if let Some(index) = children.iter().position(|child| child.pane_id() == Some(pane_id)) {
+            let mut updated = constraints.clone();
+            updated[index] = constraint;
+
+            validate_container(children, &updated)
+                .context("Validate container")?;
+
+            *constraints = updated;
+            return Ok(true);
+        }
+
+        for child in children {
+            if child.set_pane_constraint(pane_id, constraint)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Id of the element, if it is a pane which has one.
+    fn pane_id(&self) -> Option<usize> {
+        match self {
+            LayoutElement::Pane(LayoutPane::ScrollPane { id, pane: _ }) => *id,
+            LayoutElement::Pane(LayoutPane::StaticPane { id, pane: _ }) => *id,
+            _ => None,
+        }
+    }
+
+    /// Height the element needs, if it is more than what its constraint says.
+    fn desired_height(&self) -> Option<u16> {
+        match self {
+            LayoutElement::Pane(LayoutPane::InputPane(input_pane)) if input_pane.is_multiline() || input_pane.height() > 1 => {
+                Some(input_pane.height() + 1 /* top bar */)
+            },
+            _ => None,
+        }
+    }
+
     pub fn pane(&mut self, pane_id: usize) -> Option<&mut ScrollPane> {
         match self {
             LayoutElement::HorizontalStack { children, constraints: _ } => {
@@ -134,20 +386,94 @@ impl LayoutElement {
         }
     }
 
-    pub fn input(&mut self) -> Option<&mut InputPane> {
+    /// Take the scroll panes which have ids out of the layout, e.g. to carry their buffers over to a new one.
+    pub fn into_panes(self) -> Vec<(usize, ScrollPane)> {
         match self {
             LayoutElement::HorizontalStack { children, constraints: _ } => {
-                children.iter_mut().find_map(|child| child.input())
+                children.into_iter().flat_map(|child| child.into_panes()).collect()
             },
             LayoutElement::VerticalStack { children, constraints: _ } => {
-                children.iter_mut().find_map(|child| child.input())
+                children.into_iter().flat_map(|child| child.into_panes()).collect()
             },
-            LayoutElement::Pane(LayoutPane::InputPane(input_pane)) => {
-                Some(input_pane)
+            LayoutElement::Pane(LayoutPane::ScrollPane { id: Some(id), pane }) => {
+                vec![(id, pane)]
+            },
+            _ => { vec![] },
+        }
+    }
+
+    pub fn static_pane(&mut self, pane_id: usize) -> Option<&mut StaticPane> {
+        match self {
+            LayoutElement::HorizontalStack { children, constraints: _ } => {
+                children.iter_mut().find_map(|child| child.static_pane(pane_id))
+            },
+            LayoutElement::VerticalStack { children, constraints: _ } => {
+                children.iter_mut().find_map(|child| child.static_pane(pane_id))
+            },
+            LayoutElement::Pane(LayoutPane::StaticPane { id: Some(id), pane }) if pane_id == *id => {
+                Some(pane)
             },
             _ => { None },
         }
     }
+
+    /// The scroll pane last rendered at a position on the screen.
+    pub fn pane_at(&mut self, column: u16, row: u16) -> Option<&mut ScrollPane> {
+        match self {
+            LayoutElement::HorizontalStack { children, constraints: _ } => {
+                children.iter_mut().find_map(|child| child.pane_at(column, row))
+            },
+            LayoutElement::VerticalStack { children, constraints: _ } => {
+                children.iter_mut().find_map(|child| child.pane_at(column, row))
+            },
+            LayoutElement::Pane(LayoutPane::ScrollPane { id: _, pane }) if pane.contains(column, row) => {
+                Some(pane)
+            },
+            _ => { None },
+        }
+    }
+
+    /// Ids of the scroll panes that have one, in layout order.
+    pub fn pane_ids(&self) -> Vec<usize> {
+        match self {
+            LayoutElement::HorizontalStack { children, constraints: _ }
+                | LayoutElement::VerticalStack { children, constraints: _ } => {
+                children.iter().flat_map(|child| child.pane_ids()).collect()
+            },
+            LayoutElement::Pane(LayoutPane::ScrollPane { id: Some(id), pane: _ }) => vec![*id],
+            _ => vec![],
+        }
+    }
+
+    /// Id of the scroll pane last rendered at a position on the screen, if it has one.
+    pub fn pane_id_at(&self, column: u16, row: u16) -> Option<usize> {
+        match self {
+            LayoutElement::HorizontalStack { children, constraints: _ }
+                | LayoutElement::VerticalStack { children, constraints: _ } => {
+                children.iter().find_map(|child| child.pane_id_at(column, row))
+            },
+            LayoutElement::Pane(LayoutPane::ScrollPane { id, pane }) if pane.contains(column, row) => *id,
+            _ => None,
+        }
+    }
+
+    /// Input panes, in layout order.
+    pub fn inputs(&mut self) -> Vec<&mut InputPane> {
+        match self {
+            LayoutElement::HorizontalStack { children, constraints: _ }
+                | LayoutElement::VerticalStack { children, constraints: _ } => {
+                children.iter_mut().flat_map(|child| child.inputs()).collect()
+            },
+            LayoutElement::Pane(LayoutPane::InputPane(input_pane)) => vec![input_pane.as_mut()],
+            _ => vec![],
+        }
+    }
+}
+
+impl fmt::Display for LayoutElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_map(f, 0)
+    }
 }
 
 fn parse_container(layout: Map) -> Result<(Vec<LayoutElement>, Vec<Constraint>)> {
@@ -163,9 +489,36 @@ fn parse_container(layout: Map) -> Result<(Vec<LayoutElement>, Vec<Constraint>)>
         create_constraint)
         .context("Parse container's constraints")?;
 
+    validate_container(&children, &constraints)
+        .context("Validate container")?;
+
     Ok((children, constraints))
 }
 
+/// Reject containers that could not be rendered as intended, as each child is rendered in the area of its constraint.
+fn validate_container(children: &[LayoutElement], constraints: &[Constraint]) -> Result<()> {
+    if children.is_empty() {
+        bail!("Container has no children");
+    }
+
+    if children.len() != constraints.len() {
+        bail!("Container has {} children, but {} constraints (there must be one per child)", children.len(), constraints.len());
+    }
+
+    let percentage: u32 = constraints.iter()
+        .map(|constraint| match constraint {
+            Constraint::Percentage(value) => *value as u32,
+            _ => 0,
+        })
+        .sum();
+
+    if percentage > 100 {
+        bail!("Percentage constraints add up to {percentage}%, which is over 100%");
+    }
+
+    Ok(())
+}
+
 fn get_array_property<T>(layout: &Map, property_name: &str, mapper: impl Fn(&Dynamic) -> Result<T>) -> Result<Vec<T>> {
     let items: Vec<_> = layout.get(property_name)
         .convert(format!("property \"{property_name}\"").as_str())?;
@@ -191,38 +544,96 @@ fn create_layout_element(item: &Dynamic) -> Result<LayoutElement> {
 }
 
 
-fn create_constraint(item: &Dynamic) -> Result<Constraint> {
+/// Parse a constraint given as an array of its type and values, e.g. `["length", 10]` or `["ratio", 1, 3]`.
+///
+/// `fill` (without values) takes whatever room is left, i.e. it is the same as `["min", 0]`.
+pub fn create_constraint(item: &Dynamic) -> Result<Constraint> {
     let constraint: Vec<_> = Some(item)
         .convert("constraint")?;
 
-    let constraint_type: String = constraint.get(0)
+    let constraint_type: String = constraint.first()
         .convert("constraint type")?;
 
     match constraint_type.as_str() {
         "max" => {
-            let max_value: i64 = constraint.get(1)
-                .convert("constraint max value")?;
+            expect_values(&constraint, 1)?;
+            let max_value = constraint_value(&constraint, 1, "constraint max value")?;
 
-            Ok(Constraint::Max(max_value as u16))
+            Ok(Constraint::Max(max_value))
         },
         "min" => {
-            let min_value: i64 = constraint.get(1)
-                .convert("constraint min value")?;
+            expect_values(&constraint, 1)?;
+            let min_value = constraint_value(&constraint, 1, "constraint min value")?;
 
-            Ok(Constraint::Min(min_value as u16))
+            Ok(Constraint::Min(min_value))
         }
         "percentage" => {
-            let prc_value: i64 = constraint.get(1)
-                .convert("constraint percentage value")?;
+            expect_values(&constraint, 1)?;
+            let prc_value = constraint_value(&constraint, 1, "constraint percentage value")?;
+
+            if prc_value > 100 {
+                bail!("Percentage constraint of {prc_value}% is over 100%");
+            }
+
+            Ok(Constraint::Percentage(prc_value))
+        }
+        "length" => {
+            expect_values(&constraint, 1)?;
+            let length_value = constraint_value(&constraint, 1, "constraint length value")?;
+
+            Ok(Constraint::Length(length_value))
+        }
+        "ratio" => {
+            expect_values(&constraint, 2)?;
+            let numerator = constraint_value(&constraint, 1, "constraint ratio numerator")?;
+            let denominator = constraint_value(&constraint, 2, "constraint ratio denominator")?;
+
+            if denominator == 0 {
+                bail!("Ratio constraint has a zero denominator");
+            }
 
-            Ok(Constraint::Percentage(prc_value as u16))
+            Ok(Constraint::Ratio(numerator as u32, denominator as u32))
+        }
+        "fill" => {
+            expect_values(&constraint, 0)?;
+
+            Ok(Constraint::Min(0))
         }
         _ => {
-            bail!("Invalid constraint type: {}", constraint_type);
+            bail!("Invalid constraint type: {constraint_type} (expected max, min, percentage, length, ratio or fill)");
         }
     }
 }
 
+/// Check the number of values given after the type of a constraint.
+fn expect_values(constraint: &[Dynamic], count: usize) -> Result<()> {
+    if constraint.len() != count + 1 {
+        bail!("Constraint {} takes {count} value(s), but {} were given", constraint[0], constraint.len() - 1);
+    }
+
+    Ok(())
+}
+
+/// A value of a constraint, which has to be a whole number of rows or columns (or a percentage).
+fn constraint_value(constraint: &[Dynamic], index: usize, what: &str) -> Result<u16> {
+    let value: i64 = constraint.get(index)
+        .convert(what)?;
+
+    u16::try_from(value)
+        .context(format!("{what} must be between 0 and {}, got {value}", u16::MAX))
+}
+
+/// Inverse of `create_constraint`.
+fn constraint_to_string(constraint: &Constraint) -> String {
+    match constraint {
+        Constraint::Max(value) => format!("[\"max\", {value}]"),
+        Constraint::Min(value) => format!("[\"min\", {value}]"),
+        Constraint::Percentage(value) => format!("[\"percentage\", {value}]"),
+        Constraint::Length(value) => format!("[\"length\", {value}]"),
+        Constraint::Ratio(numerator, denominator) => format!("[\"ratio\", {numerator}, {denominator}]"),
+    }
+}
+
 trait DynamicExt {
     fn convert<T: Clone + 'static>(self, what: &str) -> Result<T>;
 }
@@ -237,4 +648,137 @@ impl DynamicExt for Option<&Dynamic> {
             .try_cast::<T>()
             .context(format!("Get {what} as {}", type_name::<T>()))
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(values: Vec<Dynamic>) -> Result<Constraint> {
+        create_constraint(&Dynamic::from_array(values))
+    }
+
+    fn two_panes() -> LayoutElement {
+        let pane = |id| LayoutElement::Pane(LayoutPane::ScrollPane { id: Some(id), pane: ScrollPane::new(10) });
+
+        LayoutElement::VerticalStack {
+            children: vec![pane(1), pane(2)],
+            constraints: vec![Constraint::Percentage(50), Constraint::Percentage(50)],
+        }
+    }
+
+    fn parse(script: &str) -> Result<LayoutElement> {
+        LayoutElement::from(rhai::Engine::new().eval::<Map>(script).unwrap())
+    }
+
+    fn constraints(layout: &LayoutElement) -> Vec<Constraint> {
+        match layout {
+            LayoutElement::VerticalStack { children: _, constraints }
+                | LayoutElement::HorizontalStack { children: _, constraints } => constraints.clone(),
+            LayoutElement::Pane(_) => vec![],
+        }
+    }
+
+    #[test]
+    fn create_constraint_parses_each_type() {
+        assert_eq!(constraint(vec!["max".into(), 10_i64.into()]).unwrap(), Constraint::Max(10));
+        assert_eq!(constraint(vec!["min".into(), 3_i64.into()]).unwrap(), Constraint::Min(3));
+        assert_eq!(constraint(vec!["percentage".into(), 30_i64.into()]).unwrap(), Constraint::Percentage(30));
+        assert_eq!(constraint(vec!["length".into(), 5_i64.into()]).unwrap(), Constraint::Length(5));
+        assert_eq!(constraint(vec!["ratio".into(), 1_i64.into(), 3_i64.into()]).unwrap(), Constraint::Ratio(1, 3));
+        assert_eq!(constraint(vec!["fill".into()]).unwrap(), Constraint::Min(0));
+    }
+
+    #[test]
+    fn create_constraint_rejects_invalid_values() {
+        assert!(constraint(vec!["percentage".into(), 101_i64.into()]).is_err());
+        assert!(constraint(vec!["ratio".into(), 1_i64.into(), 0_i64.into()]).is_err());
+        assert!(constraint(vec!["length".into(), (-1_i64).into()]).is_err());
+        assert!(constraint(vec!["length".into()]).is_err());
+        assert!(constraint(vec!["fill".into(), 1_i64.into()]).is_err());
+        assert!(constraint(vec!["huge".into(), 1_i64.into()]).is_err());
+    }
+
+    #[test]
+    fn resize_pane_fixes_the_length_of_its_slot() {
+        let mut layout = two_panes();
+
+        assert!(layout.resize_pane(Rect::new(0, 0, 80, 20), 1, Direction::Vertical, 3));
+
+        assert_eq!(constraints(&layout)[0], Constraint::Length(13));
+    }
+
+    #[test]
+    fn resize_pane_leaves_room_for_the_other_panes() {
+        let area = Rect::new(0, 0, 80, 20);
+        let mut layout = two_panes();
+
+        layout.resize_pane(area, 1, Direction::Vertical, 100);
+        assert_eq!(constraints(&layout)[0], Constraint::Length(20 - MIN_PANE_SIZE));
+
+        layout.resize_pane(area, 1, Direction::Vertical, -100);
+        assert_eq!(constraints(&layout)[0], Constraint::Length(MIN_PANE_SIZE));
+    }
+
+    #[test]
+    fn resize_pane_needs_a_stack_in_its_direction() {
+        let area = Rect::new(0, 0, 80, 20);
+        let mut layout = two_panes();
+
+        assert!(!layout.resize_pane(area, 1, Direction::Horizontal, 3));
+        assert!(!layout.resize_pane(area, 3, Direction::Vertical, 3));
+        assert_eq!(constraints(&layout), vec![Constraint::Percentage(50), Constraint::Percentage(50)]);
+    }
+
+    #[test]
+    fn containers_need_a_constraint_per_child() {
+        let more_children = parse(r#"#{ type: "vstack", constraints: [["fill"]],
+            children: [#{ type: "scroll", id: 1 }, #{ type: "input" }] }"#);
+        let more_constraints = parse(r#"#{ type: "hstack", constraints: [["fill"], ["length", 10]],
+            children: [#{ type: "scroll", id: 1 }] }"#);
+
+        assert!(more_children.is_err());
+        assert!(more_constraints.is_err());
+    }
+
+    #[test]
+    fn containers_need_children() {
+        assert!(parse(r#"#{ type: "vstack", constraints: [], children: [] }"#).is_err());
+    }
+
+    #[test]
+    fn a_stack_with_fewer_constraints_than_children_renders() {
+        /* Bypasses the parser, which would reject it */
+        let pane = |id| LayoutElement::Pane(LayoutPane::ScrollPane { id: Some(id), pane: ScrollPane::new(10) });
+        let mut layout = LayoutElement::VerticalStack {
+            children: vec![pane(1), pane(2), pane(3)],
+            constraints: vec![Constraint::Percentage(50), Constraint::Percentage(50)],
+        };
+
+        let mut terminal = Terminal::new(backend::TestBackend::new(40, 10)).unwrap();
+        terminal.draw(|frame| layout.render(frame, frame.size(), 1, &Theme::default())).unwrap();
+    }
+
+    #[test]
+    fn dumped_layout_parses_back_to_the_same_layout() {
+        let layout = parse(r#"#{ type: "vstack", constraints: [["fill"], ["length", 3]], children: [
+            #{ type: "hstack", constraints: [["percentage", 70], ["ratio", 1, 3]], children: [
+                #{ type: "scroll", id: 1, capacity: 5000, title: "Main" },
+                #{ type: "scroll", id: 2, border_color: "cyan" },
+            ] },
+            #{ type: "input", target: "chat" },
+        ] }"#).unwrap();
+
+        let mut reparsed = parse(&layout.to_string()).unwrap();
+
+        assert_eq!(reparsed.pane_ids(), vec![1, 2]);
+        assert_eq!(constraints(&reparsed), vec![Constraint::Min(0), Constraint::Length(3)]);
+
+        let LayoutElement::VerticalStack { children, constraints: _ } = &reparsed else { panic!("Expected a vstack") };
+        assert_eq!(constraints(&children[0]), vec![Constraint::Percentage(70), Constraint::Ratio(1, 3)]);
+
+        assert_eq!(reparsed.pane(1).unwrap().capacity(), 5000);
+        assert_eq!(reparsed.pane(2).unwrap().capacity(), ScrollPane::DEFAULT_CAPACITY);
+        assert_eq!(reparsed.to_string(), layout.to_string());
+    }
 }
\ No newline at end of file
@@ -1,13 +1,24 @@
+mod capabilities;
+mod emphasis;
 mod input;
+mod keys;
 mod layout;
+mod numpad;
 mod panes;
+mod speedwalk;
+mod status;
+mod theme;
+mod urls;
 mod wrapper;
 
 use std::io::{stdout, Stdout};
 use tokio::sync::mpsc::{channel, Sender, Receiver};
+use tokio::sync::oneshot;
 use anyhow::{Context, Result};
+use regex::Regex;
 use crossterm::{
-    event::{self, KeyCode, KeyEventKind, KeyModifiers, EventStream, Event},
+    event::{self, KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers, EventStream, Event, MouseButton, MouseEventKind,
+        EnableMouseCapture, DisableMouseCapture, EnableBracketedPaste, DisableBracketedPaste},
     terminal::{
         disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
         LeaveAlternateScreen,
@@ -17,41 +28,118 @@ use crossterm::{
 use tokio_stream::StreamExt;
 use ratatui::prelude::*;
 
+use crate::pipeline::OutputLine;
+
+use capabilities::downgrade_colors;
 use input::*;
 use layout::*;
 use panes::*;
 use wrapper::*;
 
-pub use layout::LayoutElement;
+pub use capabilities::{Capabilities, ColorSupport};
+pub use emphasis::Emphasis;
+pub use input::{HistoryConfig, HistoryDedup};
+pub use keys::KeySpec;
+pub use layout::{LayoutElement, create_constraint};
+pub use numpad::NumpadDirections;
+pub use theme::{Theme, parse_color};
 
 pub enum TuiRequest {
     Print(String, usize),
+    /// Server output, as kept by the output pipeline.
+    PrintOutput(Vec<OutputLine>),
     PrintUserInput(String, usize),
     PrintInfo(String, usize),
     PrintWarning(String, usize),
     PrintError(String, usize),
     SetLayout(LayoutElement),
+    RegisterLayout(String, LayoutElement),
+    UseLayout(String),
+    SetTheme(Theme),
+    SetCollapseDuplicates(usize, bool),
+    SetScrollLock(usize, bool),
+    DumpLayout,
+    SetQueuedCommands(usize),
+    /// Text of the status bar, e.g. the connection state.
+    SetStatus(String),
+    /// Show the status bar at the bottom of the screen (or hide it, giving its row to the layout).
+    SetStatusBar(bool),
+    /// Show the server's prompt (possibly with ANSI codes) by the input.
+    SetServerPrompt(String),
+    /// Mask the input and send it as a secret (e.g. while logging in), or stop doing so.
+    SetSecretInput(bool),
+    /// Whether the server echoes input itself (e.g. while asking for a password): secret mode is turned on
+    /// while it does, and only turned off afterwards if it was turned on this way.
+    SetServerEcho(bool),
+    /// Show the server's prompt on a line of its own above the input, rather than in its title.
+    SetPromptLine(bool),
+    SetMaxLineWidth(usize, Option<usize>),
+    /// Prefix the lines of a pane with the time they were received.
+    SetTimestamps(usize, bool),
+    /// Change how many lines a pane keeps, dropping the oldest ones if they no longer fit.
+    SetScrollback(usize, usize),
+    /// Reply with the scroll offset and buffer size of a pane, or `None` if there is no such pane.
+    GetScrollInfo(usize, oneshot::Sender<Option<(usize, usize)>>),
+    ScrollTo(usize, usize),
+    /// Change the constraint of a pane in its stack, leaving the rest of the layout as it is.
+    SetPaneConstraint(usize, Constraint),
+    /// Replace the content of a static pane with text (possibly with ANSI codes).
+    SetPane(usize, String),
+    /// Write the lines of a pane to a file as plain text.
+    SaveBuffer(usize, String),
+    SetClickSend(bool),
+    /// Set the part of a pane's height scrolled by PageUp/PageDown.
+    SetScrollFraction(f64),
+    /// Ask for confirmation before sending user input matching a pattern.
+    AddGuard(Regex),
+    RemoveGuard(String),
+    ListGuards,
+    /// List the URLs recently seen in output, numbered from the newest.
+    ListUrls,
+    /// Open one of the URLs recently seen in output, by its number in `ListUrls`.
+    OpenUrl(usize),
+    /// Expand speedwalks (e.g. `3n2e`) typed as input into separate movement commands.
+    SetSpeedwalk(bool),
+    /// Style plain-text emphasis in output (or stop doing so with `None`).
+    SetEmphasis(Option<Emphasis>),
+    /// Walk with the numpad (or stop doing so with `None`).
+    SetNumpad(Option<NumpadDirections>),
+    /// Report presses of a key to the script engine, with a description of what it does for the F1 help
+    /// (or stop doing so if `None`), unless it has a built-in function.
+    SetKeyBinding(KeySpec, Option<String>),
 }
 
 pub enum TuiEvent {
-    Send(String),
+    /// User input, with the target of the input pane it was typed in (`None` for commands to the server).
+    Send(String, Option<String>),
     SendSecret(String),
+    /// The terminal now has this many columns and rows.
+    WindowResize(u16, u16),
+    /// A key bound by a script was pressed.
+    KeyPressed(KeySpec),
     Quit,
 }
 
-pub async fn create_tui() -> Result<(Sender<TuiRequest>, Receiver<TuiEvent>)> {
+pub async fn create_tui(history_config: HistoryConfig, capabilities: Capabilities) -> Result<(Sender<TuiRequest>, Receiver<TuiEvent>)> {
     let (req_tx, mut req_rx) = channel(256);
     let (ev_tx, ev_rx) = channel(256);
 
-    let mut terminal = init_terminal()
+    let mut terminal = init_terminal(capabilities.mouse)
         .context("Initialize terminal")?;
 
     install_panic_hook();
 
     terminal.clear()?;
 
+    /* Later changes are reported as they happen, but the server may ask before any */
+    let size = terminal.size()
+        .context("Get terminal size")?;
+
+    ev_tx.send(TuiEvent::WindowResize(size.width, size.height)).await
+        .context("Report initial window size")?;
+
     tokio::spawn(async move {
-        let mut tui = TuiWrapper::new(terminal, ev_tx);
+        let mut tui = TuiWrapper::new(terminal, ev_tx, history_config, capabilities);
 
         let mut event_stream = EventStream::new();
 
@@ -95,14 +183,22 @@ pub async fn create_tui() -> Result<(Sender<TuiRequest>, Receiver<TuiEvent>)> {
     Ok((req_tx, ev_rx))
 }
 
-fn init_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
+/// Set up the terminal; with `mouse`, it reports mouse events (for wheel scrolling and click-send mode).
+fn init_terminal(mouse: bool) -> Result<Terminal<CrosstermBackend<Stdout>>> {
     enable_raw_mode()?;
     stdout().execute(EnterAlternateScreen)?;
+    if mouse {
+        stdout().execute(EnableMouseCapture)?;
+    }
+    /* Pasted text then comes as a whole, rather than as keys (with line breaks pressing Enter) */
+    stdout().execute(EnableBracketedPaste)?;
     let terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     Ok(terminal)
 }
 
 fn restore_terminal() -> Result<()> {
+    stdout().execute(DisableBracketedPaste)?;
+    stdout().execute(DisableMouseCapture)?;
     stdout().execute(LeaveAlternateScreen)?;
     disable_raw_mode()?;
     Ok(())
@@ -111,9 +207,10 @@ fn restore_terminal() -> Result<()> {
 fn install_panic_hook() {
     let original_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
+        stdout().execute(DisableBracketedPaste).unwrap();
+        stdout().execute(DisableMouseCapture).unwrap();
         stdout().execute(LeaveAlternateScreen).unwrap();
         disable_raw_mode().unwrap();
         original_hook(panic_info);
     }));
 }
-
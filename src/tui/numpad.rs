@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, bail};
+use rhai::Map;
+
+/// Commands sent by keys of the numpad, for walking with it.
+pub struct NumpadDirections {
+    commands: HashMap<char, String>,
+}
+
+impl Default for NumpadDirections {
+    /// The classic layout, with directions where the keys point to (8 = north, 3 = southeast etc.).
+    fn default() -> NumpadDirections {
+        let commands = [
+            ('7', "northwest"), ('8', "north"), ('9', "northeast"),
+            ('4', "west"), ('6', "east"),
+            ('1', "southwest"), ('2', "south"), ('3', "southeast"),
+        ];
+
+        NumpadDirections {
+            commands: commands.into_iter()
+                .map(|(key, command)| (key, command.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl NumpadDirections {
+    /// Create from a script map of keys to commands, e.g. `#{ "8": "n", "2": "s", "+": "up" }`.
+    pub fn from(commands: Map) -> Result<NumpadDirections> {
+        let mut result = HashMap::new();
+
+        for (key, command) in commands {
+            let mut chars = key.chars();
+
+            let (Some(key), None) = (chars.next(), chars.next()) else {
+                bail!("Numpad key must be a single character: {key}");
+            };
+
+            result.insert(key, command.to_string());
+        }
+
+        Ok(NumpadDirections { commands: result })
+    }
+
+    pub fn command(&self, key: char) -> Option<&str> {
+        self.commands.get(&key).map(String::as_str)
+    }
+}
@@ -3,80 +3,685 @@ use ratatui::{
     widgets::{*, block::*},
 };
 
+use std::io::Write;
+use std::ops::Range;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+use chrono::{DateTime, Local};
+use regex::Regex;
+use unicode_width::UnicodeWidthChar;
+
 use crate::ring::RingBuffer;
+use crate::tui::theme::Theme;
+
+/// Columns taken by the timestamp lines are prefixed with (`[HH:MM:SS] `).
+const TIMESTAMP_WIDTH: usize = 11;
+
+/// A pane showing lines set as a whole by scripts, e.g. a status bar, rather than appended output.
+pub struct StaticPane {
+    lines: Vec<Line<'static>>,
+}
+
+impl StaticPane {
+    pub fn new() -> StaticPane {
+        StaticPane { lines: vec![] }
+    }
+
+    pub fn set_content(&mut self, lines: Vec<Line<'static>>) {
+        self.lines = lines;
+    }
+
+    pub fn render(&self, frame: &mut Frame<'_>, area: Rect, id: Option<usize>, theme: &Theme) {
+        if area.width == 0 || area.height == 0 {
+            return;
+        }
+
+        let title = if let Some(id) = id {
+            Title::from(vec![
+                "[".fg(theme.border),
+                id.to_string().fg(theme.title_inactive),
+                "]".fg(theme.border),
+            ]).alignment(Alignment::Center)
+        } else {
+            Title::from("")
+        };
+
+        frame.render_widget(
+            Paragraph::new(Text::from(self.lines.clone()))
+                .block(Block::default()
+                    .title(title)
+                    .borders(Borders::TOP)
+                    .border_style(Style::default().fg(theme.border)))
+                .wrap(Wrap { trim: false }),
+            area,
+        );
+    }
+}
+
+/// What clicking a part of a line does.
+#[derive(Clone, PartialEq)]
+pub enum Link {
+    /// Send a command, e.g. from an MXP `<send>` link.
+    Command(String),
+    /// Open a URL found in the line.
+    Url(String),
+}
+
+/// A line of output as kept in a pane, with the time it was pushed.
+#[derive(Clone, PartialEq)]
+struct BufferedLine {
+    line: Line<'static>,
+    time: SystemTime,
+    /// Parts of the line (byte ranges of its text) which can be clicked.
+    links: Vec<(Range<usize>, Link)>,
+}
 
 pub struct ScrollPane {
-    buffer: RingBuffer<Line<'static>>,
+    buffer: RingBuffer<BufferedLine>,
 
     scroll_offset: usize,
 
     last_seen_area: Rect,
+
+    /// Keep showing the same lines while new output arrives, even when scrolled to the bottom.
+    locked: bool,
+
+    /// Lines were pushed since the pane was last rendered as the active one; shown as `*` in the top bar.
+    has_unread: bool,
+
+    /// Show consecutive identical lines once, with a repeat count, instead of pushing each one.
+    collapse_duplicates: bool,
+    /// The last pushed line as received (i.e. without the repeat count) and how many times it was repeated.
+    last_pushed: Option<(Line<'static>, usize)>,
+
+    /// Lines wider than this many columns are shown truncated (the buffer keeps them whole).
+    max_line_width: Option<usize>,
+
+    /// Prefix lines with the (local) time they were received.
+    show_timestamps: bool,
+
+    /// Show the top bar (with the title and id); without it, the whole pane is taken by lines.
+    border: bool,
+    /// Shown in the top bar next to the id.
+    title: Option<String>,
+    /// Color of the top bar instead of the theme's.
+    border_color: Option<Color>,
+
+    /// Text being searched for; matches are shown reversed.
+    search: Option<Regex>,
+    /// The current match, as the number of lines up from the newest one.
+    search_match: Option<usize>,
 }
 
 impl ScrollPane {
+    /// Lines kept by a pane unless its layout gives a `capacity`.
+    pub const DEFAULT_CAPACITY: usize = 2000;
+
     pub fn new(capacity: usize) -> ScrollPane {
         ScrollPane {
             buffer: RingBuffer::new(capacity),
             scroll_offset: 0,
             last_seen_area: Rect::new(0, 0, 1, 1),
+            locked: false,
+            has_unread: false,
+            collapse_duplicates: false,
+            last_pushed: None,
+            max_line_width: None,
+            show_timestamps: false,
+            border: true,
+            title: None,
+            border_color: None,
+            search: None,
+            search_match: None,
         }
     }
 
-    pub fn render(&mut self, frame: &mut Frame<'_>, area: Rect, id: Option<usize>, is_active: bool) {
-        let mut last: Vec<Line> = self.buffer
-            .iter_from_back()
-            .skip(self.scroll_offset)
-            .take(area.height as usize - 1 /* -1 for top bar */)
-            .collect();
-        last.reverse();
+    pub fn render(&mut self, frame: &mut Frame<'_>, area: Rect, id: Option<usize>, is_active: bool, theme: &Theme) {
+        self.last_seen_area = area;
+
+        /* Being shown as the active pane is taken as having been read */
+        if is_active {
+            self.has_unread = false;
+        }
 
-        let wraps: u16 = last.iter().map(|l| { (l.width().saturating_sub(1) as u16) / area.width }).sum();
+        /* Nothing fits into a degenerate area (e.g. squeezed by constraints or in a tiny terminal) */
+        if area.width == 0 || area.height == 0 {
+            return;
+        }
 
-        let title = if let Some(id) = id {
-            Title::from(vec![
-                "[".yellow(),
-                if is_active { id.to_string().white() } else { id.to_string().dark_gray() },
-                "]".yellow(),
-            ]).alignment(Alignment::Center)
-        } else {
+        let last = self.visible_lines(area, theme);
+
+        let wraps = wraps(&last, area.width);
+
+        let paragraph = Paragraph::new(Text::from(last))
+            .wrap(Wrap { trim: false })
+            .scroll((wraps, 0));
+
+        if !self.border {
+            frame.render_widget(paragraph, area);
+            return;
+        }
+
+        let border_color = self.border_color.unwrap_or(theme.border);
+        let title_color = if is_active { theme.title_active } else { theme.title_inactive };
+
+        let mut title = vec![];
+
+        if let Some(id) = id {
+            title.push(id.to_string().fg(title_color));
+        }
+
+        if let Some(text) = &self.title {
+            if !title.is_empty() {
+                title.push(" ".into());
+            }
+
+            title.push(text.clone().fg(title_color));
+        }
+
+        if self.locked {
+            title.push(" 🔒".fg(border_color));
+        }
+
+        if self.has_unread {
+            title.push(" *".fg(theme.info));
+        }
+
+        let title = if title.is_empty() {
             Title::from("")
+        } else {
+            title.insert(0, "[".fg(border_color));
+            title.push("]".fg(border_color));
+
+            Title::from(title).alignment(Alignment::Center)
         };
 
         frame.render_widget(
-            Paragraph::new(Text::from(last))
+            paragraph
                 .block(Block::default()
                     .title(title)
                     .borders(Borders::TOP)
-                    .border_style(Style::default().fg(Color::Yellow)))
-                .wrap(Wrap { trim: false })
-                .scroll((wraps, 0)),
+                    .border_style(Style::default().fg(border_color))),
             area,
         );
+    }
 
-        self.last_seen_area = area;
+    /// Lines shown in `area` (oldest first), as displayed.
+    fn visible_lines(&self, area: Rect, theme: &Theme) -> Vec<Line<'static>> {
+        self.visible_entries(area).iter()
+            .map(|entry| self.display_line(entry, theme))
+            .collect()
+    }
+
+    /// Buffered lines shown in `area`, oldest first.
+    fn visible_entries(&self, area: Rect) -> Vec<BufferedLine> {
+        let mut last: Vec<BufferedLine> = self.buffer
+            .iter_from_back()
+            .skip(self.scroll_offset)
+            .take(area.height.saturating_sub(self.top_bar_height()) as usize)
+            .collect();
+        last.reverse();
+
+        last
+    }
+
+    /// A buffered line as displayed, i.e. truncated and timestamped as configured.
+    fn display_line(&self, entry: &BufferedLine, theme: &Theme) -> Line<'static> {
+        let mut line = match self.max_line_width {
+            Some(max_width) => truncate_line(entry.line.clone(), max_width, theme.dim),
+            None => entry.line.clone(),
+        };
+
+        if let Some(search) = &self.search {
+            line = restyle_matches(line, search, Style::default().add_modifier(Modifier::REVERSED));
+        }
+
+        /* Being part of the line, the timestamp is only shown on its first row and counted when wrapping */
+        if self.show_timestamps {
+            let time = DateTime::<Local>::from(entry.time).format("[%H:%M:%S] ");
+            line.spans.insert(0, time.to_string().fg(theme.dim));
+        }
+
+        line
+    }
+
+    /// Whether the pane was last rendered at a position on the screen.
+    pub fn contains(&self, column: u16, row: u16) -> bool {
+        let area = self.last_seen_area;
+
+        column >= area.left() && column < area.right() && row >= area.top() && row < area.bottom()
+    }
+
+    /// Plain text of the line displayed at a row of the screen (without a timestamp), if any.
+    pub fn line_at(&self, row: u16) -> Option<String> {
+        self.entry_at(self.last_seen_area.left(), row)
+            .map(|(entry, _)| line_text(&entry.line))
+    }
+
+    /// The link displayed at a position of the screen, if any.
+    pub fn link_at(&self, column: u16, row: u16) -> Option<Link> {
+        let (entry, mut offset) = self.entry_at(column, row)?;
+
+        if self.show_timestamps {
+            offset = offset.checked_sub(TIMESTAMP_WIDTH)?;
+        }
+
+        /* Find the character at that many columns into the line */
+        let text = line_text(&entry.line);
+        let (position, _) = text.char_indices()
+            .scan(0, |width, (position, ch)| {
+                *width += ch.width().unwrap_or(0);
+                Some((position, *width))
+            })
+            .find(|&(_, width)| width > offset)?;
+
+        entry.links.iter()
+            .find(|(range, _)| range.contains(&position))
+            .map(|(_, link)| link.clone())
+    }
+
+    /// The buffered line displayed at a position of the screen and how many columns into it (as displayed) that is,
+    /// counting wrapped rows as if lines were wrapped at any character.
+    fn entry_at(&self, column: u16, row: u16) -> Option<(BufferedLine, usize)> {
+        let area = self.last_seen_area;
+
+        if row < area.top() + self.top_bar_height() || row >= area.bottom() || column < area.left() || column >= area.right() {
+            return None;
+        }
+
+        /* Only the widths of the lines matter here, not their colors */
+        let entries = self.visible_entries(area);
+        let lines: Vec<Line> = entries.iter()
+            .map(|entry| self.display_line(entry, &Theme::default()))
+            .collect();
+
+        /* The rendered text is scrolled by the wraps, see `render` */
+        let mut target = wraps(&lines, area.width) + (row - area.top() - self.top_bar_height());
+
+        for (entry, line) in entries.iter().zip(&lines) {
+            let height = (line.width().saturating_sub(1) as u16) / area.width + 1;
+
+            if target < height {
+                let offset = target as usize * area.width as usize + (column - area.left()) as usize;
+
+                return Some((entry.clone(), offset));
+            }
+
+            target -= height;
+        }
+
+        None
+    }
+
+    /// Write all buffered lines as plain text (i.e. with all styling stripped), oldest first.
+    pub fn dump_to_writer<W: Write>(&self, w: &mut W) -> Result<()> {
+        for entry in self.buffer.iter_forwards() {
+            writeln!(w, "{}", line_text(&entry.line))
+                .context("Write line")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// A pane with the same capacity and top bar, but no lines.
+    pub fn empty_copy(&self) -> ScrollPane {
+        let mut pane = ScrollPane::new(self.capacity());
+        pane.set_top_bar(self.border, self.title.clone(), self.border_color);
+
+        pane
+    }
+
+    /// Show or hide the top bar, with an optional title and color.
+    pub fn set_top_bar(&mut self, border: bool, title: Option<String>, border_color: Option<Color>) {
+        self.border = border;
+        self.title = title;
+        self.border_color = border_color;
+    }
+
+    /// Properties of the top bar which differ from the defaults, in the script map form (e.g. `, title: "Chat"`).
+    pub fn top_bar_properties(&self) -> String {
+        let mut properties = String::new();
+
+        if !self.border {
+            properties.push_str(", border: false");
+        }
+
+        if let Some(title) = &self.title {
+            properties.push_str(&format!(", title: {title:?}"));
+        }
+
+        if let Some(color) = self.border_color {
+            properties.push_str(&format!(", border_color: \"{color}\""));
+        }
+
+        properties
+    }
+
+    /// Take over the lines and scroll position of a pane this one replaces, keeping as many of the newest lines as fit.
+    pub fn take_buffer(&mut self, other: ScrollPane) {
+        let capacity = self.buffer.capacity();
+
+        self.buffer = other.buffer;
+        self.buffer.resize(capacity);
+
+        self.last_pushed = other.last_pushed;
+        self.has_unread = other.has_unread;
+        self.scroll_offset = other.scroll_offset.min(self.buffer.size());
     }
 
     pub fn push(&mut self, line: Line<'static>) {
-        self.buffer.push_back(line);
-        if self.scroll_offset > 0 {
+        self.push_linked(line, vec![]);
+    }
+
+    /// Push a line with parts of it (byte ranges of its text) which can be clicked.
+    pub fn push_linked(&mut self, line: Line<'static>, links: Vec<(Range<usize>, Link)>) {
+        if self.collapse_duplicates {
+            if let Some((last, count)) = &mut self.last_pushed {
+                if !line_text(&line).trim().is_empty() && line_text(last) == line_text(&line) {
+                    *count += 1;
+
+                    let mut collapsed = last.clone();
+                    collapsed.spans.push(format!(" (x{count})").dark_gray());
+
+                    if let Some(back) = self.buffer.back_mut() {
+                        back.line = collapsed;
+                    }
+
+                    self.has_unread = true;
+
+                    return;
+                }
+            }
+
+            self.last_pushed = Some((line.clone(), 1));
+        }
+
+        self.buffer.push_back(BufferedLine { line, time: SystemTime::now(), links });
+        self.has_unread = true;
+
+        if let Some(search_match) = &mut self.search_match {
+            *search_match += 1;
+        }
+
+        /* Follow new output only when at the bottom and not locked; otherwise keep showing the same lines */
+        if self.scroll_offset > 0 || self.locked {
             self.scroll_offset = (self.scroll_offset + 1)
-                .min(self.buffer.size() - self.last_seen_area.height as usize);
+                .min(self.buffer.size().saturating_sub(self.last_seen_area.height as usize));
         }
     }
 
-    pub fn append(&mut self, lines: Vec<Line<'static>>) {
-        for line in lines {
-            self.push(line);
-        }
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    pub fn set_collapse_duplicates(&mut self, enabled: bool) {
+        self.collapse_duplicates = enabled;
+        self.last_pushed = None;
     }
 
-    pub fn page_up(&mut self) {
-        self.scroll_offset = (self.scroll_offset + self.last_seen_area.height as usize / 2)
+    pub fn set_max_line_width(&mut self, max_width: Option<usize>) {
+        self.max_line_width = max_width;
+    }
+
+    pub fn set_show_timestamps(&mut self, enabled: bool) {
+        self.show_timestamps = enabled;
+    }
+
+    /// Change how many lines the pane keeps (which must be positive), dropping the oldest ones if they no longer fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.buffer.resize(capacity);
+        self.scroll_offset = self.scroll_offset.min(self.buffer.size());
+    }
+
+    pub fn scroll_offset(&self) -> usize {
+        self.scroll_offset
+    }
+
+    /// Number of lines in the buffer.
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// Scroll to `offset` lines up from the bottom, clamped so that the view stays within the buffer.
+    pub fn scroll_to(&mut self, offset: usize) {
+        self.scroll_offset = offset
             .min(self.buffer.size().saturating_sub(self.last_seen_area.height as usize));
     }
 
-    pub fn page_down(&mut self) {
-        self.scroll_offset = self.scroll_offset.saturating_sub(self.last_seen_area.height as usize / 2);
+    /// Scroll up (back in the buffer) by a number of lines.
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll_to(self.scroll_offset + lines);
+    }
+
+    /// Scroll down (towards the newest output) by a number of lines.
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+    }
+
+    /// Search for a text (case-insensitively), from the newest line shown towards older ones,
+    /// and scroll to the first match; returns how many lines up from the newest line it is.
+    pub fn search(&mut self, needle: &str) -> Option<usize> {
+        self.search = Regex::new(&format!("(?i){}", regex::escape(needle))).ok();
+        self.search_match = None;
+
+        self.find_match(self.scroll_offset, true)
+    }
+
+    /// Go to the next older (or newer) match of the current search.
+    pub fn search_next(&mut self, older: bool) -> Option<usize> {
+        let start = match self.search_match {
+            Some(search_match) if older => search_match + 1,
+            Some(search_match) => search_match.checked_sub(1)?,
+            None => self.scroll_offset,
+        };
+
+        self.find_match(start, older)
+    }
+
+    pub fn clear_search(&mut self) {
+        self.search = None;
+        self.search_match = None;
     }
 
+    fn find_match(&mut self, start: usize, older: bool) -> Option<usize> {
+        let search = self.search.as_ref()?;
+        let size = self.buffer.size();
+
+        /* Lines up from the newest, i.e. the same way as `scroll_offset` counts */
+        let matches = |from_back: &usize| self.buffer.get_checked(size - 1 - from_back)
+            .is_some_and(|entry| search.is_match(&line_text(&entry.line)));
+
+        let found = if older {
+            (start..size).find(matches)
+        } else {
+            (0..=start.min(size.checked_sub(1)?)).rev().find(matches)
+        }?;
+
+        self.search_match = Some(found);
+
+        /* Show the match in the middle of the pane, where possible */
+        let half_page = self.last_seen_area.height.saturating_sub(self.top_bar_height()) as usize / 2;
+        self.scroll_to(found.saturating_sub(half_page));
+
+        Some(found)
+    }
+
+    /// Rows taken by the top bar, if it is shown.
+    fn top_bar_height(&self) -> u16 {
+        if self.border { 1 } else { 0 }
+    }
+
+    /// Number of lines scrolled by `fraction` of the pane's height (at least one).
+    pub fn page_lines(&self, fraction: f64) -> usize {
+        ((self.last_seen_area.height as f64 * fraction) as usize).max(1)
+    }
+}
+
+/// Plain text content of a line, i.e. with all styling stripped.
+pub fn line_text(line: &Line) -> String {
+    line.spans.iter()
+        .map(|span| span.content.as_ref())
+        .collect()
+}
+
+/// Patch the style of the parts of a line matching a pattern, splitting spans where needed.
+pub fn restyle_matches(line: Line<'static>, pattern: &Regex, style: Style) -> Line<'static> {
+    let text = line_text(&line);
+    let ranges: Vec<_> = pattern.find_iter(&text)
+        .filter(|found| !found.is_empty())
+        .map(|found| found.range())
+        .collect();
+
+    restyle_ranges(line, &ranges, style)
+}
+
+/// Patch the style of parts of a line (byte ranges of its text), splitting spans where needed.
+pub fn restyle_ranges(line: Line<'static>, ranges: &[Range<usize>], style: Style) -> Line<'static> {
+    if ranges.is_empty() {
+        return line;
+    }
+
+    let mut spans = vec![];
+    let mut offset = 0;
+
+    for span in line.spans {
+        let (start, end) = (offset, offset + span.content.len());
+        offset = end;
+
+        /* Boundaries within this span: its own and those of the matches overlapping it */
+        let mut cuts = vec![start, end];
+        for range in ranges {
+            cuts.extend([range.start, range.end].into_iter().filter(|&cut| cut > start && cut < end));
+        }
+        cuts.sort_unstable();
+        cuts.dedup();
+
+        for part in cuts.windows(2) {
+            let content = span.content[(part[0] - start)..(part[1] - start)].to_string();
+            let matched = ranges.iter().any(|range| range.start <= part[0] && part[1] <= range.end);
+
+            spans.push(Span::styled(content, if matched { span.style.patch(style) } else { span.style }));
+        }
+    }
+
+    Line::from(spans)
+}
+
+/// Number of extra rows needed by lines wrapping at `width`.
+fn wraps(lines: &[Line], width: u16) -> u16 {
+    lines.iter().map(|l| { (l.width().saturating_sub(1) as u16) / width }).sum()
+}
+
+/// Cut a line down to `max_width` columns, marking the cut with an ellipsis in `mark_color`.
+fn truncate_line(line: Line<'static>, max_width: usize, mark_color: Color) -> Line<'static> {
+    if line.width() <= max_width {
+        return line;
+    }
+
+    let mut remaining = max_width.saturating_sub(1 /* ellipsis */);
+    let mut spans = vec![];
+
+    for span in line.spans {
+        let mut content = String::new();
+
+        for ch in span.content.chars() {
+            let width = ch.width().unwrap_or(0);
+            if width > remaining {
+                remaining = 0;
+                break;
+            }
+
+            remaining -= width;
+            content.push(ch);
+        }
+
+        spans.push(Span::styled(content, span.style));
+
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    spans.push("…".fg(mark_color));
+
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrolling_up_a_scrollback_shorter_than_the_pane_stays_at_the_bottom() {
+        let mut pane = ScrollPane::new(100);
+        pane.push("one".into());
+        pane.push("two".into());
+        pane.last_seen_area = Rect::new(0, 0, 80, 40);
+
+        pane.scroll_up(20);
+
+        assert_eq!(pane.scroll_offset, 0);
+    }
+
+    #[test]
+    fn shrinking_the_scrollback_keeps_the_newest_lines_and_clamps_the_offset() {
+        let mut pane = ScrollPane::new(10);
+        for i in 0..10 {
+            pane.push(format!("line {i}").into());
+        }
+        pane.scroll_to(8);
+
+        pane.set_capacity(3);
+
+        assert_eq!(pane.capacity(), 3);
+        assert_eq!(pane.size(), 3);
+        assert_eq!(pane.scroll_offset, 3);
+    }
+
+    fn render_into(pane: &mut ScrollPane, width: u16, height: u16) {
+        let mut terminal = Terminal::new(backend::TestBackend::new(width, height)).unwrap();
+
+        terminal.draw(|frame| pane.render(frame, frame.size(), Some(1), true, &Theme::default())).unwrap();
+    }
+
+    fn pane_with_lines(count: usize) -> ScrollPane {
+        let mut pane = ScrollPane::new(100);
+        for i in 0..count {
+            pane.push(format!("line {i}").into());
+        }
+        pane
+    }
+
+    #[test]
+    fn rendering_into_a_single_cell_does_not_panic() {
+        render_into(&mut pane_with_lines(5), 1, 1);
+        render_into(&mut pane_with_lines(0), 1, 1);
+    }
+
+    #[test]
+    fn rendering_into_no_rows_does_not_panic() {
+        render_into(&mut pane_with_lines(5), 80, 0);
+        render_into(&mut pane_with_lines(5), 0, 24);
+    }
+
+    #[test]
+    fn static_pane_renders_into_tiny_areas() {
+        let mut pane = StaticPane::new();
+        pane.set_content(vec!["status".into()]);
+
+        for (width, height) in [(1, 1), (80, 0), (0, 0)] {
+            let mut terminal = Terminal::new(backend::TestBackend::new(width, height)).unwrap();
+            terminal.draw(|frame| pane.render(frame, frame.size(), Some(2), &Theme::default())).unwrap();
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,53 @@
+/// Directions understood in speedwalks, longest first so that e.g. `ne` is not taken for `n` followed by `e`.
+const DIRECTIONS: [(&str, &str); 10] = [
+    ("ne", "northeast"),
+    ("nw", "northwest"),
+    ("se", "southeast"),
+    ("sw", "southwest"),
+    ("n", "north"),
+    ("s", "south"),
+    ("e", "east"),
+    ("w", "west"),
+    ("u", "up"),
+    ("d", "down"),
+];
+
+/// Largest repeat count of a single direction, so that a typo cannot send hundreds of commands.
+const MAX_COUNT: usize = 99;
+
+/// Expand a speedwalk such as `3n2e` into movement commands (`north`, `north`, `north`, `east`, `east`).
+///
+/// The input has to consist solely of directions, each optionally preceded by a count, and include at least
+/// one count, so that ordinary commands made of direction letters (e.g. `news`) are left alone.
+/// Returns `None` for anything else.
+pub fn expand(input: &str) -> Option<Vec<String>> {
+    let mut rest = input.trim();
+    let mut commands = vec![];
+    let mut has_count = false;
+
+    while !rest.is_empty() {
+        let digits = rest.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(rest.len());
+
+        let count = if digits > 0 {
+            has_count = true;
+            rest[..digits].parse().ok().filter(|count| (1..=MAX_COUNT).contains(count))?
+        } else {
+            1
+        };
+
+        rest = &rest[digits..];
+
+        let (short, direction) = DIRECTIONS.iter()
+            .find(|(short, _)| rest.starts_with(short))?;
+
+        rest = &rest[short.len()..];
+
+        commands.extend(std::iter::repeat_n(direction.to_string(), count));
+    }
+
+    if has_count {
+        Some(commands)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,30 @@
+use ratatui::{
+    prelude::*,
+    widgets::*,
+};
+
+use crate::tui::theme::Theme;
+
+/// A one-line bar at the bottom of the screen, showing the state of the connection.
+pub struct StatusBar {
+    text: String,
+}
+
+impl StatusBar {
+    pub fn new() -> StatusBar {
+        StatusBar { text: "Not connected".into() }
+    }
+
+    pub fn set_text(&mut self, text: String) {
+        self.text = text;
+    }
+
+    pub fn render(&self, frame: &mut Frame<'_>, area: Rect, theme: &Theme) {
+        let line = Line::from(format!(" {}", self.text).fg(theme.title_active));
+
+        frame.render_widget(
+            Paragraph::new(line).style(Style::default().bg(theme.border)),
+            area,
+        );
+    }
+}
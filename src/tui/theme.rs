@@ -0,0 +1,125 @@
+use std::str::FromStr;
+use rhai::Map;
+use anyhow::{Context, Result, bail};
+use ratatui::prelude::*;
+
+/// Semantic colors used throughout the UI.
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub border: Color,
+    pub title_active: Color,
+    pub title_inactive: Color,
+    pub text: Color,
+    pub completion: Color,
+    pub user_input: Color,
+    pub info: Color,
+    pub warning: Color,
+    pub error: Color,
+    /// URLs in output.
+    pub link: Color,
+    /// Timestamps and marks of truncated lines.
+    pub dim: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            border: Color::Yellow,
+            title_active: Color::White,
+            title_inactive: Color::DarkGray,
+            text: Color::White,
+            completion: Color::Cyan,
+            user_input: Color::LightCyan,
+            info: Color::LightGreen,
+            warning: Color::LightYellow,
+            error: Color::LightRed,
+            link: Color::Cyan,
+            dim: Color::DarkGray,
+        }
+    }
+}
+
+impl Theme {
+    pub fn solarized() -> Theme {
+        Theme {
+            border: Color::Rgb(0xb5, 0x89, 0x00),
+            title_active: Color::Rgb(0x93, 0xa1, 0xa1),
+            title_inactive: Color::Rgb(0x58, 0x6e, 0x75),
+            text: Color::Rgb(0x83, 0x94, 0x96),
+            completion: Color::Rgb(0x2a, 0xa1, 0x98),
+            user_input: Color::Rgb(0x26, 0x8b, 0xd2),
+            info: Color::Rgb(0x85, 0x99, 0x00),
+            warning: Color::Rgb(0xcb, 0x4b, 0x16),
+            error: Color::Rgb(0xdc, 0x32, 0x2f),
+            link: Color::Rgb(0x6c, 0x71, 0xc4),
+            dim: Color::Rgb(0x58, 0x6e, 0x75),
+        }
+    }
+
+    pub fn monochrome() -> Theme {
+        Theme {
+            border: Color::White,
+            title_active: Color::White,
+            title_inactive: Color::Gray,
+            text: Color::White,
+            completion: Color::Gray,
+            user_input: Color::White,
+            info: Color::White,
+            warning: Color::White,
+            error: Color::White,
+            link: Color::White,
+            dim: Color::Gray,
+        }
+    }
+
+    /// Get one of the built-in themes by name.
+    pub fn named(name: &str) -> Result<Theme> {
+        match name {
+            "default" => Ok(Theme::default()),
+            "solarized" => Ok(Theme::solarized()),
+            "monochrome" => Ok(Theme::monochrome()),
+            _ => bail!("Invalid theme name: {name}"),
+        }
+    }
+
+    /// Create a theme from a script map.
+    ///
+    /// The map can name a built-in theme to start from as `base` (otherwise the default theme is used)
+    /// and override any of the individual colors, e.g. `#{ base: "solarized", error: "#ff0000" }`.
+    pub fn from(theme: Map) -> Result<Theme> {
+        let mut result = match theme.get("base") {
+            Some(base) => Theme::named(&base.to_string())
+                .context("Get base theme")?,
+            None => Theme::default(),
+        };
+
+        for (key, value) in theme {
+            let color = match key.as_str() {
+                "base" => { continue; },
+                "border" => &mut result.border,
+                "title_active" => &mut result.title_active,
+                "title_inactive" => &mut result.title_inactive,
+                "text" => &mut result.text,
+                "completion" => &mut result.completion,
+                "user_input" => &mut result.user_input,
+                "info" => &mut result.info,
+                "warning" => &mut result.warning,
+                "error" => &mut result.error,
+                "link" => &mut result.link,
+                "dim" => &mut result.dim,
+                _ => bail!("Invalid theme color: {key}"),
+            };
+
+            *color = parse_color(&value.to_string())
+                .context(format!("Parse theme color {key}"))?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Parse a color name (e.g. "light red"), palette index or "#rrggbb" value.
+pub fn parse_color(color: &str) -> Result<Color> {
+    Color::from_str(color)
+        .map_err(|_| anyhow::anyhow!("Invalid color: {color}"))
+}
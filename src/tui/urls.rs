@@ -0,0 +1,56 @@
+use std::ops::Range;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+/// Schemes of the URLs recognized in output.
+const SCHEMES: [&str; 2] = ["http://", "https://"];
+
+/// Find `http://` and `https://` URLs in text, as byte ranges; punctuation ending a sentence is left out of them.
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut urls = vec![];
+    let mut from = 0;
+
+    while let Some(found) = text[from..].find("http") {
+        let start = from + found;
+        let rest = &text[start..];
+
+        /* Only at the start of a word, so that e.g. `xhttp://` is not taken for a URL */
+        let at_word_start = !text[..start].ends_with(|ch: char| ch.is_alphanumeric());
+
+        let Some(scheme) = SCHEMES.iter().find(|scheme| rest.starts_with(*scheme)).filter(|_| at_word_start) else {
+            from = start + "http".len();
+            continue;
+        };
+
+        let length = rest.find(|ch: char| ch.is_whitespace() || matches!(ch, '<' | '>' | '"' | '\'' | '`'))
+            .unwrap_or(rest.len());
+        let url = rest[..length].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '}']);
+
+        if url.len() > scheme.len() {
+            urls.push(start..start + url.len());
+        }
+
+        from = start + length.max(scheme.len());
+    }
+
+    urls
+}
+
+/// Open a URL with the desktop's default handler (e.g. a web browser), without waiting for it.
+pub fn open_url(url: &str) -> Result<()> {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+
+    let mut child = Command::new(opener)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context(format!("Run {opener} to open {url}"))?;
+
+    /* Reap it once it exits */
+    std::thread::spawn(move || child.wait());
+
+    Ok(())
+}
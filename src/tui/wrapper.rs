@@ -1,10 +1,42 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+use std::ops::Range;
+
 use ansi_to_tui::IntoText;
+use regex::Regex;
 use tokio::sync::mpsc::Sender;
 use anyhow::{Context, Result};
 
 use ratatui::prelude::*;
+use ratatui::widgets::{Block, Borders, Clear, Paragraph};
 
+use crate::pipeline::strip_ansi;
+use crate::ring::RingBuffer;
 use crate::tui::*;
+use crate::tui::status::StatusBar;
+
+/// Searching the scrollback of the active pane.
+#[derive(Clone, Copy, PartialEq)]
+enum Search {
+    Off,
+    /// The input is taking the text to search for.
+    Typing,
+    /// A search was made; `n`/`N` go to the next older/newer match.
+    Browsing,
+}
+
+/// Number of distinct words from output remembered for completion.
+const COMPLETION_WORDS: usize = 1000;
+
+/// Words shorter than this are not worth completing.
+const MIN_COMPLETION_LENGTH: usize = 3;
+
+/// Number of distinct URLs from output remembered for `/urls`.
+const RECENT_URLS: usize = 20;
+
+/// Lines scrolled by one notch of the mouse wheel.
+const WHEEL_LINES: usize = 3;
 
 pub struct TuiWrapper<B: Backend> {
     terminal: Terminal<B>,
@@ -12,23 +44,106 @@ pub struct TuiWrapper<B: Backend> {
 
     layout: LayoutElement,
     active_pane: usize,
+    /// Index (in layout order) of the input pane being typed into.
+    active_input: usize,
+
+    history_config: HistoryConfig,
+
+    capabilities: Capabilities,
+    /// Whether the user has been warned that the terminal may not report mouse clicks.
+    mouse_warned: bool,
+
+    /// Named layouts registered by scripts, to be switched to with `UseLayout`.
+    layouts: HashMap<String, LayoutElement>,
+
+    theme: Theme,
+
+    /// Whether secret mode was turned on because the server echoes input, so that it ends when the echoing does.
+    secret_by_server: bool,
+
+    /// Clicking a line sends its text.
+    click_send: bool,
+
+    /// Input matching any of these is only sent after confirming it.
+    guards: Vec<Regex>,
+    /// Input waiting for confirmation (by pressing `y`; any other key cancels), with the target of the input it was typed in.
+    pending_confirmation: Option<(String, Option<String>)>,
+
+    /// Off by default, as it may misfire on ordinary text.
+    emphasis: Option<Emphasis>,
+
+    /// Off by default, as some MUDs have commands which look like speedwalks.
+    speedwalk: bool,
+
+    /// Off by default, as terminals rarely tell numpad digits from the others.
+    numpad: Option<NumpadDirections>,
+
+    /// Show the server's prompt on a line of its own above the input.
+    prompt_line: bool,
+
+    /// Part of the pane's height scrolled by PageUp/PageDown.
+    scroll_fraction: f64,
+
+    search: Search,
+
+    /// Words recently seen in output, the most recent at the back, for Tab completion.
+    words: RingBuffer<String>,
+
+    /// URLs recently seen in output, the most recent at the back, to be opened by number.
+    urls: RingBuffer<String>,
+
+    /// Keys bound by scripts, with descriptions of what they do; they only get keys which are not handled here already.
+    bound_keys: HashMap<KeySpec, String>,
+    /// Whether the list of key bindings is shown over the layout (until a key is pressed).
+    show_bindings: bool,
+
+    status: StatusBar,
+    show_status: bool,
 }
 
 impl<B: Backend> TuiWrapper<B> {
-    pub fn new(terminal: Terminal<B>, tx: Sender<TuiEvent>) -> TuiWrapper<B> {
-        TuiWrapper { terminal, tx, layout: TuiWrapper::<B>::default_layout(), active_pane: 1 }
+    pub fn new(terminal: Terminal<B>, tx: Sender<TuiEvent>, history_config: HistoryConfig, capabilities: Capabilities) -> TuiWrapper<B> {
+        TuiWrapper {
+            terminal,
+            tx,
+            layout: TuiWrapper::<B>::default_layout(history_config),
+            history_config,
+            capabilities,
+            mouse_warned: false,
+            active_pane: 1,
+            active_input: 0,
+            layouts: HashMap::new(),
+            theme: Theme::default(),
+            secret_by_server: false,
+            click_send: false,
+            guards: vec![],
+            pending_confirmation: None,
+            emphasis: None,
+            speedwalk: false,
+            numpad: None,
+            prompt_line: false,
+            scroll_fraction: 0.5,
+            search: Search::Off,
+            words: RingBuffer::new(COMPLETION_WORDS),
+            urls: RingBuffer::new(RECENT_URLS),
+            bound_keys: HashMap::new(),
+            show_bindings: false,
+            status: StatusBar::new(),
+            show_status: true,
+        }
     }
 
-    fn default_layout() -> LayoutElement {
+    fn default_layout(history_config: HistoryConfig) -> LayoutElement {
+        let mut input = InputPane::new();
+        input.set_history_config(history_config);
+
         LayoutElement::VerticalStack {
             children: vec![
                 LayoutElement::Pane(LayoutPane::ScrollPane {
                     id: Some(1),
-                    pane: ScrollPane::new(2000),
+                    pane: ScrollPane::new(ScrollPane::DEFAULT_CAPACITY),
                 }),
-                LayoutElement::Pane(LayoutPane::InputPane(
-                    InputPane::new()
-                ))
+                LayoutElement::Pane(LayoutPane::InputPane(Box::new(input)))
             ],
             constraints: vec![
                 Constraint::Max(9999),
@@ -39,9 +154,22 @@ impl<B: Backend> TuiWrapper<B> {
 
     pub fn render_ui(&mut self) -> Result<()> {
         self.terminal.draw(|frame| {
-            let area = frame.size();
+            let mut area = frame.size();
+
+            /* The status bar takes the bottom row, unless that would leave no room for the layout */
+            if self.show_status && area.height > 1 {
+                area.height -= 1;
+
+                self.status.render(frame, Rect::new(area.x, area.bottom(), area.width, 1), &self.theme);
+            }
+
+            self.layout.render(frame, area, self.active_pane, &self.theme);
 
-            self.layout.render(frame, area, self.active_pane);
+            if self.show_bindings {
+                render_bindings(frame, area, &self.bound_keys, &self.theme);
+            }
+
+            downgrade_colors(frame.buffer_mut(), self.capabilities.color);
         }).context("Draw to terminal")?;
 
         Ok(())
@@ -50,6 +178,38 @@ impl<B: Backend> TuiWrapper<B> {
     pub async fn process_input(&mut self, event: Event) -> Result<bool> {
         if let event::Event::Key(key) = event {
             if key.kind == KeyEventKind::Press {
+                if let Some((data, target)) = self.pending_confirmation.take() {
+                    if key.code == KeyCode::Char('y') || key.code == KeyCode::Char('Y') {
+                        self.send_input(data, target).await
+                            .context("Submit confirmed user input")?;
+                    } else {
+                        self.print_info(format!("Not sent: {data}"));
+                    }
+
+                    return Ok(false);
+                }
+
+                /* Any key closes the list of key bindings */
+                if self.show_bindings {
+                    self.show_bindings = false;
+                    return Ok(false);
+                }
+
+                if self.search != Search::Off && self.process_search_key(key) {
+                    return Ok(false);
+                }
+
+                if let Some(command) = self.numpad_command(key) {
+                    self.submit(command, None).await
+                        .context("Submit numpad command")?;
+
+                    return Ok(false);
+                }
+
+                if key.code != KeyCode::Tab {
+                    self.input().end_completion();
+                }
+
                 match (key.modifiers, key.code) {
                     /* Alt+q = Exit program */
                     (KeyModifiers::ALT, KeyCode::Char('q')) => {
@@ -58,17 +218,46 @@ impl<B: Backend> TuiWrapper<B> {
                         return Ok(true);
                     },
 
-                    /* Enter = submit input */
+                    /* Enter = submit input (or line break in multi-line mode) */
                     (KeyModifiers::NONE, KeyCode::Enter) => {
-                        let data = self.input().get_and_submit();
-                        self.tx.send(TuiEvent::Send(data)).await
-                            .context("Submit user input")?;
+                        if self.input().is_secret() {
+                            self.submit_secret().await?;
+                        } else if self.input().is_multiline() {
+                            self.input().type_string("\n".into());
+                        } else {
+                            let data = self.input().get_and_submit();
+                            let target = self.input().target();
+                            self.submit(data, target).await?;
+                        }
+                    },
+                    /* Ctrl+Enter/Ctrl+D = submit input, also in multi-line mode */
+                    (KeyModifiers::CONTROL, KeyCode::Enter) | (KeyModifiers::CONTROL, KeyCode::Char('d')) => {
+                        if self.input().is_secret() {
+                            self.submit_secret().await?;
+                        } else {
+                            let data = self.input().get_and_submit();
+                            let target = self.input().target();
+                            self.submit(data, target).await?;
+                        }
+                    },
+                    /* Tab = complete the word being typed from words seen in output (again for the next one) */
+                    (KeyModifiers::NONE, KeyCode::Tab) => {
+                        let candidates: Vec<_> = self.words.iter_from_back().collect();
+                        self.input().complete_word(&candidates);
+                    },
+                    /* Alt+m = toggle multi-line input mode */
+                    (KeyModifiers::ALT, KeyCode::Char('m')) => {
+                        self.input().toggle_multiline();
                     },
                     /* Alt+Enter = submit secret (e.g. password) */
                     (KeyModifiers::ALT, KeyCode::Enter) => {
-                        let data = self.input().get_and_clear();
-                        self.tx.send(TuiEvent::SendSecret(data)).await
-                            .context("Submit secret user input")?;
+                        self.submit_secret().await?;
+                    },
+                    /* Ctrl+P = toggle secret mode, in which input is masked and always submitted as a secret */
+                    (KeyModifiers::CONTROL, KeyCode::Char('p')) => {
+                        let secret = !self.input().is_secret();
+                        self.input().set_secret(secret);
+                        self.secret_by_server = false;
                     },
 
                     /* Lowercase characters */
@@ -88,67 +277,718 @@ impl<B: Backend> TuiWrapper<B> {
                     /* Navigation */
                     (KeyModifiers::NONE, KeyCode::Right) => { self.input().right(); },
                     (KeyModifiers::NONE, KeyCode::Left) => { self.input().left(); },
+                    (KeyModifiers::CONTROL, KeyCode::Up) => { self.input().history_up(); },
+                    (KeyModifiers::CONTROL, KeyCode::Down) => { self.input().history_down(); },
+                    (KeyModifiers::CONTROL, KeyCode::Char('r')) => { self.input().search_history(); },
+                    (KeyModifiers::CONTROL, KeyCode::Right) => { self.input().word_right(); },
+                    (KeyModifiers::CONTROL, KeyCode::Left) => { self.input().word_left(); },
+                    (KeyModifiers::CONTROL, KeyCode::Char('k')) => { self.input().kill_to_end(); },
+                    (KeyModifiers::CONTROL, KeyCode::Char('u')) => { self.input().kill_to_start(); },
+                    (KeyModifiers::CONTROL, KeyCode::Char('y')) => { self.input().yank(); },
+                    /* Home/End with nothing typed = scroll the active pane to the top/bottom */
+                    (KeyModifiers::NONE, KeyCode::Home) if self.input().is_empty() => { self.active_pane().scroll_to(usize::MAX); },
+                    (KeyModifiers::NONE, KeyCode::End) if self.input().is_empty() => { self.active_pane().scroll_to(0); },
                     (KeyModifiers::NONE, KeyCode::Home) => { self.input().home(); },
                     (KeyModifiers::NONE, KeyCode::End) => { self.input().end(); },
                     (KeyModifiers::NONE, KeyCode::Up) => { self.input().up() }
                     (KeyModifiers::NONE, KeyCode::Down) => { self.input().down() }
-                    (KeyModifiers::NONE, KeyCode::PageUp) => { self.active_pane().page_up(); }
-                    (KeyModifiers::NONE, KeyCode::PageDown) => { self.active_pane().page_down(); }
+
+                    /* Scrolling: PageUp/PageDown by part of a page, with Shift by a full page, Shift+Up/Down by a line */
+                    (KeyModifiers::NONE, KeyCode::PageUp) => { self.scroll_active_pane(self.scroll_fraction, true); }
+                    (KeyModifiers::NONE, KeyCode::PageDown) => { self.scroll_active_pane(self.scroll_fraction, false); }
+                    (KeyModifiers::SHIFT, KeyCode::PageUp) => { self.scroll_active_pane(1.0, true); }
+                    (KeyModifiers::SHIFT, KeyCode::PageDown) => { self.scroll_active_pane(1.0, false); }
+                    (KeyModifiers::SHIFT, KeyCode::Up) => { self.active_pane().scroll_up(1); }
+                    (KeyModifiers::SHIFT, KeyCode::Down) => { self.active_pane().scroll_down(1); }
+
+                    /* Ctrl+PageDown/Ctrl+PageUp = make the next/previous pane the active one */
+                    (KeyModifiers::CONTROL, KeyCode::PageDown) => { self.cycle_active_pane(true); }
+                    (KeyModifiers::CONTROL, KeyCode::PageUp) => { self.cycle_active_pane(false); }
+
+                    /* Alt+l/Scroll Lock = toggle scroll lock of the active pane */
+                    (KeyModifiers::ALT, KeyCode::Char('l')) | (KeyModifiers::NONE, KeyCode::ScrollLock) => {
+                        let pane = self.active_pane();
+                        pane.set_locked(!pane.is_locked());
+                    },
+
+                    /* Alt+arrows = resize the active pane (Down/Right grow it, Up/Left shrink it) */
+                    (KeyModifiers::ALT, KeyCode::Up) => { self.resize_active_pane(Direction::Vertical, -1)?; },
+                    (KeyModifiers::ALT, KeyCode::Down) => { self.resize_active_pane(Direction::Vertical, 1)?; },
+                    (KeyModifiers::ALT, KeyCode::Left) => { self.resize_active_pane(Direction::Horizontal, -1)?; },
+                    (KeyModifiers::ALT, KeyCode::Right) => { self.resize_active_pane(Direction::Horizontal, 1)?; },
+
+                    /* Ctrl+F = search the scrollback of the active pane */
+                    (KeyModifiers::CONTROL, KeyCode::Char('f')) => {
+                        self.search = Search::Typing;
+                        self.input().set_prompt(Some("search (Enter to find, Esc to cancel)".into()));
+                    },
+
+                    /* Alt+i = type into the next input pane, when there are several */
+                    (KeyModifiers::ALT, KeyCode::Char('i')) if self.layout.inputs().len() > 1 => {
+                        let count = self.layout.inputs().len();
+                        self.focus_input((self.active_input + 1) % count);
+                    },
+
+                    /* Alt+c = toggle click-send mode */
+                    (KeyModifiers::ALT, KeyCode::Char('c')) => {
+                        self.set_click_send(!self.click_send)?;
+                    },
 
                     /* Escape = cancel completion suggestions */
                     (KeyModifiers::NONE, KeyCode::Esc) => { self.input().cancel(); }
 
+                    /* Bound by a script */
+                    _ if self.bound_keys.contains_key(&KeySpec::from(key)) => {
+                        self.tx.send(TuiEvent::KeyPressed(KeySpec::from(key))).await
+                            .context("Report bound key")?;
+                    },
+
+                    /* F1 = list the key bindings, unless bound by a script */
+                    (KeyModifiers::NONE, KeyCode::F(1)) => {
+                        self.show_bindings = true;
+                    },
+
+                    /* Other function keys are left for scripts to bind */
+                    (_, KeyCode::F(_)) => {
+                        self.print_info(format!("{} is not bound (use bind() in a script)", KeySpec::from(key)));
+                    },
+
                     /* Unhandled */
                     _ => {
-                        self.default_pane().push(format!("Unhandled key: {:?}", key).light_yellow().into());
+                        let color = self.theme.warning;
+                        self.default_pane().push(format!("Unhandled key: {:?}", key).fg(color).into());
                     },
                 }
             }
         }
 
+        if let event::Event::Mouse(mouse) = event {
+            /* The wheel scrolls the pane under the pointer, whichever pane is active */
+            if let MouseEventKind::ScrollUp | MouseEventKind::ScrollDown = mouse.kind {
+                if let Some(pane) = self.layout.pane_at(mouse.column, mouse.row) {
+                    if mouse.kind == MouseEventKind::ScrollUp {
+                        pane.scroll_up(WHEEL_LINES);
+                    } else {
+                        pane.scroll_down(WHEEL_LINES);
+                    }
+                }
+            }
+
+            /* Clicking a pane makes it the active one, and clicking an input pane makes it the one typed into */
+            if mouse.kind == MouseEventKind::Down(MouseButton::Left) {
+                if let Some(pane_id) = self.layout.pane_id_at(mouse.column, mouse.row) {
+                    self.active_pane = pane_id;
+                }
+
+                if let Some(index) = self.layout.inputs().iter().position(|input| input.contains(mouse.column, mouse.row)) {
+                    self.focus_input(index);
+                }
+            }
+
+            /* Clicking a link sends its command (or opens its URL); otherwise, in click-send mode, the line is sent */
+            let link = match mouse.kind {
+                MouseEventKind::Down(MouseButton::Left) => self.layout.pane_at(mouse.column, mouse.row)
+                    .and_then(|pane| pane.link_at(mouse.column, mouse.row)),
+                _ => None,
+            };
+
+            match link {
+                Some(Link::Command(command)) => {
+                    self.submit_clicked(command).await
+                        .context("Submit clicked link")?;
+                },
+                Some(Link::Url(url)) => {
+                    self.open_url(&url);
+                },
+                None if self.click_send && mouse.kind == MouseEventKind::Down(MouseButton::Left) => {
+                    let text = self.layout.pane_at(mouse.column, mouse.row)
+                        .and_then(|pane| pane.line_at(mouse.row));
+
+                    if let Some(text) = text.filter(|text| !text.trim().is_empty()) {
+                        self.submit_clicked(text.trim().to_string()).await
+                            .context("Submit clicked line")?;
+                    }
+                },
+                None => {},
+            }
+        }
+
+        if let event::Event::Resize(columns, rows) = event {
+            self.tx.send(TuiEvent::WindowResize(columns, rows)).await
+                .context("Report window size")?;
+        }
+
+        if let event::Event::Paste(text) = event {
+            self.paste(text);
+        }
+
         Ok(false)
     }
 
     pub fn process_request(&mut self, recv: TuiRequest) -> Result<()> {
         match recv {
-            TuiRequest::Print(data, _) => {
-                let line = data.into_text()
+            TuiRequest::Print(data, pane_id) => {
+                let lines = data.into_text()
                     .context("Parse ANSI color codes")?
                     .lines;
-                self.default_pane().append(line);
+
+                for line in lines {
+                    let mut links = vec![];
+                    let line = self.link_urls(line, &mut links);
+
+                    self.pane_or_default(pane_id).push_linked(line, links);
+                }
+            },
+            TuiRequest::PrintOutput(output) => {
+                /* Parsed together, as colors may carry over from one line to the next */
+                let data = output.iter()
+                    .map(|line| line.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                let lines = data.into_text()
+                    .context("Parse ANSI color codes")?
+                    .lines;
+
+                for output in &output {
+                    for word in output.plain().split(|ch: char| !ch.is_alphanumeric()) {
+                        if word.chars().count() >= MIN_COMPLETION_LENGTH {
+                            self.words.find_and_push_back(word.to_string());
+                        }
+                    }
+                }
+
+                let mut linked = vec![];
+
+                for (index, mut line) in lines.into_iter().enumerate() {
+                    let mut links = vec![];
+
+                    if let Some(output) = output.get(index) {
+                        if let Some(emphasis) = &self.emphasis {
+                            emphasis.apply(&mut line);
+                        }
+
+                        for (pattern, color) in &output.highlights {
+                            line = restyle_matches(line, pattern, Style::default().fg(*color));
+                        }
+
+                        if let Some(color) = output.background {
+                            for span in &mut line.spans {
+                                span.style = span.style.bg(color);
+                            }
+                        }
+
+                        let ranges: Vec<_> = output.links.iter().map(|(range, _)| range.clone()).collect();
+                        line = restyle_ranges(line, &ranges, Style::default().add_modifier(Modifier::UNDERLINED));
+
+                        links.extend(output.links.iter().map(|(range, command)| (range.clone(), Link::Command(command.clone()))));
+                    }
+
+                    line = self.link_urls(line, &mut links);
+                    linked.push((line, links));
+                }
+
+                let pane = self.default_pane();
+                for (line, links) in linked {
+                    pane.push_linked(line, links);
+                }
+            },
+            TuiRequest::PrintUserInput(data, pane_id) => {
+                let color = self.theme.user_input;
+                self.pane_or_default(pane_id).push(data.fg(color).bold().into());
+            },
+            TuiRequest::PrintInfo(data, pane_id) => {
+                let color = self.theme.info;
+                self.print_prefixed(pane_id, "[INFO]", color, data);
+            },
+            TuiRequest::PrintWarning(data, pane_id) => {
+                let color = self.theme.warning;
+                self.print_prefixed(pane_id, "[WARN]", color, data);
+            },
+            TuiRequest::PrintError(data, pane_id) => {
+                let color = self.theme.error;
+                self.print_prefixed(pane_id, "[ERR]", color, data);
+            },
+            TuiRequest::SetLayout(layout) => {
+                self.replace_layout(layout);
+            },
+            TuiRequest::RegisterLayout(name, layout) => {
+                self.layouts.insert(name, layout);
             },
-            TuiRequest::PrintUserInput(data, _) => {
-                self.default_pane().push(data.light_cyan().bold().into());
+            TuiRequest::UseLayout(name) => {
+                if let Some(layout) = self.layouts.get(&name) {
+                    let layout = layout.empty_copy();
+                    self.replace_layout(layout);
+                } else {
+                    self.print_warning(format!("No layout named \"{name}\""));
+                }
+            },
+            TuiRequest::SetTheme(theme) => {
+                self.theme = theme;
             },
-            TuiRequest::PrintInfo(data, _) => {
-                for line in data.split('\n') {
-                    self.default_pane().push(format!("[INFO] {line}").light_green().into());
+            TuiRequest::SetCollapseDuplicates(pane_id, enabled) => {
+                if let Some(pane) = self.layout.pane(pane_id) {
+                    pane.set_collapse_duplicates(enabled);
+                } else {
+                    self.print_warning(format!("No pane with id = {pane_id}"));
                 }
             },
-            TuiRequest::PrintWarning(data, _) => {
-                for line in data.split('\n') {
-                    self.default_pane().push(format!("[WARN] {line}").light_yellow().into());
+            TuiRequest::DumpLayout => {
+                /* Plain text without a prefix, so that it can be copied into a script */
+                let color = self.theme.text;
+                let layout = self.layout.to_string();
+                for line in layout.split('\n') {
+                    self.default_pane().push(line.to_string().fg(color).into());
                 }
             },
-            TuiRequest::PrintError(data, _) => {
-                for line in data.split('\n') {
-                    self.default_pane().push(format!("[ERR] {line}").light_red().into());
+            TuiRequest::SetQueuedCommands(queued) => {
+                self.game_input().set_queued(queued);
+            },
+            TuiRequest::SetServerPrompt(prompt) => {
+                let prompt = strip_ansi(&prompt).trim().to_string();
+                self.game_input().set_server_prompt(Some(prompt).filter(|prompt| !prompt.is_empty()));
+            },
+            TuiRequest::SetSecretInput(secret) => {
+                self.game_input().set_secret(secret);
+                self.secret_by_server = false;
+            },
+            TuiRequest::SetServerEcho(echo) => {
+                if echo && !self.game_input().is_secret() {
+                    self.game_input().set_secret(true);
+                    self.secret_by_server = true;
+                } else if !echo && self.secret_by_server {
+                    self.game_input().set_secret(false);
+                    self.secret_by_server = false;
                 }
             },
-            TuiRequest::SetLayout(layout) => {
-                self.layout = layout; /* TODO: copy over the buffers */
+            TuiRequest::SetPromptLine(enabled) => {
+                self.prompt_line = enabled;
+                for input in self.layout.inputs() {
+                    input.set_prompt_line(enabled);
+                }
+            },
+            TuiRequest::SetMaxLineWidth(pane_id, max_width) => {
+                if let Some(pane) = self.layout.pane(pane_id) {
+                    pane.set_max_line_width(max_width);
+                } else {
+                    self.print_warning(format!("No pane with id = {pane_id}"));
+                }
+            },
+            TuiRequest::SetTimestamps(pane_id, enabled) => {
+                if let Some(pane) = self.layout.pane(pane_id) {
+                    pane.set_show_timestamps(enabled);
+                } else {
+                    self.print_warning(format!("No pane with id = {pane_id}"));
+                }
+            },
+            TuiRequest::SetScrollback(pane_id, lines) => {
+                if let Some(pane) = self.layout.pane(pane_id) {
+                    pane.set_capacity(lines);
+                } else {
+                    self.print_warning(format!("No pane with id = {pane_id}"));
+                }
+            },
+            TuiRequest::SaveBuffer(pane_id, path) => {
+                match self.layout.pane(pane_id).map(|pane| save_buffer(pane, &path)) {
+                    Some(Ok(())) => self.print_info(format!("Saved pane {pane_id} to {path}")),
+                    Some(Err(err)) => self.print_error(format!("{err:?}")),
+                    None => self.print_warning(format!("No pane with id = {pane_id}")),
+                }
+            },
+            TuiRequest::GetScrollInfo(pane_id, reply) => {
+                let info = self.layout.pane(pane_id)
+                    .map(|pane| (pane.scroll_offset(), pane.size()));
+
+                /* The script may have given up waiting */
+                let _ = reply.send(info);
+            },
+            TuiRequest::ScrollTo(pane_id, offset) => {
+                if let Some(pane) = self.layout.pane(pane_id) {
+                    pane.scroll_to(offset);
+                } else {
+                    self.print_warning(format!("No pane with id = {pane_id}"));
+                }
+            },
+            TuiRequest::SetPaneConstraint(pane_id, constraint) => {
+                let found = self.layout.set_pane_constraint(pane_id, constraint)
+                    .context(format!("Resize pane {pane_id}"))?;
+
+                if !found {
+                    self.print_warning(format!("No pane with id = {pane_id}"));
+                }
+            },
+            TuiRequest::SetPane(pane_id, text) => {
+                let lines = text.into_text()
+                    .context("Parse ANSI color codes")?
+                    .lines;
+
+                if let Some(pane) = self.layout.static_pane(pane_id) {
+                    pane.set_content(lines);
+                } else {
+                    self.print_warning(format!("No static pane with id = {pane_id}"));
+                }
+            },
+            TuiRequest::SetScrollFraction(fraction) => {
+                self.scroll_fraction = fraction;
+            },
+            TuiRequest::SetClickSend(enabled) => {
+                self.set_click_send(enabled)?;
+            },
+            TuiRequest::AddGuard(guard) => {
+                self.guards.retain(|existing| existing.as_str() != guard.as_str());
+                self.guards.push(guard);
+            },
+            TuiRequest::RemoveGuard(pattern) => {
+                self.guards.retain(|guard| guard.as_str() != pattern);
+            },
+            TuiRequest::ListGuards => {
+                let guards = if self.guards.is_empty() {
+                    "No guards".to_string()
+                } else {
+                    self.guards.iter()
+                        .map(|guard| format!("Guard: {}", guard.as_str()))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+
+                self.print_info(guards);
+            },
+            TuiRequest::ListUrls => {
+                let urls = if self.urls.is_empty() {
+                    "No URLs seen yet".to_string()
+                } else {
+                    self.urls.iter_from_back()
+                        .enumerate()
+                        .map(|(index, url)| format!("{}: {url}", index + 1))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+
+                self.print_info(urls);
+            },
+            TuiRequest::OpenUrl(number) => {
+                let url = self.urls.iter_from_back().nth(number.wrapping_sub(1));
+
+                match url {
+                    Some(url) => self.open_url(&url),
+                    None => self.print_error(format!("No URL number {number} (see /urls)")),
+                }
+            },
+            TuiRequest::SetStatus(text) => {
+                self.status.set_text(text);
+            },
+            TuiRequest::SetStatusBar(enabled) => {
+                self.show_status = enabled;
+            },
+            TuiRequest::SetSpeedwalk(enabled) => {
+                self.speedwalk = enabled;
+            },
+            TuiRequest::SetKeyBinding(key, Some(description)) => {
+                self.bound_keys.insert(key, description);
+            },
+            TuiRequest::SetKeyBinding(key, None) => {
+                self.bound_keys.remove(&key);
+            },
+            TuiRequest::SetEmphasis(emphasis) => {
+                self.emphasis = emphasis;
+            },
+            TuiRequest::SetNumpad(numpad) => {
+                self.numpad = numpad;
+            },
+            TuiRequest::SetScrollLock(pane_id, locked) => {
+                if let Some(pane) = self.layout.pane(pane_id) {
+                    pane.set_locked(locked);
+                } else {
+                    self.print_warning(format!("No pane with id = {pane_id}"));
+                }
             },
         }
 
         Ok(())
     }
 
-    fn input(&mut self) -> &mut InputPane {
-        if let Some(input) = self.layout.input() {
-            input
+    /// Send user input (to the target of the input pane it was typed in, if any), unless it matches a guard,
+    /// in which case ask for confirmation first.
+    async fn submit(&mut self, data: String, target: Option<String>) -> Result<()> {
+        if self.guards.iter().any(|guard| guard.is_match(&data)) {
+            let color = self.theme.warning;
+            self.default_pane().push(format!("Send '{data}'? (y/n)").fg(color).bold().into());
+
+            self.pending_confirmation = Some((data, target));
         } else {
+            self.send_input(data, target).await
+                .context("Submit user input")?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a command clicked in the output as if it had been typed in the input for the server,
+    /// into whose history it goes.
+    async fn submit_clicked(&mut self, command: String) -> Result<()> {
+        self.game_input().remember(command.clone());
+
+        self.submit(command, None).await
+    }
+
+    /// Command for a numpad key, if walking with the numpad is enabled.
+    ///
+    /// Few terminals report which digits come from the numpad, so unless it is reported,
+    /// digits only walk when nothing has been typed (leaving them to be typed as part of commands).
+    fn numpad_command(&mut self, key: KeyEvent) -> Option<String> {
+        let KeyCode::Char(ch) = key.code else {
+            return None;
+        };
+
+        if key.modifiers != KeyModifiers::NONE {
+            return None;
+        }
+
+        if !key.state.contains(KeyEventState::KEYPAD) && !self.input().is_empty() {
+            return None;
+        }
+
+        self.numpad.as_ref()?.command(ch).map(str::to_string)
+    }
+
+    /// Send the input without echoing it or keeping it in the history.
+    async fn submit_secret(&mut self) -> Result<()> {
+        let data = self.input().get_and_clear();
+
+        self.tx.send(TuiEvent::SendSecret(data)).await
+            .context("Submit secret user input")
+    }
+
+    /// Handle a key while searching; returns whether it was consumed.
+    fn process_search_key(&mut self, key: KeyEvent) -> bool {
+        match (self.search, key.code) {
+            (Search::Typing, KeyCode::Enter) => {
+                let needle = self.input().get_and_clear();
+                self.input().set_prompt(None);
+
+                if needle.is_empty() {
+                    self.search = Search::Off;
+                } else {
+                    self.search = Search::Browsing;
+
+                    if self.active_pane().search(&needle).is_none() {
+                        self.print_info(format!("Not found: {needle}"));
+                    }
+                }
+
+                true
+            },
+            (Search::Typing, KeyCode::Esc) => {
+                self.search = Search::Off;
+                self.input().set_prompt(None);
+
+                true
+            },
+            (Search::Typing, _) => false,
+            (Search::Browsing, KeyCode::Char(ch @ ('n' | 'N'))) => {
+                if self.active_pane().search_next(ch == 'n').is_none() {
+                    self.print_info("No more matches".into());
+                }
+
+                true
+            },
+            /* Any other key ends the search, and Esc does nothing else */
+            (Search::Browsing, code) => {
+                self.search = Search::Off;
+                self.active_pane().clear_search();
+
+                code == KeyCode::Esc
+            },
+            (Search::Off, _) => false,
+        }
+    }
+
+    /// Insert pasted text into the input; text of several lines switches to multi-line mode,
+    /// so that it is only sent once submitted with Ctrl+Enter rather than line by line as pasted.
+    fn paste(&mut self, text: String) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let text = text.strip_suffix('\n').unwrap_or(&text).to_string();
+
+        if text.contains('\n') && !self.input().is_multiline() {
+            self.input().toggle_multiline();
+            self.print_info(format!("Pasted {} lines; press Ctrl+Enter to send them", text.lines().count()));
+        }
+
+        self.input().type_string(text);
+    }
+
+    /// Send user input, expanded into separate commands if it is a speedwalk (and those are enabled).
+    async fn send_input(&mut self, data: String, target: Option<String>) -> Result<()> {
+        /* Speedwalks only make sense as commands for the server */
+        let commands = match speedwalk::expand(&data) {
+            Some(commands) if self.speedwalk && target.is_none() => commands,
+            _ => vec![data],
+        };
+
+        for command in commands {
+            self.tx.send(TuiEvent::Send(command, target.clone())).await
+                .context("Send user input")?;
+        }
+
+        Ok(())
+    }
+
+    fn set_click_send(&mut self, enabled: bool) -> Result<()> {
+        if enabled && !self.capabilities.mouse && !self.mouse_warned {
+            self.print_warning("This terminal does not seem to report mouse clicks (run with --mouse true if it does)".into());
+            self.mouse_warned = true;
+        }
+
+        /* Otherwise the mouse is captured all along */
+        if !self.capabilities.mouse {
+            if enabled {
+                stdout().execute(EnableMouseCapture)
+                    .context("Enable mouse capture")?;
+            } else {
+                stdout().execute(DisableMouseCapture)
+                    .context("Disable mouse capture")?;
+            }
+        }
+
+        self.click_send = enabled;
+
+        let color = self.theme.info;
+        let state = if enabled { "on (Alt+c to turn off)" } else { "off" };
+        self.default_pane().push(format!("[INFO] Click-send mode {state}").fg(color).into());
+
+        Ok(())
+    }
+
+    fn resize_active_pane(&mut self, direction: Direction, delta: i32) -> Result<()> {
+        let area = self.terminal.size()
+            .context("Get terminal size")?;
+
+        if !self.layout.resize_pane(area, self.active_pane, direction, delta) {
+            self.print_warning("The active pane cannot be resized that way".into());
+        }
+
+        Ok(())
+    }
+
+    fn replace_layout(&mut self, mut layout: LayoutElement) {
+        let queued = self.game_input().queued();
+        let server_prompt = self.game_input().server_prompt();
+        let secret = self.game_input().is_secret();
+        let active_target = self.input().target();
+
+        /* Each input pane keeps the history of the one with the same target */
+        let mut histories: HashMap<_, _> = self.layout.inputs().into_iter()
+            .map(|input| (input.target(), input.history()))
+            .collect();
+
+        for input in layout.inputs() {
+            let target = input.target();
+            let history = histories.remove(&target).unwrap_or_default();
+
+            *input = InputPane::with_history(history, self.history_config.size);
+            input.set_target(target);
+            input.set_prompt_line(self.prompt_line);
+            input.set_history_config(self.history_config);
+        }
+
+        let old_panes = std::mem::replace(&mut self.layout, layout).into_panes();
+
+        /* Panes missing from the new layout are dropped along with their lines */
+        for (pane_id, old_pane) in old_panes {
+            if let Some(pane) = self.layout.pane(pane_id) {
+                pane.take_buffer(old_pane);
+            }
+        }
+
+        self.game_input().set_queued(queued);
+        self.game_input().set_server_prompt(server_prompt);
+        self.game_input().set_secret(secret);
+
+        let active_input = self.layout.inputs().iter()
+            .position(|input| input.target() == active_target)
+            .unwrap_or(0);
+        self.focus_input(active_input);
+
+        if self.layout.pane(self.active_pane).is_none() {
+            self.active_pane = 1;
+        }
+    }
+
+    /// Underline URLs found in a line, adding them to its links and to the recent ones.
+    fn link_urls(&mut self, line: Line<'static>, links: &mut Vec<(Range<usize>, Link)>) -> Line<'static> {
+        let text = line_text(&line);
+        let ranges = urls::find_urls(&text);
+
+        for range in &ranges {
+            let url = text[range.clone()].to_string();
+
+            self.urls.find_and_push_back(url.clone());
+            links.push((range.clone(), Link::Url(url)));
+        }
+
+        restyle_ranges(line, &ranges, Style::default().fg(self.theme.link).add_modifier(Modifier::UNDERLINED))
+    }
+
+    fn open_url(&mut self, url: &str) {
+        match urls::open_url(url) {
+            Ok(()) => self.print_info(format!("Opening {url}")),
+            Err(err) => self.print_error(format!("{:?}", err.context("Open URL"))),
+        }
+    }
+
+    fn print_error(&mut self, data: String) {
+        let color = self.theme.error;
+        self.print_prefixed(1, "[ERR]", color, data);
+    }
+
+    fn print_info(&mut self, data: String) {
+        let color = self.theme.info;
+        self.print_prefixed(1, "[INFO]", color, data);
+    }
+
+    fn print_warning(&mut self, data: String) {
+        let color = self.theme.warning;
+        self.print_prefixed(1, "[WARN]", color, data);
+    }
+
+    fn print_prefixed(&mut self, pane_id: usize, prefix: &str, color: Color, data: String) {
+        let pane = self.pane_or_default(pane_id);
+        for line in data.lines() {
+            pane.push(format!("{prefix} {line}").fg(color).into());
+        }
+    }
+
+    /// The input pane being typed into.
+    fn input(&mut self) -> &mut InputPane {
+        let active_input = self.active_input;
+        let mut inputs = self.layout.inputs();
+
+        if inputs.is_empty() {
+            panic!("No input!");
+        }
+
+        inputs.swap_remove(active_input.min(inputs.len() - 1))
+    }
+
+    /// The input pane for commands to the server (the first one without a target), which shows the server's prompt.
+    fn game_input(&mut self) -> &mut InputPane {
+        let mut inputs = self.layout.inputs();
+        let index = inputs.iter().position(|input| input.target().is_none()).unwrap_or(0);
+
+        if inputs.is_empty() {
             panic!("No input!");
         }
+
+        inputs.swap_remove(index)
+    }
+
+    /// Make an input pane (by index in layout order) the one typed into.
+    fn focus_input(&mut self, index: usize) {
+        for (i, input) in self.layout.inputs().into_iter().enumerate() {
+            input.set_focused(i == index);
+        }
+
+        self.active_input = index;
     }
 
     fn default_pane(&mut self) -> &mut ScrollPane {
@@ -156,8 +996,96 @@ impl<B: Backend> TuiWrapper<B> {
             .expect("There should be a pane with id = 1")
     }
 
+    /// The pane with a given id, or the default one if the layout has no such pane.
+    fn pane_or_default(&mut self, pane_id: usize) -> &mut ScrollPane {
+        if self.layout.pane(pane_id).is_some() {
+            self.layout.pane(pane_id)
+                .expect("The pane was just found")
+        } else {
+            self.default_pane()
+        }
+    }
+
+    /// Scroll the active pane up or down by `fraction` of its height.
+    fn scroll_active_pane(&mut self, fraction: f64, up: bool) {
+        let pane = self.active_pane();
+        let lines = pane.page_lines(fraction);
+
+        if up {
+            pane.scroll_up(lines);
+        } else {
+            pane.scroll_down(lines);
+        }
+    }
+
+    /// Make the next (or previous) scroll pane in layout order the active one, wrapping around.
+    fn cycle_active_pane(&mut self, forwards: bool) {
+        let ids = self.layout.pane_ids();
+
+        let Some(index) = ids.iter().position(|&id| id == self.active_pane) else {
+            return;
+        };
+
+        let index = if forwards {
+            (index + 1) % ids.len()
+        } else {
+            (index + ids.len() - 1) % ids.len()
+        };
+
+        self.active_pane = ids[index];
+    }
+
     fn active_pane(&mut self) -> &mut ScrollPane {
         self.layout.pane(self.active_pane)
             .expect("There should be an active pane")
     }
+}
+
+/// Draw the keys bound by scripts, with what they do, in a box over the middle of `area`.
+fn render_bindings(frame: &mut Frame<'_>, area: Rect, bound_keys: &HashMap<KeySpec, String>, theme: &Theme) {
+    let mut bindings: Vec<_> = bound_keys.iter()
+        .map(|(key, description)| (key.to_string(), description.clone()))
+        .collect();
+    bindings.sort();
+
+    let lines: Vec<Line> = if bindings.is_empty() {
+        vec!["No keys are bound; scripts bind them with bind(key, function).".fg(theme.text).into()]
+    } else {
+        let key_width = bindings.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+
+        bindings.into_iter()
+            .map(|(key, description)| Line::from(vec![
+                format!("{key:key_width$}  ").fg(theme.title_active),
+                description.fg(theme.text),
+            ]))
+            .collect()
+    };
+
+    let title = " Key bindings (any key to close) ";
+    let width = (lines.iter().map(Line::width).max().unwrap_or(0).max(title.len()) as u16 + 4).min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect::new(area.x + (area.width - width) / 2, area.y + (area.height - height) / 2, width, height);
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .block(Block::default()
+                .title(title.fg(theme.title_active))
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(theme.border))),
+        popup,
+    );
+}
+
+fn save_buffer(pane: &ScrollPane, path: &str) -> Result<()> {
+    let file = File::create(path)
+        .context(format!("Create buffer file {path}"))?;
+
+    let mut writer = BufWriter::new(file);
+
+    pane.dump_to_writer(&mut writer)
+        .context("Write buffer")?;
+
+    writer.flush()
+        .context("Flush buffer file")
 }
\ No newline at end of file